@@ -0,0 +1,961 @@
+//! ARM Debug Interface v5 (ADIv5): Debug Port and Access Port registers, and memory access
+//! through a MEM-AP.
+//!
+//! The [`DebugPort`] trait runs batches of register transfers in as few transfers over USB as
+//! it can, and is implemented over [SWD](crate::swd) by [`Swd`] and over a JTAG-DP by [`Jtag`].
+//! A [`Dap`] adds power-up and SELECT caching on top, and a [`MemAp`] reads and writes memory.
+//!
+//! ```no_run
+//! use mpsse::adiv5::{Dap, Error, MemAp};
+//! use mpsse::swd::{self, Swd};
+//! use mpsse::transport::Transport;
+//!
+//! // Read the Cortex-M CPUID register through AP 0.
+//! fn cpuid<T: Transport>(mut swd: Swd<T>) -> Result<u32, Error<swd::Error<T::Error>>> {
+//!     swd.setup().map_err(Error::Port)?;
+//!
+//!     let mut dap = Dap::new(swd);
+//!     dap.connect()?;
+//!
+//!     let mut memory = MemAp::new(dap, 0);
+//!     memory.read_word(0xE000_ED00)
+//! }
+//! ```
+use std::fmt;
+
+use crate::builder::Builder;
+use crate::jtag::{self, from_bits, push_clocks, to_bits, Jtag};
+use crate::swd::{self, decode_ack, decode_data, Ack, Swd};
+use crate::transport::Transport;
+
+pub use crate::swd::Port;
+
+/// DP register: the identification register, when read.
+pub const DPIDR: u8 = 0x0;
+/// DP register: the ABORT register, when written.
+pub const ABORT: u8 = 0x0;
+/// DP register: control and status.
+pub const CTRL_STAT: u8 = 0x4;
+/// DP register: AP and register bank selection.
+pub const SELECT: u8 = 0x8;
+/// DP register: the result of the last AP read.
+pub const RDBUFF: u8 = 0xC;
+
+/// MEM-AP register: control and status word.
+pub const CSW: u8 = 0x00;
+/// MEM-AP register: transfer address.
+pub const TAR: u8 = 0x04;
+/// MEM-AP register: data read/write.
+pub const DRW: u8 = 0x0C;
+/// AP register: identification.
+pub const IDR: u8 = 0xFC;
+
+const ORUNDETECT: u32 = 1 << 0;
+const STICKYORUN: u32 = 1 << 1;
+const STICKYCMP: u32 = 1 << 4;
+const STICKYERR: u32 = 1 << 5;
+const CDBGPWRUPREQ: u32 = 1 << 28;
+const CDBGPWRUPACK: u32 = 1 << 29;
+const CSYSPWRUPREQ: u32 = 1 << 30;
+const CSYSPWRUPACK: u32 = 1 << 31;
+
+/// ABORT bits that clear every sticky flag over SWD.
+const ABORT_CLEAR_STICKY: u32 = 0x1E;
+
+/// Number of times CTRL/STAT is read waiting for the power-up acknowledgements.
+const POWER_UP_POLLS: usize = 100;
+
+const CSW_SIZE: u32 = 0x07;
+const CSW_ADDR_INC: u32 = 0x30;
+const CSW_ADDR_INC_SINGLE: u32 = 0x10;
+
+/// TAR auto-increment is only guaranteed within an aligned block of this many bytes.
+pub const AUTO_INCREMENT_BLOCK: u32 = 0x400;
+
+const JTAG_ABORT: u64 = 0x8;
+const JTAG_DPACC: u64 = 0xA;
+const JTAG_APACC: u64 = 0xB;
+const JTAG_IR_LENGTH: usize = 4;
+const JTAG_DR_LENGTH: usize = 35;
+const JTAG_OK: u64 = 0b010;
+
+/// Clocks spent in Run-Test/Idle after each AP access over JTAG, to give the access time to
+/// finish before the next scan.
+pub const JTAG_IDLE_CYCLES: usize = 8;
+
+/// A DP or AP register read or write.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transfer {
+    pub port: Port,
+    /// The register address. Only bits 2 and 3 are sent; APs use SELECT for the rest.
+    pub address: u8,
+    /// The value to write, or `None` to read.
+    pub write: Option<u32>,
+}
+
+impl Transfer {
+    pub fn read(port: Port, address: u8) -> Self {
+        Transfer {
+            port,
+            address,
+            write: None,
+        }
+    }
+
+    pub fn write(port: Port, address: u8, value: u32) -> Self {
+        Transfer {
+            port,
+            address,
+            write: Some(value),
+        }
+    }
+}
+
+/// Error returned by the ADIv5 layer.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The SWD or JTAG driver failed.
+    Port(E),
+    /// The target kept answering WAIT.
+    Wait,
+    /// The target answered FAULT, or set a sticky error flag.
+    Fault,
+    /// The debug or system power domain did not power up.
+    PowerUp,
+    /// A memory access was not aligned to its size.
+    Alignment(u32),
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Port(err) => write!(f, "{}", err),
+            Error::Wait => write!(f, "target kept answering WAIT"),
+            Error::Fault => write!(f, "target reported a fault"),
+            Error::PowerUp => write!(f, "debug power-up was not acknowledged"),
+            Error::Alignment(address) => write!(f, "address {:#010x} is not aligned", address),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+/// A way of reaching a Debug Port.
+pub trait DebugPort {
+    type Error;
+
+    /// Run `transfers` in order, returning a value for each, which is the value read for reads
+    /// and 0 for writes.
+    ///
+    /// AP reads are posted, so implementations take care of fetching each read's result from
+    /// the next transfer or RDBUFF.
+    fn transfer(&mut self, transfers: &[Transfer]) -> Result<Vec<u32>, Error<Self::Error>>;
+
+    /// Clear the DP's sticky error flags.
+    fn clear_errors(&mut self) -> Result<(), Error<Self::Error>>;
+}
+
+/// A transaction to send over SWD, and the transfer its read data (if any) belongs to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct SwdOp {
+    transfer: Transfer,
+    value_for: Option<usize>,
+}
+
+/// Plan the SWD transactions for `transfers`. The result of an AP read comes back with the
+/// next AP read, or with a read of RDBUFF added after the last one.
+fn plan_swd(transfers: &[Transfer]) -> Vec<SwdOp> {
+    let rdbuff = |index| SwdOp {
+        transfer: Transfer::read(Port::Debug, RDBUFF),
+        value_for: Some(index),
+    };
+    let mut ops = Vec::new();
+    let mut pending = None;
+
+    for (index, transfer) in transfers.iter().enumerate() {
+        let ap_read = transfer.port == Port::Access && transfer.write.is_none();
+        if !ap_read {
+            ops.extend(pending.take().map(rdbuff));
+        }
+
+        let value_for = match (transfer.write, transfer.port) {
+            (Some(_), _) => None,
+            (None, Port::Debug) => Some(index),
+            (None, Port::Access) => pending.replace(index),
+        };
+        ops.push(SwdOp {
+            transfer: *transfer,
+            value_for,
+        });
+    }
+
+    ops.extend(pending.map(rdbuff));
+    ops
+}
+
+/// Convert an SWD error, pulling out the errors ADIv5 has its own variants for.
+fn from_swd<E>(err: swd::Error<E>) -> Error<swd::Error<E>> {
+    match err {
+        swd::Error::Wait => Error::Wait,
+        swd::Error::Fault => Error::Fault,
+        err => Error::Port(err),
+    }
+}
+
+/// Whether `op` writes CTRL/STAT, which may change overrun detection.
+fn writes_ctrl_stat(op: &SwdOp) -> bool {
+    op.transfer.port == Port::Debug
+        && op.transfer.address == CTRL_STAT
+        && op.transfer.write.is_some()
+}
+
+impl<T: Transport> Swd<T> {
+    /// Send `ops` back to back, which needs overrun detection enabled, storing each read
+    /// value. Returns how many were accepted before a WAIT.
+    fn send_batch(
+        &mut self,
+        ops: &[SwdOp],
+        values: &mut [u32],
+    ) -> Result<usize, Error<swd::Error<T::Error>>> {
+        let mut builder = Builder::new().swd();
+        for op in ops {
+            let transfer = op.transfer;
+            builder = builder.request(transfer.port, transfer.write.is_none(), transfer.address);
+            builder = match transfer.write {
+                Some(value) => builder.write_data(value),
+                None => builder.read_data(),
+            };
+        }
+        let response = self.execute(builder.idle(8).then()).map_err(from_swd)?;
+
+        let mut offset = 0;
+        for (index, op) in ops.iter().enumerate() {
+            let ack = decode_ack(response[offset]);
+            offset += 1;
+            let data = match op.transfer.write {
+                Some(_) => None,
+                None => {
+                    offset += 5;
+                    Some(decode_data(&response[offset - 5..offset]))
+                }
+            };
+
+            match ack {
+                Ok(Ack::Ok) => (),
+                Ok(Ack::Wait) => {
+                    self.clear_overrun().map_err(from_swd)?;
+                    return Ok(index);
+                }
+                Ok(Ack::Fault) => return Err(Error::Fault),
+                Err(bits) => return Err(Error::Port(swd::Error::Protocol(bits))),
+            }
+
+            match (data, op.value_for) {
+                (Some((_, false)), _) => return Err(Error::Port(swd::Error::Parity)),
+                (Some((value, true)), Some(index)) => values[index] = value,
+                _ => (),
+            }
+        }
+
+        Ok(ops.len())
+    }
+
+    /// Send one op, checking its ACK before the data phase.
+    fn send_one(
+        &mut self,
+        op: &SwdOp,
+        values: &mut [u32],
+    ) -> Result<(), Error<swd::Error<T::Error>>> {
+        let transfer = op.transfer;
+        match transfer.write {
+            Some(value) => self.write(transfer.port, transfer.address, value),
+            None => self.read(transfer.port, transfer.address).map(|value| {
+                if let Some(index) = op.value_for {
+                    values[index] = value;
+                }
+            }),
+        }
+        .map_err(from_swd)
+    }
+}
+
+/// Over SWD, transfers are sent as a single batch once overrun detection is enabled, which
+/// [`Dap::connect`] does. Until then, each transfer's ACK is checked before its data phase.
+impl<T: Transport> DebugPort for Swd<T> {
+    type Error = swd::Error<T::Error>;
+
+    fn transfer(&mut self, transfers: &[Transfer]) -> Result<Vec<u32>, Error<Self::Error>> {
+        let ops = plan_swd(transfers);
+        let mut values = vec![0; transfers.len()];
+        let mut start = 0;
+        let mut waits = 0;
+
+        while start < ops.len() {
+            // Writes to CTRL/STAT end a batch, as they may turn overrun detection on or off.
+            let end = ops[start..]
+                .iter()
+                .position(writes_ctrl_stat)
+                .map_or(ops.len(), |position| start + position + 1);
+
+            let sent = match self.overrun_detection() {
+                true => self.send_batch(&ops[start..end], &mut values)?,
+                false => {
+                    self.send_one(&ops[start], &mut values)?;
+                    1
+                }
+            };
+
+            if let Some(value) = ops[start..start + sent]
+                .iter()
+                .filter(|op| writes_ctrl_stat(op))
+                .find_map(|op| op.transfer.write)
+            {
+                self.set_overrun_detection(value & ORUNDETECT != 0);
+            }
+
+            waits = match sent {
+                0 => waits + 1,
+                _ => 0,
+            };
+            if waits > swd::WAIT_RETRIES {
+                return Err(Error::Wait);
+            }
+            start += sent;
+        }
+
+        Ok(values)
+    }
+
+    fn clear_errors(&mut self) -> Result<(), Error<Self::Error>> {
+        self.write(Port::Debug, ABORT, ABORT_CLEAR_STICKY)
+            .map_err(from_swd)
+    }
+}
+
+/// Over JTAG, every transfer is a scan of DPACC or APACC, and all of them go in one batch. The
+/// DP answers WAIT if an access is still running when the next scan arrives, which is reported
+/// as [`Error::Wait`]; raise [`JTAG_IDLE_CYCLES`] if that happens. Sticky errors are checked by
+/// reading CTRL/STAT at the end of each batch.
+impl<T: Transport> DebugPort for Jtag<T> {
+    type Error = jtag::Error<T::Error>;
+
+    fn transfer(&mut self, transfers: &[Transfer]) -> Result<Vec<u32>, Error<Self::Error>> {
+        let scans: Vec<Transfer> = transfers
+            .iter()
+            .copied()
+            .chain(vec![
+                Transfer::read(Port::Debug, CTRL_STAT),
+                Transfer::read(Port::Debug, RDBUFF),
+            ])
+            .collect();
+
+        let mut builder = self.builder();
+        let mut instruction = None;
+        let mut offsets = Vec::new();
+        for transfer in scans.iter() {
+            let ir = match (transfer.port, transfer.address, transfer.write) {
+                (Port::Debug, ABORT, Some(_)) => JTAG_ABORT,
+                (Port::Debug, ..) => JTAG_DPACC,
+                (Port::Access, ..) => JTAG_APACC,
+            };
+            if instruction != Some(ir) {
+                builder = builder.scan_ir(&to_bits(ir, JTAG_IR_LENGTH)).then();
+                instruction = Some(ir);
+            }
+
+            let request = transfer.write.is_none() as u64
+                | (transfer.address as u64 >> 2 & 0x3) << 1
+                | (transfer.write.unwrap_or(0) as u64) << 3;
+            offsets.push((ir, builder.expected_response_length()));
+            builder = builder
+                .scan_dr(&to_bits(request, JTAG_DR_LENGTH), true)
+                .then();
+            if transfer.port == Port::Access {
+                push_clocks(&mut builder.commands, JTAG_IDLE_CYCLES);
+            }
+        }
+
+        let response = self.execute(builder).map_err(Error::Port)?;
+        let captured: Vec<u64> = offsets
+            .iter()
+            .map(|(_, offset)| {
+                from_bits(&self.decode_scan_dr(&response[*offset..], JTAG_DR_LENGTH))
+            })
+            .collect();
+
+        // Each scan captures the ACK and result of the one before it. ABORT scans capture
+        // nothing useful.
+        let waited = offsets
+            .iter()
+            .zip(&captured)
+            .any(|((ir, _), captured)| *ir != JTAG_ABORT && captured & 0x7 != JTAG_OK);
+        if waited {
+            return Err(Error::Wait);
+        }
+
+        let ctrl_stat = captured[captured.len() - 1] >> 3;
+        if ctrl_stat as u32 & (STICKYERR | STICKYORUN) != 0 {
+            return Err(Error::Fault);
+        }
+
+        Ok(transfers
+            .iter()
+            .zip(&captured[1..])
+            .map(|(transfer, captured)| match transfer.write {
+                Some(_) => 0,
+                None => (captured >> 3) as u32,
+            })
+            .collect())
+    }
+
+    /// The sticky flags in CTRL/STAT are cleared by writing ones to them over JTAG.
+    fn clear_errors(&mut self) -> Result<(), Error<Self::Error>> {
+        let scans = [
+            Transfer::read(Port::Debug, CTRL_STAT),
+            Transfer::read(Port::Debug, RDBUFF),
+        ];
+        let mut builder = self.builder();
+        builder = builder.scan_ir(&to_bits(JTAG_DPACC, JTAG_IR_LENGTH)).then();
+        let mut offset = 0;
+        for transfer in scans.iter() {
+            let request = 1 | (transfer.address as u64 >> 2 & 0x3) << 1;
+            offset = builder.expected_response_length();
+            builder = builder
+                .scan_dr(&to_bits(request, JTAG_DR_LENGTH), true)
+                .then();
+        }
+
+        let response = self.execute(builder).map_err(Error::Port)?;
+        let ctrl_stat = from_bits(&self.decode_scan_dr(&response[offset..], JTAG_DR_LENGTH)) >> 3;
+        let clear = ctrl_stat as u32 | STICKYERR | STICKYCMP | STICKYORUN;
+
+        let request = (CTRL_STAT as u64 >> 2) << 1 | (clear as u64) << 3;
+        let builder = self
+            .builder()
+            .scan_dr(&to_bits(request, JTAG_DR_LENGTH), false)
+            .then();
+        self.execute(builder).map_err(Error::Port)?;
+        Ok(())
+    }
+}
+
+/// A Debug Access Port, with the AP and register bank in SELECT cached between transfers.
+#[derive(Debug)]
+pub struct Dap<P> {
+    port: P,
+    select: Option<u32>,
+}
+
+impl<P: DebugPort> Dap<P> {
+    /// Create a DAP on the given SWD or JTAG driver.
+    pub fn new(port: P) -> Self {
+        Dap { port, select: None }
+    }
+
+    /// Get the underlying SWD or JTAG driver.
+    pub fn port(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Get back the underlying SWD or JTAG driver.
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// Read DPIDR, clear any sticky errors, and power up the debug and system domains with
+    /// overrun detection enabled.
+    ///
+    /// Returns DPIDR.
+    pub fn connect(&mut self) -> Result<u32, Error<P::Error>> {
+        let dpidr = self.read_dp(DPIDR)?;
+        self.port.clear_errors()?;
+        self.write_dp(CTRL_STAT, CDBGPWRUPREQ | CSYSPWRUPREQ | ORUNDETECT)?;
+
+        for _ in 0..POWER_UP_POLLS {
+            let ctrl_stat = self.read_dp(CTRL_STAT)?;
+            if ctrl_stat & (CDBGPWRUPACK | CSYSPWRUPACK) == CDBGPWRUPACK | CSYSPWRUPACK {
+                return Ok(dpidr);
+            }
+        }
+
+        Err(Error::PowerUp)
+    }
+
+    /// Run transfers, with any AP transfers going to AP `ap`. SELECT is written first if it
+    /// isn't already set to the right AP and register bank.
+    pub fn transfer(
+        &mut self,
+        ap: u8,
+        transfers: &[Transfer],
+    ) -> Result<Vec<u32>, Error<P::Error>> {
+        let mut select = self.select;
+        let mut batch = Vec::with_capacity(transfers.len() + 1);
+        let mut indices = Vec::with_capacity(transfers.len());
+
+        for transfer in transfers {
+            match (transfer.port, transfer.address, transfer.write) {
+                (Port::Access, address, _) => {
+                    let value = (ap as u32) << 24 | (address & 0xF0) as u32;
+                    if select != Some(value) {
+                        batch.push(Transfer::write(Port::Debug, SELECT, value));
+                        select = Some(value);
+                    }
+                }
+                (Port::Debug, SELECT, Some(value)) => select = Some(value),
+                _ => (),
+            }
+            indices.push(batch.len());
+            batch.push(*transfer);
+        }
+
+        // If anything goes wrong, the SELECT write may or may not have happened.
+        self.select = None;
+        let values = self.port.transfer(&batch)?;
+        self.select = select;
+
+        Ok(indices.iter().map(|index| values[*index]).collect())
+    }
+
+    /// Read a DP register.
+    pub fn read_dp(&mut self, address: u8) -> Result<u32, Error<P::Error>> {
+        Ok(self.transfer(0, &[Transfer::read(Port::Debug, address)])?[0])
+    }
+
+    /// Write a DP register.
+    pub fn write_dp(&mut self, address: u8, value: u32) -> Result<(), Error<P::Error>> {
+        self.transfer(0, &[Transfer::write(Port::Debug, address, value)])?;
+        Ok(())
+    }
+
+    /// Read a register of AP `ap`.
+    pub fn read_ap(&mut self, ap: u8, address: u8) -> Result<u32, Error<P::Error>> {
+        Ok(self.transfer(ap, &[Transfer::read(Port::Access, address)])?[0])
+    }
+
+    /// Write a register of AP `ap`.
+    pub fn write_ap(&mut self, ap: u8, address: u8, value: u32) -> Result<(), Error<P::Error>> {
+        self.transfer(ap, &[Transfer::write(Port::Access, address, value)])?;
+        Ok(())
+    }
+}
+
+/// The size of each memory access.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Size {
+    Byte,
+    HalfWord,
+    Word,
+}
+
+impl Size {
+    /// The access size in bytes.
+    pub fn bytes(self) -> u32 {
+        match self {
+            Size::Byte => 1,
+            Size::HalfWord => 2,
+            Size::Word => 4,
+        }
+    }
+
+    /// The CSW Size field value.
+    fn csw(self) -> u32 {
+        match self {
+            Size::Byte => 0,
+            Size::HalfWord => 1,
+            Size::Word => 2,
+        }
+    }
+}
+
+/// Read and write memory through a MEM-AP.
+///
+/// Accesses use TAR auto-increment, with TAR written again at each 1 KiB boundary, and each
+/// call runs as a single batch of transfers.
+#[derive(Debug)]
+pub struct MemAp<P> {
+    dap: Dap<P>,
+    ap: u8,
+    csw_base: Option<u32>,
+    csw: Option<u32>,
+}
+
+impl<P: DebugPort> MemAp<P> {
+    /// Access memory through AP `ap` of `dap`.
+    pub fn new(dap: Dap<P>, ap: u8) -> Self {
+        MemAp {
+            dap,
+            ap,
+            csw_base: None,
+            csw: None,
+        }
+    }
+
+    /// Get the underlying DAP.
+    pub fn dap(&mut self) -> &mut Dap<P> {
+        &mut self.dap
+    }
+
+    /// Get back the underlying DAP.
+    pub fn into_inner(self) -> Dap<P> {
+        self.dap
+    }
+
+    /// Plan the transfers for `count` accesses of `size` from `address`, calling `access` for
+    /// each DRW transfer.
+    fn plan(
+        &mut self,
+        address: u32,
+        size: Size,
+        count: usize,
+        mut access: impl FnMut(u32) -> Transfer,
+    ) -> Result<Vec<Transfer>, Error<P::Error>> {
+        if !address.is_multiple_of(size.bytes()) {
+            return Err(Error::Alignment(address));
+        }
+
+        // CSW has implementation defined bits, which are kept as the AP reset them.
+        let base = match self.csw_base {
+            Some(base) => base,
+            None => {
+                let csw = self.dap.read_ap(self.ap, CSW)?;
+                *self.csw_base.insert(csw & !(CSW_SIZE | CSW_ADDR_INC))
+            }
+        };
+        let csw = base | CSW_ADDR_INC_SINGLE | size.csw();
+
+        let mut transfers = Vec::new();
+        if self.csw != Some(csw) {
+            transfers.push(Transfer::write(Port::Access, CSW, csw));
+        }
+
+        for index in 0..count as u32 {
+            let element = address.wrapping_add(index * size.bytes());
+            if index == 0 || element.is_multiple_of(AUTO_INCREMENT_BLOCK) {
+                transfers.push(Transfer::write(Port::Access, TAR, element));
+            }
+            transfers.push(access(element));
+        }
+
+        self.csw = Some(csw);
+        Ok(transfers)
+    }
+
+    /// Run planned transfers, forgetting the CSW if they fail.
+    fn run(&mut self, transfers: &[Transfer]) -> Result<Vec<u32>, Error<P::Error>> {
+        let values = self.dap.transfer(self.ap, transfers);
+        if values.is_err() {
+            self.csw = None;
+        }
+
+        values
+    }
+
+    /// Read `count` values of `size` starting at `address`.
+    pub fn read(
+        &mut self,
+        address: u32,
+        size: Size,
+        count: usize,
+    ) -> Result<Vec<u32>, Error<P::Error>> {
+        let transfers = self.plan(address, size, count, |_| Transfer::read(Port::Access, DRW))?;
+        let values = self.run(&transfers)?;
+
+        // Narrow accesses come back in the byte lanes of their addresses.
+        let mask = (1u64 << (size.bytes() * 8)) - 1;
+        Ok(transfers
+            .iter()
+            .zip(values)
+            .filter(|(transfer, _)| transfer.address == DRW)
+            .enumerate()
+            .map(|(index, (_, value))| {
+                let element = address.wrapping_add(index as u32 * size.bytes());
+                (value >> (element % 4 * 8)) & mask as u32
+            })
+            .collect())
+    }
+
+    /// Write `values` of `size` starting at `address`.
+    pub fn write(
+        &mut self,
+        address: u32,
+        size: Size,
+        values: &[u32],
+    ) -> Result<(), Error<P::Error>> {
+        let mut values = values.iter();
+        let transfers = self.plan(address, size, values.len(), |element| {
+            let value = values.next().copied().unwrap_or_default();
+            Transfer::write(Port::Access, DRW, value << (element % 4 * 8))
+        })?;
+
+        self.run(&transfers)?;
+        Ok(())
+    }
+
+    /// Read a 32-bit word.
+    pub fn read_word(&mut self, address: u32) -> Result<u32, Error<P::Error>> {
+        Ok(self.read(address, Size::Word, 1)?[0])
+    }
+
+    /// Write a 32-bit word.
+    pub fn write_word(&mut self, address: u32, value: u32) -> Result<(), Error<P::Error>> {
+        self.write(address, Size::Word, &[value])
+    }
+
+    /// Read 32-bit words.
+    pub fn read_words(&mut self, address: u32, count: usize) -> Result<Vec<u32>, Error<P::Error>> {
+        self.read(address, Size::Word, count)
+    }
+
+    /// Write 32-bit words.
+    pub fn write_words(&mut self, address: u32, words: &[u32]) -> Result<(), Error<P::Error>> {
+        self.write(address, Size::Word, words)
+    }
+
+    /// Read 16-bit halfwords.
+    pub fn read_halfwords(
+        &mut self,
+        address: u32,
+        count: usize,
+    ) -> Result<Vec<u16>, Error<P::Error>> {
+        let values = self.read(address, Size::HalfWord, count)?;
+        Ok(values.into_iter().map(|value| value as u16).collect())
+    }
+
+    /// Write 16-bit halfwords.
+    pub fn write_halfwords(
+        &mut self,
+        address: u32,
+        halfwords: &[u16],
+    ) -> Result<(), Error<P::Error>> {
+        let values: Vec<u32> = halfwords.iter().map(|value| *value as u32).collect();
+        self.write(address, Size::HalfWord, &values)
+    }
+
+    /// Read bytes.
+    pub fn read_bytes(&mut self, address: u32, count: usize) -> Result<Vec<u8>, Error<P::Error>> {
+        let values = self.read(address, Size::Byte, count)?;
+        Ok(values.into_iter().map(|value| value as u8).collect())
+    }
+
+    /// Write bytes.
+    pub fn write_bytes(&mut self, address: u32, bytes: &[u8]) -> Result<(), Error<P::Error>> {
+        let values: Vec<u32> = bytes.iter().map(|value| *value as u32).collect();
+        self.write(address, Size::Byte, &values)
+    }
+}
+
+#[cfg(test)]
+mod adiv5_tests {
+    use super::*;
+    use crate::jtag::Chain;
+    use crate::transport::mock::MockTransport;
+
+    /// A DP with a single MEM-AP in front of 4 KiB of memory, which wraps TAR at 1 KiB
+    /// boundaries like the smallest real auto-increment.
+    struct FakePort {
+        memory: Vec<u8>,
+        select: u32,
+        csw: u32,
+        tar: u32,
+        batches: usize,
+    }
+
+    impl FakePort {
+        fn new() -> Self {
+            FakePort {
+                memory: (0..4096).map(|i| i as u8).collect(),
+                select: 0,
+                csw: 0x2300_0040,
+                tar: 0,
+                batches: 0,
+            }
+        }
+
+        fn size(&self) -> u32 {
+            1 << (self.csw & CSW_SIZE)
+        }
+
+        fn increment(&mut self) {
+            let tar = self.tar.wrapping_add(self.size()) % AUTO_INCREMENT_BLOCK;
+            self.tar = self.tar & !(AUTO_INCREMENT_BLOCK - 1) | tar;
+        }
+    }
+
+    impl DebugPort for FakePort {
+        type Error = ();
+
+        fn transfer(&mut self, transfers: &[Transfer]) -> Result<Vec<u32>, Error<()>> {
+            self.batches += 1;
+            let mut values = Vec::new();
+            for transfer in transfers {
+                let word = (self.tar & !3) as usize;
+                let value = match (transfer.port, transfer.address, transfer.write) {
+                    (Port::Debug, SELECT, Some(value)) => {
+                        self.select = value;
+                        0
+                    }
+                    (Port::Access, address, _) if self.select & 0xF0 != address as u32 & 0xF0 => {
+                        panic!("AP register {:#x} accessed in the wrong bank", address)
+                    }
+                    (Port::Access, CSW, Some(value)) => {
+                        self.csw = value;
+                        0
+                    }
+                    (Port::Access, CSW, None) => self.csw,
+                    (Port::Access, TAR, Some(value)) => {
+                        self.tar = value;
+                        0
+                    }
+                    (Port::Access, DRW, None) => {
+                        let bytes = &self.memory[word..word + 4];
+                        let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                        self.increment();
+                        value
+                    }
+                    (Port::Access, DRW, Some(value)) => {
+                        let lane = self.tar as usize % 4;
+                        let bytes = value.to_le_bytes();
+                        let lanes = lane..lane + self.size() as usize;
+                        self.memory[word..word + 4][lanes.clone()].copy_from_slice(&bytes[lanes]);
+                        self.increment();
+                        0
+                    }
+                    other => panic!("unexpected transfer {:?}", other),
+                };
+                values.push(value);
+            }
+
+            Ok(values)
+        }
+
+        fn clear_errors(&mut self) -> Result<(), Error<()>> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reads_across_auto_increment_boundary() {
+        let mut memory = MemAp::new(Dap::new(FakePort::new()), 0);
+
+        let words = memory.read_words(0x3F8, 4).unwrap();
+        assert_eq!(
+            words,
+            vec![0xFBFA_F9F8, 0xFFFE_FDFC, 0x0302_0100, 0x0706_0504]
+        );
+
+        let bytes = memory.read_bytes(0x3FE, 3).unwrap();
+        assert_eq!(bytes, vec![0xFE, 0xFF, 0x00]);
+
+        // One batch reads CSW, then each call is a single batch.
+        assert_eq!(memory.dap().port().batches, 3);
+    }
+
+    #[test]
+    fn narrow_writes_use_byte_lanes() {
+        let mut memory = MemAp::new(Dap::new(FakePort::new()), 0);
+
+        memory.write_halfwords(0x102, &[0xBEEF, 0xDEAD]).unwrap();
+        memory.write_bytes(0x101, &[0x55]).unwrap();
+
+        assert_eq!(memory.read_word(0x100).unwrap(), 0xBEEF_5500);
+        assert_eq!(memory.read_halfwords(0x104, 1).unwrap(), vec![0xDEAD]);
+        match memory.read_word(0x102) {
+            Err(Error::Alignment(0x102)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn swd_posted_reads() {
+        let ops = plan_swd(&[
+            Transfer::read(Port::Access, DRW),
+            Transfer::read(Port::Access, DRW),
+            Transfer::write(Port::Access, TAR, 0),
+            Transfer::read(Port::Debug, CTRL_STAT),
+        ]);
+        let value_for: Vec<_> = ops.iter().map(|op| op.value_for).collect();
+
+        assert_eq!(value_for, vec![None, Some(0), Some(1), None, Some(3)]);
+        assert_eq!(ops[2].transfer, Transfer::read(Port::Debug, RDBUFF));
+    }
+
+    #[test]
+    fn swd_batch_retries_after_wait() {
+        let ok_read = |value: u32| {
+            let mut response = vec![0x20];
+            response.extend_from_slice(&value.to_le_bytes());
+            response.push(if swd::parity(value) { 0x80 } else { 0x00 });
+            response
+        };
+        let mut responses = Vec::new();
+        // First batch: the first AP read is accepted, the second answers WAIT, and the RDBUFF
+        // read faults on the sticky overrun flag.
+        responses.extend(ok_read(0));
+        responses.extend([0x40, 0, 0, 0, 0, 0]);
+        responses.extend([0x80, 0, 0, 0, 0, 0]);
+        // Clearing the overrun flag.
+        responses.push(0x20);
+        // Second batch: the second AP read and RDBUFF.
+        responses.extend(ok_read(0x1234_5678));
+        responses.extend(ok_read(0x0000_0001));
+
+        let mut swd = Swd::new(MockTransport::with_responses(&responses));
+        swd.set_overrun_detection(true);
+        let values = swd
+            .transfer(&[
+                Transfer::read(Port::Access, DRW),
+                Transfer::read(Port::Access, DRW),
+            ])
+            .unwrap();
+
+        assert_eq!(values, vec![0x1234_5678, 0x0000_0001]);
+        assert!(swd.transport().responses.is_empty());
+    }
+
+    #[test]
+    fn jtag_reads_come_from_next_scan() {
+        let capture = |value: u64| {
+            let mut response = (value as u32).to_le_bytes().to_vec();
+            response.push(((value >> 32 & 0x3) << 6) as u8);
+            response.push(((value >> 34 & 0x1) << 7) as u8);
+            response
+        };
+        let mut responses = Vec::new();
+        // DPIDR read, CTRL/STAT read, then RDBUFF read.
+        responses.extend(capture(JTAG_OK));
+        responses.extend(capture(0x2BA0_1477 << 3 | JTAG_OK));
+        responses.extend(capture((CDBGPWRUPACK as u64) << 3 | JTAG_OK));
+
+        let mut jtag = Jtag::new(MockTransport::with_responses(&responses));
+        let values = jtag
+            .transfer(&[Transfer::read(Port::Debug, DPIDR)])
+            .unwrap();
+
+        assert_eq!(values, vec![0x2BA0_1477]);
+    }
+
+    #[test]
+    fn jtag_scans_are_unpadded_for_selected_device() {
+        // The device nearest TDO comes out first, followed by the other device's BYPASS bit,
+        // so each 36 bit scan captures 4 bytes, then 3 bits, then the last bit from TMS.
+        let capture = |value: u64| {
+            let mut response = (value as u32).to_le_bytes().to_vec();
+            response.push(((value >> 32 & 0x7) << 5) as u8);
+            response.push(0x00);
+            response
+        };
+        let mut responses = Vec::new();
+        responses.extend(capture(JTAG_OK));
+        responses.extend(capture(0x2BA0_1477 << 3 | JTAG_OK));
+        responses.extend(capture((CDBGPWRUPACK as u64) << 3 | JTAG_OK));
+
+        let mut jtag = Jtag::new(MockTransport::with_responses(&responses));
+        jtag.select_device(Chain::new(vec![4, 4]), 0).unwrap();
+        let values = jtag
+            .transfer(&[Transfer::read(Port::Debug, DPIDR)])
+            .unwrap();
+
+        assert_eq!(values, vec![0x2BA0_1477]);
+        assert!(jtag.transport().responses.is_empty());
+    }
+}
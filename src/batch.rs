@@ -0,0 +1,278 @@
+//! Splitting long command lists to fit the chip's buffers.
+//!
+//! The MPSSE reads commands from a receive buffer and queues its response in a transmit buffer,
+//! both of which are small. A command list that is too long, or that reads back more than the
+//! transmit buffer holds before the host collects it, can stall. [`batch`] splits a list into
+//! groups that each fit within [`Limits`] and end with a Send Immediate, and [`execute`] runs
+//! the groups back to back.
+//!
+//! ```
+//! use mpsse::batch::{batch, Limits};
+//! use mpsse::Builder;
+//!
+//! let commands = Builder::new().read_data(300).then().into_command_list();
+//! let limits = Limits {
+//!     command_bytes: 64,
+//!     response_bytes: 128,
+//! };
+//! let groups: Vec<Vec<u8>> = batch(commands, limits).into_iter().map(Vec::from).collect();
+//!
+//! assert_eq!(
+//!     groups,
+//!     vec![
+//!         vec![0x20, 0x7F, 0x00, 0x87],
+//!         vec![0x20, 0x7F, 0x00, 0x87],
+//!         vec![0x20, 0x2B, 0x00, 0x87],
+//!     ]
+//! );
+//! ```
+use std::mem;
+use std::time::Duration;
+
+use crate::command::{Command, CommandList};
+use crate::init::Chip;
+use crate::transport::{self, Transport};
+
+/// Largest number of bytes a single data shifting command can write.
+const MAX_WRITE_BYTES: usize = 0x10000;
+
+/// Largest number of bytes a single data shifting command can read.
+const MAX_READ_BYTES: usize = 0xFFFF;
+
+/// The most data a group may hold.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Limits {
+    /// Command bytes sent to the chip, including the final Send Immediate.
+    pub command_bytes: usize,
+    /// Response bytes sent back by the chip.
+    pub response_bytes: usize,
+}
+
+impl Limits {
+    /// The buffer sizes of `chip`.
+    pub fn for_chip(chip: Chip) -> Self {
+        let (command_bytes, response_bytes) = match chip {
+            Chip::Ft2232d => (384, 128),
+            Chip::Ft232h => (1024, 1024),
+            Chip::Ft4232h => (2048, 2048),
+            Chip::Ft2232h => (4096, 4096),
+        };
+
+        Limits {
+            command_bytes,
+            response_bytes,
+        }
+    }
+}
+
+/// Split `command` so that the first part writes and reads at most `length` bytes.
+///
+/// Returns the command unchanged if it doesn't shift bytes, or is no longer than `length`.
+fn split(command: Command, length: usize) -> (Command, Option<Command>) {
+    match command {
+        Command::WriteDataShiftBytes { options, mut bytes } if bytes.len() > length => {
+            let rest = bytes.split_off(length);
+            (
+                Command::WriteDataShiftBytes { options, bytes },
+                Some(Command::WriteDataShiftBytes {
+                    options,
+                    bytes: rest,
+                }),
+            )
+        }
+        Command::ReadWriteDataShiftBytes { options, mut bytes } if bytes.len() > length => {
+            let rest = bytes.split_off(length);
+            (
+                Command::ReadWriteDataShiftBytes { options, bytes },
+                Some(Command::ReadWriteDataShiftBytes {
+                    options,
+                    bytes: rest,
+                }),
+            )
+        }
+        Command::ReadDataShiftBytes {
+            options,
+            length: total,
+        } if total as usize > length => (
+            Command::ReadDataShiftBytes {
+                options,
+                length: length as u16,
+            },
+            Some(Command::ReadDataShiftBytes {
+                options,
+                length: total - length as u16,
+            }),
+        ),
+        command => (command, None),
+    }
+}
+
+/// The most bytes of `command`'s data that fit in the space left in a group, or 0 if the
+/// command can't be split.
+fn fitting_length(command: &Command, command_space: usize, response_space: usize) -> usize {
+    let header = 3;
+    match command {
+        Command::WriteDataShiftBytes { .. } => command_space.saturating_sub(header),
+        Command::ReadWriteDataShiftBytes { .. } => {
+            command_space.saturating_sub(header).min(response_space)
+        }
+        Command::ReadDataShiftBytes { .. } if command_space >= header => response_space,
+        _ => 0,
+    }
+}
+
+#[derive(Default)]
+struct Group {
+    commands: Vec<Command>,
+    command_bytes: usize,
+    response_bytes: usize,
+}
+
+impl Group {
+    fn push(&mut self, command: Command) {
+        self.command_bytes += command.encoded_length();
+        self.response_bytes += command.expected_response_length();
+        self.commands.push(command);
+    }
+
+    fn finish(&mut self) -> CommandList {
+        if !matches!(self.commands.last(), Some(Command::SendImmediate)) {
+            self.commands.push(Command::SendImmediate);
+        }
+
+        CommandList(mem::take(self).commands)
+    }
+}
+
+/// Split `list` into groups that each fit within `limits` and end with a Send Immediate.
+///
+/// Data shifting commands that move whole bytes are split across groups where needed. Any
+/// other command that is too big for a group on its own is put in a group by itself.
+pub fn batch(list: CommandList, limits: Limits) -> Vec<CommandList> {
+    // Leave room for the Send Immediate ending each group.
+    let command_limit = limits.command_bytes.saturating_sub(1);
+    let mut groups = Vec::new();
+    let mut group = Group::default();
+
+    for command in list.0 {
+        let mut pending = Some(command);
+        while let Some(command) = pending.take() {
+            let command_space = command_limit.saturating_sub(group.command_bytes);
+            let response_space = limits.response_bytes.saturating_sub(group.response_bytes);
+
+            if command.encoded_length() <= command_space
+                && command.expected_response_length() <= response_space
+            {
+                group.push(command);
+                continue;
+            }
+
+            let length = fitting_length(&command, command_space, response_space)
+                .min(MAX_WRITE_BYTES)
+                .min(MAX_READ_BYTES);
+            if length > 0 {
+                let (head, rest) = split(command, length);
+                group.push(head);
+                pending = rest;
+            } else if group.commands.is_empty() {
+                group.push(command);
+            } else {
+                pending = Some(command);
+            }
+            groups.push(group.finish());
+        }
+    }
+
+    if !group.commands.is_empty() {
+        groups.push(group.finish());
+    }
+
+    groups
+}
+
+/// Run `list` over `transport` in groups that fit within `limits`, returning the whole
+/// response.
+///
+/// * `timeout` - How long to wait for each chunk of each group's response.
+pub fn execute<T>(
+    transport: &mut T,
+    list: CommandList,
+    limits: Limits,
+    timeout: Duration,
+) -> Result<Vec<u8>, transport::Error<T::Error>>
+where
+    T: Transport + ?Sized,
+{
+    let mut response = Vec::with_capacity(list.expected_response_length());
+    for group in batch(list, limits) {
+        response.extend(transport::execute(transport, group, timeout)?);
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod batch_tests {
+    use super::*;
+    use crate::builder::Builder;
+    use crate::command::PinRange;
+    use crate::transport::mock::MockTransport;
+
+    const LIMITS: Limits = Limits {
+        command_bytes: 16,
+        response_bytes: 4,
+    };
+
+    #[test]
+    fn groups_fit_limits() {
+        let list = Builder::new()
+            .write_data(vec![0x11; 20])
+            .then()
+            .read_pins(PinRange::Low)
+            .then()
+            .read_pins(PinRange::High)
+            .then()
+            .read_data(5)
+            .then()
+            .into_command_list();
+        let groups = batch(list, LIMITS);
+
+        for group in groups.iter() {
+            let bytes = group.0.iter().map(Command::encoded_length).sum::<usize>();
+            assert!(bytes <= LIMITS.command_bytes);
+            assert!(group.expected_response_length() <= LIMITS.response_bytes);
+            assert!(matches!(group.0.last(), Some(Command::SendImmediate)));
+        }
+
+        let bytes: Vec<Vec<u8>> = groups.into_iter().map(Vec::from).collect();
+        assert_eq!(
+            bytes,
+            vec![
+                [vec![0x10, 0x0B, 0x00], vec![0x11; 12], vec![0x87]].concat(),
+                [
+                    vec![0x10, 0x07, 0x00],
+                    vec![0x11; 8],
+                    vec![0x81, 0x83],
+                    vec![0x87]
+                ]
+                .concat(),
+                vec![0x20, 0x03, 0x00, 0x87],
+                vec![0x20, 0x00, 0x00, 0x87],
+            ]
+        );
+    }
+
+    #[test]
+    fn execute_joins_responses() {
+        let mut transport = MockTransport::with_responses(&[1, 2, 3, 4, 5, 6]);
+        let list = Builder::new().read_data(6).then().into_command_list();
+
+        let response = execute(&mut transport, list, LIMITS, Duration::from_millis(10)).unwrap();
+
+        assert_eq!(response, vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(
+            transport.written,
+            vec![0x20, 0x03, 0x00, 0x87, 0x20, 0x01, 0x00, 0x87]
+        );
+    }
+}
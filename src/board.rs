@@ -0,0 +1,400 @@
+//! Pin maps for common FTDI-based adapters.
+//!
+//! Adapters wire the ADBUS and ACBUS pins differently, especially the reset lines, LEDs and the
+//! output enables of any level-shifting buffers. A [`Profile`] names the pin used for each
+//! [`Role`], along with the pin state to start from. The built-in profiles use the same pins as
+//! OpenOCD's layouts for those adapters, and start with every reset line released.
+//!
+//! With the `profiles` feature, custom profiles can be loaded from TOML with
+//! [`Profile::from_toml`].
+//!
+//! ```
+//! use mpsse::board::{Profile, Role};
+//! use mpsse::Builder;
+//!
+//! let profile = Profile::find("tigard").unwrap();
+//!
+//! // Set the adapter's initial pin state, then pulse nSRST.
+//! let commands = profile.init(Builder::new());
+//! let commands = profile.set(commands, Role::Srst, true).then();
+//! let commands = profile.set(commands, Role::Srst, false).build();
+//!
+//! assert_eq!(
+//!     commands,
+//!     vec![0x80, 0x38, 0x3B, 0x82, 0x00, 0x00, 0x80, 0x18, 0x3B, 0x80, 0x38, 0x3B]
+//! );
+//! ```
+use crate::builder::{Builder, PinState, SetPinsBuilder};
+use crate::command::{PinRange, PinValue};
+
+/// What a pin is used for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "profiles",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Role {
+    Tck,
+    Tdi,
+    Tdo,
+    Tms,
+    /// System reset, nSRST.
+    Srst,
+    /// TAP reset, nTRST.
+    Trst,
+    Led,
+    /// Enables the buffers driving the JTAG signals.
+    OutputEnable,
+    /// Enables the buffer driving nSRST.
+    SrstOutputEnable,
+    /// Enables the buffer driving nTRST.
+    TrstOutputEnable,
+}
+
+/// A pin of the MPSSE.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Pin {
+    pub range: PinRange,
+    /// The bit within the range, from 0 to 7.
+    pub bit: u8,
+    /// Whether the role is asserted by driving the pin low.
+    pub active_low: bool,
+}
+
+impl Pin {
+    const fn low(bit: u8) -> Self {
+        Pin {
+            range: PinRange::Low,
+            bit,
+            active_low: false,
+        }
+    }
+
+    const fn high(bit: u8) -> Self {
+        Pin {
+            range: PinRange::High,
+            bit,
+            active_low: false,
+        }
+    }
+
+    const fn inverted(self) -> Self {
+        Pin {
+            active_low: true,
+            ..self
+        }
+    }
+
+    /// The pin number, as for [`Builder::set_pin`].
+    pub fn number(&self) -> u8 {
+        match self.range {
+            PinRange::Low => self.bit,
+            PinRange::High => self.bit + 8,
+        }
+    }
+}
+
+/// The pin map of an adapter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    /// The pin for each role the adapter wires up, sorted by role.
+    pub pins: Vec<(Role, Pin)>,
+    /// The state of the low pins to start from.
+    pub low: PinState,
+    /// The state of the high pins to start from.
+    pub high: PinState,
+}
+
+/// The JTAG pins every MPSSE has: TCK, TDI, TDO and TMS on ADBUS0 to ADBUS3.
+const JTAG_PINS: [(Role, Pin); 4] = [
+    (Role::Tck, Pin::low(0)),
+    (Role::Tdi, Pin::low(1)),
+    (Role::Tdo, Pin::low(2)),
+    (Role::Tms, Pin::low(3)),
+];
+
+fn builtin(name: &str, extra: &[(Role, Pin)], low: (u8, u8), high: (u8, u8)) -> Profile {
+    let mut pins: Vec<(Role, Pin)> = JTAG_PINS.iter().chain(extra).copied().collect();
+    pins.sort_by_key(|(role, _)| *role);
+
+    Profile {
+        name: name.to_string(),
+        pins,
+        low: PinState {
+            value: low.0,
+            direction: low.1,
+        },
+        high: PinState {
+            value: high.0,
+            direction: high.1,
+        },
+    }
+}
+
+impl Profile {
+    /// The built-in profiles.
+    pub fn builtins() -> Vec<Profile> {
+        vec![
+            builtin("ft232h", &[], (0x08, 0x0B), (0x00, 0x00)),
+            builtin("c232hm", &[], (0x08, 0x0B), (0x00, 0x00)),
+            builtin(
+                "olimex-arm-usb-ocd",
+                &[
+                    (Role::OutputEnable, Pin::low(4).inverted()),
+                    (Role::Trst, Pin::high(0).inverted()),
+                    (Role::Srst, Pin::high(1).inverted()),
+                    (Role::TrstOutputEnable, Pin::high(2).inverted()),
+                    (Role::SrstOutputEnable, Pin::high(3).inverted()),
+                ],
+                (0x08, 0x1B),
+                (0x0F, 0x0F),
+            ),
+            builtin(
+                "olimex-arm-usb-ocd-h",
+                &[
+                    (Role::Trst, Pin::high(0).inverted()),
+                    (Role::Srst, Pin::high(1).inverted()),
+                    (Role::Led, Pin::high(3)),
+                ],
+                (0x08, 0x1B),
+                (0x0B, 0x0B),
+            ),
+            builtin("digilent-hs2", &[], (0xE8, 0xEB), (0x00, 0x60)),
+            builtin(
+                "tigard",
+                &[
+                    (Role::Trst, Pin::low(4).inverted()),
+                    (Role::Srst, Pin::low(5).inverted()),
+                ],
+                (0x38, 0x3B),
+                (0x00, 0x00),
+            ),
+        ]
+    }
+
+    /// Find a built-in profile by name.
+    pub fn find(name: &str) -> Option<Profile> {
+        Profile::builtins()
+            .into_iter()
+            .find(|profile| profile.name == name)
+    }
+
+    /// The pin used for `role`, if the adapter has one.
+    pub fn pin(&self, role: Role) -> Option<Pin> {
+        self.pins
+            .iter()
+            .find(|(r, _)| *r == role)
+            .map(|(_, pin)| *pin)
+    }
+
+    /// Set both ranges of pins to the profile's initial state.
+    ///
+    /// This will generate two Set Data Bits commands, which the builder then tracks so that
+    /// later pin changes keep the rest of the adapter's pins as they are.
+    pub fn init(&self, builder: Builder) -> Builder {
+        builder
+            .set_pins(PinRange::Low, self.low.direction, self.low.value)
+            .then()
+            .set_pins(PinRange::High, self.high.direction, self.high.value)
+            .then()
+    }
+
+    /// Assert or release the pin for `role`, allowing for active-low pins.
+    ///
+    /// Panics if the adapter has no pin for `role`.
+    pub fn set(&self, builder: Builder, role: Role, asserted: bool) -> SetPinsBuilder {
+        let pin = self
+            .pin(role)
+            .unwrap_or_else(|| panic!("{} has no {:?} pin", self.name, role));
+        let value = match asserted != pin.active_low {
+            true => PinValue::High,
+            false => PinValue::Low,
+        };
+
+        builder.set_pin(pin.number(), value)
+    }
+}
+
+#[cfg(feature = "profiles")]
+mod file {
+    use std::collections::BTreeMap;
+    use std::fmt;
+
+    use serde::Deserialize;
+
+    use super::{Pin, Profile, Role};
+    use crate::builder::PinState;
+    use crate::command::PinRange;
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum RangeFile {
+        Low,
+        High,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct PinFile {
+        range: RangeFile,
+        bit: u8,
+        #[serde(default)]
+        active_low: bool,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(deny_unknown_fields)]
+    struct StateFile {
+        #[serde(default)]
+        value: u8,
+        #[serde(default)]
+        direction: u8,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct ProfileFile {
+        name: String,
+        #[serde(default)]
+        low: StateFile,
+        #[serde(default)]
+        high: StateFile,
+        pins: BTreeMap<Role, PinFile>,
+    }
+
+    /// Error returned when loading a profile from TOML.
+    #[derive(Debug)]
+    pub enum LoadError {
+        /// The file isn't valid TOML, or doesn't have the expected fields.
+        Toml(toml::de::Error),
+        /// A pin's bit is outside its range.
+        InvalidBit { role: Role, bit: u8 },
+    }
+
+    impl fmt::Display for LoadError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                LoadError::Toml(err) => write!(f, "{}", err),
+                LoadError::InvalidBit { role, bit } => {
+                    write!(
+                        f,
+                        "{:?} pin has bit {}, which is not from 0 to 7",
+                        role, bit
+                    )
+                }
+            }
+        }
+    }
+
+    impl std::error::Error for LoadError {}
+
+    impl Profile {
+        /// Load a profile from TOML.
+        ///
+        /// ```
+        /// use mpsse::board::{Profile, Role};
+        /// use mpsse::PinRange;
+        ///
+        /// let profile = Profile::from_toml(r#"
+        ///     name = "my-adapter"
+        ///     low = { value = 0x08, direction = 0x1B }
+        ///
+        ///     [pins]
+        ///     tck = { range = "low", bit = 0 }
+        ///     tdi = { range = "low", bit = 1 }
+        ///     tdo = { range = "low", bit = 2 }
+        ///     tms = { range = "low", bit = 3 }
+        ///     srst = { range = "high", bit = 1, active_low = true }
+        /// "#).unwrap();
+        ///
+        /// assert_eq!(profile.pin(Role::Srst).unwrap().range, PinRange::High);
+        /// assert_eq!(profile.low.direction, 0x1B);
+        /// ```
+        pub fn from_toml(source: &str) -> Result<Profile, LoadError> {
+            let file: ProfileFile = toml::from_str(source).map_err(LoadError::Toml)?;
+
+            let pins = file
+                .pins
+                .into_iter()
+                .map(|(role, pin)| match pin.bit {
+                    0..=7 => Ok((
+                        role,
+                        Pin {
+                            range: match pin.range {
+                                RangeFile::Low => PinRange::Low,
+                                RangeFile::High => PinRange::High,
+                            },
+                            bit: pin.bit,
+                            active_low: pin.active_low,
+                        },
+                    )),
+                    bit => Err(LoadError::InvalidBit { role, bit }),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(Profile {
+                name: file.name,
+                pins,
+                low: PinState {
+                    value: file.low.value,
+                    direction: file.low.direction,
+                },
+                high: PinState {
+                    value: file.high.value,
+                    direction: file.high.direction,
+                },
+            })
+        }
+    }
+}
+
+#[cfg(feature = "profiles")]
+pub use file::LoadError;
+
+#[cfg(test)]
+mod board_tests {
+    use super::*;
+
+    #[test]
+    fn builtin_init_matches_pins() {
+        for profile in Profile::builtins() {
+            // Every output role starts out released, and only TDO is an input.
+            for (role, pin) in profile.pins.iter() {
+                let state = match pin.range {
+                    PinRange::Low => profile.low,
+                    PinRange::High => profile.high,
+                };
+                let bit = 1 << pin.bit;
+                assert_eq!(
+                    state.direction & bit != 0,
+                    *role != Role::Tdo,
+                    "{} {:?}",
+                    profile.name,
+                    role
+                );
+                if let Role::Srst | Role::Trst | Role::SrstOutputEnable | Role::TrstOutputEnable =
+                    role
+                {
+                    assert_eq!(state.value & bit != 0, pin.active_low);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn set_keeps_other_pins() {
+        let profile = Profile::find("olimex-arm-usb-ocd").unwrap();
+        let builder = profile.init(Builder::new());
+        let builder = profile.set(builder, Role::SrstOutputEnable, true).then();
+
+        assert_eq!(
+            builder.pin_state(PinRange::High),
+            PinState {
+                value: 0x07,
+                direction: 0x0F
+            }
+        );
+    }
+}
@@ -0,0 +1,846 @@
+//! Boundary Scan Description Language (BSDL) parser, and boundary-scan pin control.
+//!
+//! BSDL files describe a device's JTAG instructions, its boundary register and how the register's
+//! cells map to package pins. [`parse`] pulls these out into a [`Bsdl`], which a
+//! [`BoundaryScan`] driver uses to drive and sample the device's pins by name.
+//!
+//! ```
+//! use mpsse::bsdl::{parse, CellFunction};
+//!
+//! let bsdl = parse(r#"
+//!     entity EXAMPLE is
+//!         generic (PHYSICAL_PIN_MAP : string := "SO8");
+//!         port (LED : out bit; BUTTON : in bit);
+//!
+//!         attribute INSTRUCTION_LENGTH of EXAMPLE : entity is 2;
+//!         attribute INSTRUCTION_OPCODE of EXAMPLE : entity is
+//!             "BYPASS (11), EXTEST (00), SAMPLE (01)";
+//!         constant SO8 : PIN_MAP_STRING := "LED : 3, BUTTON : 4";
+//!
+//!         attribute BOUNDARY_LENGTH of EXAMPLE : entity is 3;
+//!         attribute BOUNDARY_REGISTER of EXAMPLE : entity is
+//!             "0 (BC_1, BUTTON, input, X), " &
+//!             "1 (BC_1, *, control, 0), " &
+//!             "2 (BC_1, LED, output3, X, 1, 0, Z)";
+//!     end EXAMPLE;
+//! "#).unwrap();
+//!
+//! assert_eq!(bsdl.opcode("SAMPLE"), Some(&[true, false][..]));
+//! assert_eq!(bsdl.cells[2].function, CellFunction::Output3);
+//! assert_eq!(bsdl.port("3"), Some("LED"));
+//! ```
+use std::fmt;
+
+use crate::jtag::{self, Jtag, TapState};
+use crate::transport::Transport;
+
+/// Error found while parsing a BSDL file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The line the statement starts on, counting from 1, or `None` if something is missing
+    /// from the whole file.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// What a boundary register cell does, from the third field of its `BOUNDARY_REGISTER` entry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CellFunction {
+    Input,
+    Clock,
+    /// A two-state output.
+    Output2,
+    /// A three-state output, enabled by a control cell.
+    Output3,
+    Control,
+    /// A control cell that is forced to its disable value in Test-Logic-Reset.
+    ControlR,
+    Internal,
+    Bidir,
+    ObserveOnly,
+}
+
+impl CellFunction {
+    fn parse(name: &str) -> Option<Self> {
+        let function = match name {
+            "INPUT" => CellFunction::Input,
+            "CLOCK" => CellFunction::Clock,
+            "OUTPUT2" => CellFunction::Output2,
+            "OUTPUT3" => CellFunction::Output3,
+            "CONTROL" => CellFunction::Control,
+            "CONTROLR" => CellFunction::ControlR,
+            "INTERNAL" => CellFunction::Internal,
+            "BIDIR" => CellFunction::Bidir,
+            "OBSERVE_ONLY" => CellFunction::ObserveOnly,
+            _ => return None,
+        };
+
+        Some(function)
+    }
+
+    /// Whether the cell captures the level on its pin.
+    pub fn is_input(self) -> bool {
+        matches!(
+            self,
+            CellFunction::Input
+                | CellFunction::Clock
+                | CellFunction::Bidir
+                | CellFunction::ObserveOnly
+        )
+    }
+
+    /// Whether the cell drives its pin.
+    pub fn is_output(self) -> bool {
+        matches!(
+            self,
+            CellFunction::Output2 | CellFunction::Output3 | CellFunction::Bidir
+        )
+    }
+}
+
+/// The control cell that enables an output cell.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Control {
+    /// The control cell's number.
+    pub cell: usize,
+    /// The value that turns the output off.
+    pub disable_value: bool,
+}
+
+/// A cell of the boundary register.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    /// The cell's position in the register, counting from 0 nearest TDO.
+    pub number: usize,
+    /// The cell's design, e.g. `BC_1`.
+    pub cell_type: String,
+    /// The port the cell is connected to, or `None` for internal and control cells.
+    pub port: Option<String>,
+    pub function: CellFunction,
+    /// The value that is safe to load into the cell, or `None` if it doesn't matter.
+    pub safe: Option<bool>,
+    pub control: Option<Control>,
+}
+
+/// A package pin, and the port it is bonded to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pin {
+    /// The port name, with an index for elements of bit vector ports, e.g. `D(3)`.
+    pub port: String,
+    /// The package pin, e.g. `12` or `A3`.
+    pub pin: String,
+}
+
+/// A JTAG instruction and the opcodes that select it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub name: String,
+    /// The instruction's opcodes, first bit shifted first.
+    pub opcodes: Vec<Vec<bool>>,
+}
+
+/// The parts of a BSDL file needed for boundary scan.
+///
+/// Names are all uppercase, as BSDL is case insensitive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bsdl {
+    pub entity: String,
+    pub instruction_length: usize,
+    pub instructions: Vec<Instruction>,
+    /// The boundary register's cells, in order from TDO.
+    pub cells: Vec<Cell>,
+    /// The package pins of the selected `PHYSICAL_PIN_MAP`, if there is one.
+    pub pins: Vec<Pin>,
+}
+
+impl Bsdl {
+    /// Get the first opcode of the named instruction.
+    pub fn opcode(&self, name: &str) -> Option<&[bool]> {
+        let name = name.to_uppercase();
+        self.instructions
+            .iter()
+            .find(|instruction| instruction.name == name)
+            .and_then(|instruction| instruction.opcodes.first())
+            .map(Vec::as_slice)
+    }
+
+    /// Get the port for a port name or package pin.
+    pub fn port(&self, name: &str) -> Option<&str> {
+        let name = normalize(name);
+        let is_port = |port: &Option<String>| port.as_deref() == Some(name.as_str());
+        if let Some(cell) = self.cells.iter().find(|cell| is_port(&cell.port)) {
+            return cell.port.as_deref();
+        }
+
+        self.pins
+            .iter()
+            .find(|pin| pin.pin == name)
+            .map(|pin| pin.port.as_str())
+    }
+
+    /// Find the cell for `port` matching `filter`.
+    fn cell(&self, port: &str, filter: impl Fn(CellFunction) -> bool) -> Option<&Cell> {
+        self.cells
+            .iter()
+            .find(|cell| cell.port.as_deref() == Some(port) && filter(cell.function))
+    }
+}
+
+/// Uppercase a name and take out whitespace, so `pb (7)` and `PB(7)` match.
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase()
+}
+
+/// Split `text` at each top-level `separator`, ignoring those inside parentheses.
+fn split_top_level(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            c if c == separator && depth == 0 => {
+                parts.push(&text[start..index]);
+                start = index + 1;
+            }
+            _ => (),
+        }
+    }
+
+    parts.push(&text[start..]);
+    parts.retain(|part| !part.trim().is_empty());
+    parts
+}
+
+/// Split a file into statements, each paired with the line it starts on, taking out comments.
+fn statements(source: &str) -> Vec<(usize, String)> {
+    let mut statements = Vec::new();
+    let mut text = String::new();
+    let mut start = None;
+    let mut quoted = false;
+
+    for (index, line) in source.lines().enumerate() {
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if !quoted && c == '-' && chars.peek() == Some(&'-') {
+                break;
+            }
+            if c == '"' {
+                quoted = !quoted;
+            }
+            if !quoted && c == ';' {
+                statements.push((start.take().unwrap_or(index + 1), text.trim().to_string()));
+                text.clear();
+                continue;
+            }
+            if start.is_none() && !c.is_whitespace() {
+                start = Some(index + 1);
+            }
+            text.push(c);
+        }
+        text.push(' ');
+    }
+
+    statements
+}
+
+/// Concatenate the string literals in a VHDL expression like `"a, " & "b"`.
+fn strings(value: &str) -> String {
+    value.split('"').skip(1).step_by(2).collect()
+}
+
+/// Strip a case-insensitive keyword from the start of `text`.
+fn keyword<'a>(text: &'a str, keyword: &str) -> Option<&'a str> {
+    let text = text.trim_start();
+    match text.get(..keyword.len()) {
+        Some(start) if start.eq_ignore_ascii_case(keyword) => Some(&text[keyword.len()..]),
+        _ => None,
+    }
+}
+
+/// Split `text` around the first whitespace-delimited case-insensitive `keyword`.
+fn split_keyword<'a>(text: &'a str, keyword: &str) -> Option<(&'a str, &'a str)> {
+    let mut start = 0;
+    for word in text.split(|c: char| c.is_ascii_whitespace()) {
+        let end = start + word.len();
+        if word.eq_ignore_ascii_case(keyword) {
+            return Some((&text[..start], &text[end..]));
+        }
+        start = end + 1;
+    }
+    None
+}
+
+/// Split `attribute NAME of ENTITY : entity is VALUE` into the name and value.
+fn attribute(statement: &str) -> Option<(String, &str)> {
+    let rest = keyword(statement, "attribute")?;
+    let (name, rest) = split_keyword(rest, "of")?;
+    let (_, rest) = rest.split_once(':')?;
+    let value = keyword(keyword(rest, "entity")?, "is")?;
+    Some((name.trim().to_uppercase(), value.trim()))
+}
+
+/// Split `constant NAME : PIN_MAP_STRING := VALUE` into the name and value.
+fn pin_map_constant(statement: &str) -> Option<(String, &str)> {
+    let rest = keyword(statement, "constant")?;
+    let (name, rest) = rest.split_once(':')?;
+    let value = keyword(keyword(rest, "PIN_MAP_STRING")?, ":=")?;
+    Some((name.trim().to_uppercase(), value))
+}
+
+/// Get the declared ranges of bit vector ports, as the index of each element in order.
+fn vector_ranges(statement: &str) -> Vec<(String, Vec<usize>)> {
+    let upper = statement.to_uppercase();
+    let declaration = upper.trim_start().trim_start_matches("PORT").trim_start();
+    let declaration = declaration.strip_prefix('(').unwrap_or(declaration);
+
+    let (names, kind) = match declaration.split_once(':') {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+    let range = match kind.split_once("BIT_VECTOR") {
+        Some((_, range)) => range.trim().trim_start_matches('(').trim_end_matches(')'),
+        None => return Vec::new(),
+    };
+    let bounds: Vec<&str> = range.split_whitespace().collect();
+    let indices: Vec<usize> = match bounds.as_slice() {
+        [from, "TO", to] => match (from.parse(), to.parse()) {
+            (Ok(from), Ok(to)) => (from..=to).collect(),
+            _ => return Vec::new(),
+        },
+        [from, "DOWNTO", to] => match (from.parse(), to.parse()) {
+            (Ok(from), Ok(to)) => (to..=from).rev().collect(),
+            _ => return Vec::new(),
+        },
+        _ => return Vec::new(),
+    };
+
+    names
+        .split(',')
+        .map(|name| (name.trim().to_string(), indices.clone()))
+        .collect()
+}
+
+fn parse_opcodes(value: &str) -> Result<Vec<Instruction>, String> {
+    split_top_level(&strings(value), ',')
+        .into_iter()
+        .map(|entry| {
+            let (name, codes) = entry
+                .split_once('(')
+                .ok_or_else(|| format!("bad opcode entry {}", entry.trim()))?;
+            let opcodes = codes
+                .trim_end()
+                .trim_end_matches(')')
+                .split(',')
+                .map(|code| {
+                    code.trim()
+                        .chars()
+                        .rev()
+                        .map(|bit| match bit {
+                            '0' => Ok(false),
+                            '1' => Ok(true),
+                            _ => Err(format!("bad opcode {}", code.trim())),
+                        })
+                        .collect()
+                })
+                .collect::<Result<_, _>>()?;
+
+            Ok(Instruction {
+                name: name.trim().to_uppercase(),
+                opcodes,
+            })
+        })
+        .collect()
+}
+
+fn parse_cell(entry: &str) -> Result<Cell, String> {
+    let bad = || format!("bad boundary register cell {}", entry.trim());
+    let (number, fields) = entry.split_once('(').ok_or_else(bad)?;
+    let fields = fields.trim_end().strip_suffix(')').ok_or_else(bad)?;
+    let fields: Vec<String> = split_top_level(fields, ',')
+        .into_iter()
+        .map(normalize)
+        .collect();
+
+    let bit = |field: &str| match field {
+        "0" => Ok(Some(false)),
+        "1" => Ok(Some(true)),
+        "X" => Ok(None),
+        _ => Err(bad()),
+    };
+
+    let (cell_type, port, function, safe) = match fields.as_slice() {
+        [cell_type, port, function, safe, ..] => (cell_type, port, function, safe),
+        _ => return Err(bad()),
+    };
+    let control = match fields.get(4..6) {
+        Some([cell, disable_value]) => Some(Control {
+            cell: cell.parse().map_err(|_| bad())?,
+            disable_value: bit(disable_value)?.ok_or_else(bad)?,
+        }),
+        _ => None,
+    };
+
+    Ok(Cell {
+        number: number.trim().parse().map_err(|_| bad())?,
+        cell_type: cell_type.clone(),
+        port: match port.as_str() {
+            "*" => None,
+            port => Some(port.to_string()),
+        },
+        function: CellFunction::parse(function).ok_or_else(bad)?,
+        safe: bit(safe)?,
+        control,
+    })
+}
+
+fn parse_pin_map(value: &str, vectors: &[(String, Vec<usize>)]) -> Vec<Pin> {
+    let mut pins = Vec::new();
+    for entry in split_top_level(&strings(value), ',') {
+        let (port, packages) = match entry.split_once(':') {
+            Some((port, packages)) => (normalize(port), packages.trim()),
+            None => continue,
+        };
+
+        match packages.strip_prefix('(') {
+            Some(list) => {
+                let indices = vectors
+                    .iter()
+                    .find(|(name, _)| *name == port)
+                    .map(|(_, indices)| indices.clone());
+                for (position, pin) in list.trim_end_matches(')').split(',').enumerate() {
+                    let index = indices
+                        .as_ref()
+                        .and_then(|indices| indices.get(position).copied())
+                        .unwrap_or(position);
+                    pins.push(Pin {
+                        port: format!("{}({})", port, index),
+                        pin: normalize(pin),
+                    });
+                }
+            }
+            None => pins.push(Pin {
+                port,
+                pin: normalize(packages),
+            }),
+        }
+    }
+
+    pins
+}
+
+/// Parse the parts of a BSDL file needed for boundary scan.
+///
+/// The pin map used is the default of the `PHYSICAL_PIN_MAP` generic, or the first
+/// `PIN_MAP_STRING` constant if there is no default.
+pub fn parse(source: &str) -> Result<Bsdl, ParseError> {
+    let mut entity = None;
+    let mut pin_map_name = None;
+    let mut pin_maps = Vec::new();
+    let mut vectors = Vec::new();
+    let mut instruction_length = None;
+    let mut instructions = None;
+    let mut boundary_length = None;
+    let mut cells = None;
+
+    for (line, statement) in statements(source) {
+        let error = |message: String| ParseError {
+            line: Some(line),
+            message,
+        };
+
+        if let Some(rest) = keyword(&statement, "entity") {
+            if entity.is_none() {
+                entity = rest.split_whitespace().next().map(str::to_uppercase);
+            }
+        }
+        if statement.to_uppercase().contains("PHYSICAL_PIN_MAP") && statement.contains(":=") {
+            pin_map_name = statement
+                .split('"')
+                .nth(1)
+                .map(|name| name.trim().to_uppercase());
+        }
+        vectors.extend(vector_ranges(&statement));
+
+        if let Some((name, value)) = pin_map_constant(&statement) {
+            pin_maps.push((name, value.to_string()));
+            continue;
+        }
+
+        let (name, value) = match attribute(&statement) {
+            Some(attribute) => attribute,
+            None => continue,
+        };
+        let number = || {
+            value
+                .parse::<usize>()
+                .map_err(|_| error(format!("{} must be a number", name)))
+        };
+        match name.as_str() {
+            "INSTRUCTION_LENGTH" => instruction_length = Some(number()?),
+            "BOUNDARY_LENGTH" => boundary_length = Some(number()?),
+            "INSTRUCTION_OPCODE" => instructions = Some(parse_opcodes(value).map_err(error)?),
+            "BOUNDARY_REGISTER" => {
+                let register: Vec<Cell> = split_top_level(&strings(value), ',')
+                    .into_iter()
+                    .map(parse_cell)
+                    .collect::<Result<_, _>>()
+                    .map_err(error)?;
+                cells = Some((line, register));
+            }
+            _ => (),
+        }
+    }
+
+    let missing = |what: &str| ParseError {
+        line: None,
+        message: format!("missing {}", what),
+    };
+    let instruction_length = instruction_length.ok_or_else(|| missing("INSTRUCTION_LENGTH"))?;
+    let instructions = instructions.ok_or_else(|| missing("INSTRUCTION_OPCODE"))?;
+    let boundary_length = boundary_length.ok_or_else(|| missing("BOUNDARY_LENGTH"))?;
+    let (line, mut cells) = cells.ok_or_else(|| missing("BOUNDARY_REGISTER"))?;
+
+    cells.sort_by_key(|cell| cell.number);
+    let numbered = cells
+        .iter()
+        .enumerate()
+        .all(|(index, cell)| cell.number == index);
+    if cells.len() != boundary_length || !numbered {
+        return Err(ParseError {
+            line: Some(line),
+            message: format!(
+                "BOUNDARY_REGISTER does not have cells 0 to {}",
+                boundary_length as isize - 1
+            ),
+        });
+    }
+
+    if let Some(instruction) = instructions.iter().find(|instruction| {
+        instruction
+            .opcodes
+            .iter()
+            .any(|opcode| opcode.len() != instruction_length)
+    }) {
+        return Err(ParseError {
+            line: None,
+            message: format!(
+                "{} opcode is not {} bits",
+                instruction.name, instruction_length
+            ),
+        });
+    }
+
+    let pin_map = match pin_map_name {
+        Some(name) => pin_maps.into_iter().find(|(constant, _)| *constant == name),
+        None => pin_maps.into_iter().next(),
+    };
+    let pins = pin_map
+        .map(|(_, value)| parse_pin_map(&value, &vectors))
+        .unwrap_or_default();
+
+    Ok(Bsdl {
+        entity: entity.ok_or_else(|| missing("entity"))?,
+        instruction_length,
+        instructions,
+        cells,
+        pins,
+    })
+}
+
+/// Error returned by the [`BoundaryScan`] driver.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// Running the scans failed.
+    Jtag(jtag::Error<E>),
+    /// The device does not have the instruction.
+    MissingInstruction(&'static str),
+    /// No port or package pin has the name.
+    UnknownPin(String),
+    /// The pin has no cell that can drive it.
+    NotOutput(String),
+    /// The pin has no cell that can sample it.
+    NotInput(String),
+    /// The pin's output has no control cell, so it can't be released.
+    NotTristate(String),
+}
+
+impl<E> From<jtag::Error<E>> for Error<E> {
+    fn from(err: jtag::Error<E>) -> Self {
+        Error::Jtag(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Jtag(err) => write!(f, "{}", err),
+            Error::MissingInstruction(name) => write!(f, "device has no {} instruction", name),
+            Error::UnknownPin(name) => write!(f, "no pin named {}", name),
+            Error::NotOutput(name) => write!(f, "pin {} can't be driven", name),
+            Error::NotInput(name) => write!(f, "pin {} can't be sampled", name),
+            Error::NotTristate(name) => write!(f, "pin {} can't be released", name),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+/// Drive and sample a device's pins through its boundary register.
+///
+/// Pin changes made with [`set_pin`](BoundaryScan::set_pin) are staged, and take effect on the
+/// next [`update`](BoundaryScan::update) once [`extest`](BoundaryScan::extest) has handed the
+/// pins over to the boundary register. Every scan also samples the pins, which
+/// [`pin`](BoundaryScan::pin) reads back.
+///
+/// To address one device on a chain, [select it](Jtag::select_device) on the driver first.
+#[derive(Debug)]
+pub struct BoundaryScan<T> {
+    jtag: Jtag<T>,
+    bsdl: Bsdl,
+    register: Vec<bool>,
+    captured: Vec<bool>,
+}
+
+impl<T: Transport> BoundaryScan<T> {
+    /// Create a driver with every cell staged to its safe value.
+    pub fn new(jtag: Jtag<T>, bsdl: Bsdl) -> Self {
+        let register: Vec<bool> = bsdl
+            .cells
+            .iter()
+            .map(|cell| cell.safe.unwrap_or(false))
+            .collect();
+        let captured = vec![false; register.len()];
+
+        BoundaryScan {
+            jtag,
+            bsdl,
+            register,
+            captured,
+        }
+    }
+
+    /// Get the underlying JTAG driver.
+    pub fn jtag(&mut self) -> &mut Jtag<T> {
+        &mut self.jtag
+    }
+
+    /// Get back the underlying JTAG driver.
+    pub fn into_inner(self) -> Jtag<T> {
+        self.jtag
+    }
+
+    /// Get the device description.
+    pub fn bsdl(&self) -> &Bsdl {
+        &self.bsdl
+    }
+
+    /// Load an instruction into the device.
+    fn load(&mut self, names: &[&'static str]) -> Result<(), Error<T::Error>> {
+        let opcode = names
+            .iter()
+            .find_map(|name| self.bsdl.opcode(name))
+            .ok_or(Error::MissingInstruction(names[0]))?
+            .to_vec();
+
+        self.jtag.scan_ir(&opcode, TapState::RunTestIdle)?;
+        Ok(())
+    }
+
+    /// Load SAMPLE/PRELOAD, sampling the pins while the device carries on running normally.
+    ///
+    /// This also preloads the staged values into the boundary register, ready for EXTEST.
+    pub fn sample(&mut self) -> Result<(), Error<T::Error>> {
+        self.load(&["SAMPLE", "PRELOAD"])?;
+        self.update()
+    }
+
+    /// Preload the staged values, then load EXTEST, which hands the pins over to the boundary
+    /// register.
+    pub fn extest(&mut self) -> Result<(), Error<T::Error>> {
+        self.load(&["PRELOAD", "SAMPLE"])?;
+        self.update()?;
+        self.load(&["EXTEST"])
+    }
+
+    /// Shift the staged values into the boundary register, and sample the pins.
+    pub fn update(&mut self) -> Result<(), Error<T::Error>> {
+        self.captured = self.jtag.scan_dr(&self.register, TapState::RunTestIdle)?;
+        Ok(())
+    }
+
+    /// Get the port for a port name or package pin.
+    fn port(&self, name: &str) -> Result<String, Error<T::Error>> {
+        self.bsdl
+            .port(name)
+            .map(String::from)
+            .ok_or_else(|| Error::UnknownPin(name.to_string()))
+    }
+
+    /// Stage a pin to be driven high or low, or released with `None`.
+    pub fn set_pin(&mut self, name: &str, value: Option<bool>) -> Result<(), Error<T::Error>> {
+        let port = self.port(name)?;
+        let cell = self
+            .bsdl
+            .cell(&port, CellFunction::is_output)
+            .ok_or_else(|| Error::NotOutput(name.to_string()))?;
+
+        match (value, cell.control) {
+            (Some(value), control) => {
+                self.register[cell.number] = value;
+                if let Some(control) = control {
+                    self.register[control.cell] = !control.disable_value;
+                }
+            }
+            (None, Some(control)) => self.register[control.cell] = control.disable_value,
+            (None, None) => return Err(Error::NotTristate(name.to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// Read a pin's level from the last scan.
+    pub fn pin(&self, name: &str) -> Result<bool, Error<T::Error>> {
+        let port = self.port(name)?;
+        let cell = self
+            .bsdl
+            .cell(&port, CellFunction::is_input)
+            .ok_or_else(|| Error::NotInput(name.to_string()))?;
+
+        Ok(self.captured[cell.number])
+    }
+}
+
+#[cfg(test)]
+mod bsdl_tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    const BSDL: &str = r#"
+        -- A made up device; "quoted" -- text in a comment; is ignored
+        entity TOY is
+            generic (PHYSICAL_PIN_MAP : string := "QFN");
+            port (
+                TCK, TMS, TDI : in bit;
+                TDO : out bit;
+                D : inout bit_vector (1 downto 0);
+                GND : linkage bit
+            );
+
+            use STD_1149_1_2001.all;
+
+            attribute PIN_MAP of TOY : entity is PHYSICAL_PIN_MAP;
+            constant DIP : PIN_MAP_STRING := "D : (9, 8)";
+            constant QFN : PIN_MAP_STRING :=
+                "TCK : 1, TMS : 2, TDI : 3, TDO : 4, " &
+                "D : (A1, A2), GND : (5, 6)";
+
+            attribute INSTRUCTION_LENGTH of TOY : entity is 3;
+            attribute INSTRUCTION_OPCODE of TOY : entity is
+                "BYPASS (111)," &
+                "EXTEST (000)," &
+                "SAMPLE (001, 101)," &
+                "IDCODE (110)";
+
+            attribute BOUNDARY_LENGTH of TOY : entity is 5;
+            attribute BOUNDARY_REGISTER of TOY : entity is
+                "4 (BC_1, *, control, 1)," &
+                "3 (BC_7, D(1), bidir, X, 4, 1, Z)," &
+                "2 (BC_1, D(0), input, X)," &
+                "1 (BC_1, D(0), output3, X, 0, 1, Z)," &
+                "0 (BC_1, *, controlr, 1)";
+        end TOY;
+    "#;
+
+    #[test]
+    fn parses_registers_and_pins() {
+        let bsdl = parse(BSDL).unwrap();
+
+        assert_eq!(bsdl.entity, "TOY");
+        assert_eq!(bsdl.opcode("sample"), Some(&[true, false, false][..]));
+        assert_eq!(bsdl.instructions[2].opcodes.len(), 2);
+        assert_eq!(
+            bsdl.cells[3],
+            Cell {
+                number: 3,
+                cell_type: "BC_7".to_string(),
+                port: Some("D(1)".to_string()),
+                function: CellFunction::Bidir,
+                safe: None,
+                control: Some(Control {
+                    cell: 4,
+                    disable_value: true
+                }),
+            }
+        );
+        assert_eq!(bsdl.port("A1"), Some("D(1)"));
+        assert_eq!(bsdl.port("a2"), Some("D(0)"));
+        assert_eq!(bsdl.port("6"), Some("GND(1)"));
+    }
+
+    #[test]
+    fn attributes_ignore_case_and_spacing() {
+        assert_eq!(
+            attribute("ATTRIBUTE INSTRUCTION_LENGTH OF X : ENTITY IS 8"),
+            Some(("INSTRUCTION_LENGTH".to_string(), "8"))
+        );
+        assert_eq!(
+            attribute("attribute OFFSET\tOf X : entity is 2"),
+            Some(("OFFSET".to_string(), "2"))
+        );
+
+        let source = BSDL.replace(
+            "attribute INSTRUCTION_LENGTH of TOY : entity is 3",
+            "ATTRIBUTE INSTRUCTION_LENGTH\tOF TOY : ENTITY IS 3",
+        );
+        assert_eq!(parse(&source).unwrap().instruction_length, 3);
+    }
+
+    #[test]
+    fn boundary_length_must_match() {
+        let source = BSDL.replace(
+            "BOUNDARY_LENGTH of TOY : entity is 5",
+            "BOUNDARY_LENGTH of TOY : entity is 6",
+        );
+
+        assert!(parse(&source).is_err());
+    }
+
+    #[test]
+    fn drives_and_samples_pins() {
+        // Every scan captures: the bits before the last, then the last bit from the TMS shift.
+        // The final update samples D(0) high.
+        let transport = MockTransport::with_responses(&[0, 0, 0, 0, 0, 0, 0x40, 0x00]);
+        let mut boundary = BoundaryScan::new(Jtag::new(transport), parse(BSDL).unwrap());
+
+        boundary.set_pin("A2", Some(true)).unwrap();
+        boundary.set_pin("D(1)", None).unwrap();
+        assert_eq!(boundary.register, [false, true, false, false, true]);
+
+        boundary.extest().unwrap();
+        boundary.update().unwrap();
+        assert!(boundary.pin("D(0)").unwrap());
+        assert!(!boundary.pin("A1").unwrap());
+
+        match boundary.set_pin("GND(0)", Some(false)) {
+            Err(Error::UnknownPin(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
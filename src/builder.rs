@@ -1,9 +1,13 @@
 /// Simple buidlers for MPSSE commands
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use crate::clock::ClockModel;
 use crate::command::{Command, CommandList, DataShiftOptions};
+use crate::jtag::{Chain, TapState};
 
 pub use crate::command::{
-    BitDirection, ClockEdge, PinDirection, PinDirectionArray, PinRange, PinValue,
-    PinValueArray,
+    BitDirection, ClockEdge, PinDirection, PinDirectionArray, PinRange, PinValue, PinValueArray,
 };
 
 /// Internal macro for repetitive builder methods
@@ -21,10 +25,48 @@ macro_rules! builder_funcs {
     };
 }
 
+/// The value and direction of one byte of pins.
+///
+/// Each bit is one pin, with bit 0 being the lowest pin of the range. A set direction bit makes
+/// the pin an output.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct PinState {
+    pub value: u8,
+    pub direction: u8,
+}
+
+/// The last pin state set on each range by a Builder chain.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub(crate) struct GpioState {
+    low: PinState,
+    high: PinState,
+}
+
+impl GpioState {
+    fn get(&self, range: PinRange) -> PinState {
+        match range {
+            PinRange::Low => self.low,
+            PinRange::High => self.high,
+        }
+    }
+
+    fn get_mut(&mut self, range: PinRange) -> &mut PinState {
+        match range {
+            PinRange::Low => &mut self.low,
+            PinRange::High => &mut self.high,
+        }
+    }
+}
+
 /// Builder for MPSSE commands.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Builder {
-    commands: Vec<Command>,
+    pub(crate) commands: Vec<Command>,
+    pub(crate) tap_state: Option<TapState>,
+    pub(crate) jtag_device: Option<(Chain, usize)>,
+    pub(crate) gpio: GpioState,
+    /// The clock settings before the first command.
+    pub(crate) clock: ClockModel,
 }
 
 impl Builder {
@@ -32,9 +74,59 @@ impl Builder {
     pub fn new() -> Self {
         Builder {
             commands: Vec::new(),
+            tap_state: None,
+            jtag_device: None,
+            gpio: GpioState::default(),
+            clock: ClockModel::default(),
         }
     }
 
+    /// Assume the clock is already set up as `clock`, without sending anything.
+    ///
+    /// Builders start out assuming the clock settings after the MPSSE is reset.
+    pub fn with_clock_model(self, clock: ClockModel) -> Self {
+        Builder { clock, ..self }
+    }
+
+    /// The clock settings after the commands so far.
+    pub fn clock_model(&self) -> ClockModel {
+        let mut clock = self.clock;
+        for command in self.commands.iter() {
+            clock.apply(command);
+        }
+
+        clock
+    }
+
+    /// Assume the pins of `range` are already in the given state, without sending anything.
+    ///
+    /// Builders start out assuming every pin is an input driven low, as after the MPSSE is
+    /// reset. Use this to carry the state of one chain on to the next, see [`pin_state`].
+    ///
+    /// [`pin_state`]: Builder::pin_state
+    pub fn with_pin_state(mut self, range: PinRange, state: PinState) -> Self {
+        *self.gpio.get_mut(range) = state;
+        self
+    }
+
+    /// The state of the pins of `range` after the commands so far.
+    pub fn pin_state(&self, range: PinRange) -> PinState {
+        self.gpio.get(range)
+    }
+
+    /// Set the pins of `range` selected by `mask`, leaving the others as they were.
+    pub(crate) fn set_bits(&mut self, range: PinRange, mask: u8, value: u8, direction: u8) {
+        let state = self.gpio.get_mut(range);
+        state.value = state.value & !mask | value & mask;
+        state.direction = state.direction & !mask | direction & mask;
+
+        self.commands.push(Command::SetBits {
+            range,
+            value: state.value.into(),
+            direction: state.direction.into(),
+        });
+    }
+
     /// Write bytes of data, one bit at a time, on a single pin.
     ///
     /// This will generate a Data Shifting Command with the appropriate bits set to
@@ -119,6 +211,58 @@ impl Builder {
         }
     }
 
+    /// Drive a single pin to `value`, leaving the other pins as they were.
+    ///
+    /// Pins 0 to 7 are the low range, and pins 8 to 15 the high range. This generates a Set Data
+    /// Bits command for the pin's whole range, using the state tracked through this chain (see
+    /// [`pin_state`]). The pin's direction is left alone.
+    ///
+    /// Panics if `pin` is greater than 15.
+    ///
+    /// [`pin_state`]: Builder::pin_state
+    ///
+    /// ```
+    /// use mpsse::{Builder, PinDirection, PinRange, PinValue};
+    ///
+    /// let commands = Builder::new()
+    ///     .set_pins(PinRange::Low, 0b00001011, 0b00001000)
+    ///     .then()
+    ///     .set_direction(4, PinDirection::Output)
+    ///     .then()
+    ///     .set_pin(4, PinValue::High)
+    ///     .build();
+    ///
+    /// assert_eq!(commands, vec![0x80, 0x08, 0x0B, 0x80, 0x08, 0x1B, 0x80, 0x18, 0x1B])
+    /// ```
+    pub fn set_pin(self, pin: u8, value: PinValue) -> SetPinsBuilder {
+        let (range, bit) = pin_bit(pin);
+        let mut state = self.pin_state(range);
+        match value {
+            PinValue::High => state.value |= bit,
+            PinValue::Low => state.value &= !bit,
+        }
+
+        self.set_pins(range, state.direction, state.value)
+    }
+
+    /// Make a single pin an input or output, leaving the other pins as they were.
+    ///
+    /// Pins are numbered as for [`set_pin`], and the pin's value is left alone.
+    ///
+    /// Panics if `pin` is greater than 15.
+    ///
+    /// [`set_pin`]: Builder::set_pin
+    pub fn set_direction(self, pin: u8, direction: PinDirection) -> SetPinsBuilder {
+        let (range, bit) = pin_bit(pin);
+        let mut state = self.pin_state(range);
+        match direction {
+            PinDirection::Output => state.direction |= bit,
+            PinDirection::Input => state.direction &= !bit,
+        }
+
+        self.set_pins(range, state.direction, state.value)
+    }
+
     /// Read the value of input pins of the interface directly.
     ///
     /// This will generate a Read Data Bits command of the appropriate type
@@ -147,8 +291,8 @@ impl Builder {
     /// Set Clock Divisor command
     ///
     /// * `frequency` - The *target* frequency to set the clock to in hz. *Note*: this is a target
-    ///     frequency that may not be met due to MPSSE internals. If you need more definite control
-    ///     over the clock speed, use `.set_divisor()` instead.
+    ///   frequency that may not be met due to MPSSE internals. If you need more definite control
+    ///   over the clock speed, use `.set_divisor()` instead.
     ///
     /// ```
     /// use mpsse::Builder;
@@ -169,16 +313,37 @@ impl Builder {
         }
     }
 
-    pub fn set_divisor(self, _divisor: u16) -> ! {
-        todo!()
+    /// Set the clock divisor of the interface directly.
+    ///
+    /// This will generate a Set Clock Divisor command. The clock runs at the chip's base clock
+    /// divided by `2 * (1 + divisor)`. The base clock is 12MHz, or 60MHz on H-series chips with
+    /// divide-by-5 disabled.
+    ///
+    /// ```
+    /// use mpsse::Builder;
+    ///
+    /// let commands = Builder::new()
+    ///     .set_divisor(29)
+    ///     .build();
+    ///
+    /// assert_eq!(commands, vec![0x86, 0x1D, 0x00])
+    /// ```
+    pub fn set_divisor(self, divisor: u16) -> SetDivisorBuilder {
+        SetDivisorBuilder {
+            parent: self,
+            divisor,
+        }
     }
 
-    /// Wait for IO on pin 1.
+    /// Wait for IO on GPIOL1 (ADBUS5).
     ///
-    /// This will send a Set Clock Frequency command
+    /// This will generate a Wait On I/O High or Low command. The MPSSE runs nothing else until
+    /// the pin changes, so see [`transport::wait_for_ready`] for a wait that can time out.
     ///
     /// * `value` - Whether to wait for a High or Low state on the pin.
     ///
+    /// [`transport::wait_for_ready`]: crate::transport::wait_for_ready
+    ///
     /// ```
     /// use mpsse::{Builder, PinValue};
     ///
@@ -195,6 +360,115 @@ impl Builder {
         }
     }
 
+    /// Clock without moving any data until GPIOL1 (ADBUS5) is at `value`, or for `length` bytes.
+    ///
+    /// This will generate a Clock For n x 8 Bits Or Until GPIOL1 command, which unlike
+    /// [`wait_for_io`](Builder::wait_for_io) always finishes.
+    ///
+    /// Panics if `length` is 0.
+    ///
+    /// ```
+    /// use mpsse::{Builder, PinValue};
+    ///
+    /// let commands = Builder::new()
+    ///     .clock_until_io(PinValue::Low, 256)
+    ///     .build();
+    ///
+    /// assert_eq!(commands, vec![0x9D, 0xFF, 0x00])
+    /// ```
+    pub fn clock_until_io(self, value: PinValue, length: u16) -> ClockUntilIoBuilder {
+        assert!(length > 0, "cannot clock for 0 bytes");
+        ClockUntilIoBuilder {
+            parent: self,
+            value,
+            length,
+        }
+    }
+
+    /// Enable or disable adaptive clocking.
+    ///
+    /// While enabled, the MPSSE waits for each clock edge to be echoed back on GPIOL3 (RTCK)
+    /// before continuing. This is only available on H-series chips.
+    ///
+    /// ```
+    /// use mpsse::Builder;
+    ///
+    /// let commands = Builder::new()
+    ///     .set_adaptive_clocking(true)
+    ///     .build();
+    ///
+    /// assert_eq!(commands, vec![0x96])
+    /// ```
+    pub fn set_adaptive_clocking(self, enable: bool) -> SetAdaptiveClockingBuilder {
+        SetAdaptiveClockingBuilder {
+            parent: self,
+            enable,
+        }
+    }
+
+    /// Wait for at least `duration`, using the clock settings from [`clock_model`].
+    ///
+    /// By default this will generate clock-only commands, which toggle the clock pin without
+    /// moving any data. Use [`DelayBuilder::without_clock`] when the clock must stay still.
+    /// [`DelayBuilder::achieved`] gives the delay the commands actually take.
+    ///
+    /// Panics when committed if the delay takes more than `u32::MAX` clock cycles or commands.
+    ///
+    /// [`clock_model`]: Builder::clock_model
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use mpsse::Builder;
+    ///
+    /// // 20 cycles at 1MHz.
+    /// let delay = Builder::new()
+    ///     .set_divisor(5)
+    ///     .then()
+    ///     .delay(Duration::from_micros(20));
+    ///
+    /// assert_eq!(delay.achieved(), Duration::from_micros(20));
+    /// assert_eq!(delay.build(), vec![0x86, 0x05, 0x00, 0x8F, 0x01, 0x00, 0x8E, 0x03])
+    /// ```
+    pub fn delay(self, duration: Duration) -> DelayBuilder {
+        DelayBuilder {
+            parent: self,
+            duration,
+            clock: true,
+        }
+    }
+
+    /// Flush the MPSSE's response buffer back to the host straight away.
+    ///
+    /// This will generate a Send Immediate command
+    ///
+    /// ```
+    /// use mpsse::{Builder, PinRange};
+    ///
+    /// let commands = Builder::new()
+    ///     .read_pins(PinRange::Low)
+    ///     .then()
+    ///     .send_immediate()
+    ///     .build();
+    ///
+    /// assert_eq!(commands, vec![0x81, 0x87])
+    /// ```
+    pub fn send_immediate(self) -> SendImmediateBuilder {
+        SendImmediateBuilder { parent: self }
+    }
+
+    /// The number of bytes the device will send back after running the current command list.
+    pub fn expected_response_length(&self) -> usize {
+        self.commands
+            .iter()
+            .map(|command| command.expected_response_length())
+            .sum()
+    }
+
+    /// Get the current command list, without turning it into bytes.
+    pub fn into_command_list(self) -> CommandList {
+        CommandList(self.commands)
+    }
+
     /// Build the current command list into a sequence of bytes.
     pub fn build(self) -> Vec<u8> {
         CommandList(self.commands).into()
@@ -349,6 +623,14 @@ impl WriteBuilder {
     builder_funcs!();
 }
 
+/// The range and bit of a pin numbered as for [`Builder::set_pin`].
+fn pin_bit(pin: u8) -> (PinRange, u8) {
+    match pin {
+        0..=7 => (PinRange::Low, 1 << pin),
+        8..=15 => (PinRange::High, 1 << (pin - 8)),
+        _ => panic!("pin {} is out of range", pin),
+    }
+}
 
 /// Build a Set Pins command
 #[derive(Debug)]
@@ -362,11 +644,8 @@ pub struct SetPinsBuilder {
 impl SetPinsBuilder {
     /// Commit this command to the parent Builder.
     fn commit(mut self) -> Builder {
-        self.parent.commands.push(Command::SetBits {
-            range: self.range,
-            value: self.value,
-            direction: self.direction,
-        });
+        self.parent
+            .set_bits(self.range, 0xFF, self.value.into(), self.direction.into());
 
         self.parent
     }
@@ -394,6 +673,26 @@ impl SetFrequencyBuilder {
     builder_funcs!();
 }
 
+/// Build a Set Divisor command using the given divisor.
+#[derive(Debug)]
+pub struct SetDivisorBuilder {
+    parent: Builder,
+    divisor: u16,
+}
+
+impl SetDivisorBuilder {
+    /// Commit this command to the parent Builder.
+    fn commit(mut self) -> Builder {
+        self.parent.commands.push(Command::SetClockDivisor {
+            divisor: self.divisor,
+        });
+
+        self.parent
+    }
+
+    builder_funcs!();
+}
+
 #[derive(Debug)]
 pub struct WaitForIoBuilder {
     parent: Builder,
@@ -413,6 +712,27 @@ impl WaitForIoBuilder {
     builder_funcs!();
 }
 
+#[derive(Debug)]
+pub struct ClockUntilIoBuilder {
+    parent: Builder,
+    value: PinValue,
+    length: u16,
+}
+
+impl ClockUntilIoBuilder {
+    /// Commit this command to the parent Builder.
+    fn commit(mut self) -> Builder {
+        self.parent.commands.push(Command::ClockBytesUntilIo {
+            value: self.value,
+            length: self.length,
+        });
+
+        self.parent
+    }
+
+    builder_funcs!();
+}
+
 #[derive(Debug)]
 pub struct ReadPinsBuilder {
     parent: Builder,
@@ -432,6 +752,103 @@ impl ReadPinsBuilder {
     builder_funcs!();
 }
 
+#[derive(Debug)]
+pub struct SetAdaptiveClockingBuilder {
+    parent: Builder,
+    enable: bool,
+}
+
+impl SetAdaptiveClockingBuilder {
+    /// Commit this command to the parent Builder.
+    fn commit(mut self) -> Builder {
+        self.parent.commands.push(Command::SetAdaptiveClocking {
+            enable: self.enable,
+        });
+
+        self.parent
+    }
+
+    builder_funcs!();
+}
+
+/// Build a delay from clock-only or Set Data Bits commands.
+#[derive(Debug)]
+pub struct DelayBuilder {
+    parent: Builder,
+    duration: Duration,
+    clock: bool,
+}
+
+impl DelayBuilder {
+    /// Keep the clock still, repeating Set Data Bits with the current low pin state instead.
+    ///
+    /// Each command is 3 bytes and takes [`ClockModel::command_time`], so long delays make large
+    /// command lists: a millisecond is 60KB by default.
+    pub fn without_clock(self) -> Self {
+        DelayBuilder {
+            clock: false,
+            ..self
+        }
+    }
+
+    /// The number of clock cycles or commands the delay takes.
+    fn steps(&self) -> u32 {
+        let clock = self.parent.clock_model();
+        let steps = match self.clock {
+            true => clock.cycles_for(self.duration),
+            false => {
+                let step = clock.command_time.as_nanos().max(1);
+                self.duration.as_nanos().div_ceil(step)
+            }
+        };
+        u32::try_from(steps)
+            .unwrap_or_else(|_| panic!("a delay of {:?} takes too many steps", self.duration))
+    }
+
+    /// The delay the generated commands take to the nearest nanosecond, which is at least the
+    /// requested delay.
+    pub fn achieved(&self) -> Duration {
+        let clock = self.parent.clock_model();
+        match self.clock {
+            true => clock.clock_time(self.steps() as u64),
+            false => clock.command_time * self.steps(),
+        }
+    }
+
+    /// Commit this command to the parent Builder.
+    fn commit(mut self) -> Builder {
+        let steps = self.steps() as usize;
+        match self.clock {
+            true => crate::jtag::push_clocks(&mut self.parent.commands, steps),
+            false => {
+                for _ in 0..steps {
+                    self.parent.set_bits(PinRange::Low, 0, 0, 0);
+                }
+            }
+        }
+
+        self.parent
+    }
+
+    builder_funcs!();
+}
+
+#[derive(Debug)]
+pub struct SendImmediateBuilder {
+    parent: Builder,
+}
+
+impl SendImmediateBuilder {
+    /// Commit this command to the parent Builder.
+    fn commit(mut self) -> Builder {
+        self.parent.commands.push(Command::SendImmediate);
+
+        self.parent
+    }
+
+    builder_funcs!();
+}
+
 #[cfg(test)]
 mod write_builder_tests {
     use super::*;
@@ -486,3 +903,121 @@ mod set_freq_tests {
         assert_eq!(command_bytes, vec![0x86, 0xAF, 0x04]);
     }
 }
+
+#[cfg(test)]
+mod gpio_tests {
+    use super::*;
+
+    #[test]
+    fn high_pins_keep_their_state() {
+        let builder = Builder::new()
+            .set_pins(PinRange::High, 0xF0, 0x30)
+            .then()
+            .set_pin(9, PinValue::High)
+            .then()
+            .set_direction(12, PinDirection::Input)
+            .then();
+
+        assert_eq!(
+            builder.pin_state(PinRange::High),
+            PinState {
+                value: 0x32,
+                direction: 0xE0
+            }
+        );
+        assert_eq!(builder.pin_state(PinRange::Low), PinState::default());
+        assert_eq!(
+            builder.build(),
+            vec![0x82, 0x30, 0xF0, 0x82, 0x32, 0xF0, 0x82, 0x32, 0xE0]
+        );
+    }
+
+    #[test]
+    fn protocols_leave_other_pins_alone() {
+        let state = PinState {
+            value: 0x80,
+            direction: 0x90,
+        };
+        let commands = Builder::new()
+            .with_pin_state(PinRange::Low, state)
+            .spi()
+            .setup()
+            .build();
+
+        assert_eq!(commands, vec![0x80, 0x88, 0x9B]);
+    }
+}
+
+#[cfg(test)]
+mod delay_tests {
+    use super::*;
+
+    #[test]
+    fn delay_without_clock_repeats_pins() {
+        let delay = Builder::new()
+            .set_pins(PinRange::Low, 0x0B, 0x08)
+            .then()
+            .delay(Duration::from_nanos(120))
+            .without_clock();
+
+        assert_eq!(delay.achieved(), Duration::from_nanos(150));
+        assert_eq!(
+            delay.build(),
+            vec![0x80, 0x08, 0x0B, 0x80, 0x08, 0x0B, 0x80, 0x08, 0x0B, 0x80, 0x08, 0x0B]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn delay_rejects_too_many_steps() {
+        // 2 * 10^10 commands at 50ns each.
+        Builder::new()
+            .delay(Duration::from_secs(1000))
+            .without_clock()
+            .build();
+    }
+
+    #[test]
+    fn delay_rounds_up_to_whole_cycles() {
+        let delay = Builder::new().delay(Duration::from_nanos(200));
+
+        // Each cycle takes 1/6us after reset.
+        assert_eq!(delay.steps(), 2);
+        assert_eq!(delay.build(), vec![0x8E, 0x01]);
+    }
+
+    #[test]
+    fn delay_uses_exact_cycle_time() {
+        // 60MHz / 22 gives cycles of 366.67ns, which bit_time rounds to 367ns. Counting cycles
+        // of 367ns would stop 1ms short at 2725 cycles, or 999.17us.
+        let clock = ClockModel {
+            divide_by_5: false,
+            divisor: 10,
+            ..ClockModel::default()
+        };
+
+        let delay = Builder::new()
+            .with_clock_model(clock)
+            .delay(Duration::from_millis(1));
+        assert_eq!(delay.steps(), 2728);
+        assert_eq!(delay.achieved(), Duration::from_nanos(1_000_267));
+        assert_eq!(delay.build(), vec![0x8F, 0x54, 0x01]);
+
+        let delay = Builder::new()
+            .with_clock_model(clock)
+            .delay(Duration::from_nanos(1100));
+        assert_eq!(delay.steps(), 3);
+        assert_eq!(delay.achieved(), Duration::from_nanos(1100));
+    }
+}
+
+#[cfg(test)]
+mod clock_until_io_tests {
+    use super::*;
+
+    #[test]
+    #[should_panic]
+    fn zero_length_is_rejected() {
+        Builder::new().clock_until_io(PinValue::High, 0).build();
+    }
+}
@@ -0,0 +1,171 @@
+//! A model of the MPSSE clock, for turning cycles into time.
+//!
+//! The clock runs at a base clock of 60MHz on H-series chips, or 12MHz with divide-by-5 enabled
+//! (the only option on the FT2232D), divided by `2 * (1 + divisor)`. Three-phase clocking makes
+//! each bit take one and a half clock periods.
+//!
+//! A [`Builder`](crate::Builder) follows the clock settings it generates, starting from
+//! [`ClockModel::default`], so [`Builder::delay`](crate::Builder::delay) can work out how many
+//! cycles a delay takes.
+//!
+//! ```
+//! use mpsse::Builder;
+//!
+//! let builder = Builder::new().set_divisor(5).then();
+//!
+//! assert_eq!(builder.clock_model().frequency(), 1_000_000.0);
+//! ```
+use std::time::Duration;
+
+use crate::command::Command;
+
+/// The clock settings of an MPSSE.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ClockModel {
+    /// Whether the 60MHz base clock is divided by 5.
+    pub divide_by_5: bool,
+    pub divisor: u16,
+    pub three_phase: bool,
+    /// How long the MPSSE takes to run a command that doesn't clock any data, such as Set Data
+    /// Bits.
+    pub command_time: Duration,
+}
+
+impl Default for ClockModel {
+    /// The settings after the MPSSE is reset, with a command time of 50ns.
+    ///
+    /// The command time is how long three command bytes take to pass through the engine at
+    /// 60MHz. Commands take at least this long, so delays made from them are never short.
+    fn default() -> Self {
+        ClockModel {
+            divide_by_5: true,
+            divisor: 0,
+            three_phase: false,
+            command_time: Duration::from_nanos(50),
+        }
+    }
+}
+
+impl ClockModel {
+    /// The base clock in hz, before the divisor.
+    pub fn base_frequency(&self) -> f64 {
+        match self.divide_by_5 {
+            true => 12_000_000.0,
+            false => 60_000_000.0,
+        }
+    }
+
+    /// The clock frequency in hz.
+    pub fn frequency(&self) -> f64 {
+        self.base_frequency() / (2.0 * (1.0 + self.divisor as f64))
+    }
+
+    /// How long it takes to clock one bit.
+    pub fn bit_time(&self) -> Duration {
+        self.clock_time(1)
+    }
+
+    /// How long it takes to clock `cycles` bits, to the nearest nanosecond.
+    pub fn clock_time(&self, cycles: u64) -> Duration {
+        let (numerator, denominator) = self.bit_nanos();
+        let nanos = (cycles as u128 * numerator + denominator / 2) / denominator;
+
+        Duration::from_nanos(nanos as u64)
+    }
+
+    /// The fewest bits that take at least `duration` to clock.
+    pub(crate) fn cycles_for(&self, duration: Duration) -> u128 {
+        let (numerator, denominator) = self.bit_nanos();
+
+        (duration.as_nanos() * denominator).div_ceil(numerator)
+    }
+
+    /// The exact time to clock one bit in nanoseconds, as a numerator and denominator.
+    fn bit_nanos(&self) -> (u128, u128) {
+        let base = match self.divide_by_5 {
+            true => 12_000_000,
+            false => 60_000_000,
+        };
+        // Each bit takes two half periods, or three with three-phase clocking.
+        let half_periods = match self.three_phase {
+            true => 3,
+            false => 2,
+        };
+
+        (
+            1_000_000_000 * (1 + self.divisor as u128) * half_periods,
+            base,
+        )
+    }
+
+    /// Follow any change `command` makes to the clock settings.
+    pub fn apply(&mut self, command: &Command) {
+        match command {
+            Command::SetClockDivisor { divisor } => self.divisor = *divisor,
+            Command::SetClockDivideBy5 { enable } => self.divide_by_5 = *enable,
+            Command::SetThreePhaseClocking { enable } => self.three_phase = *enable,
+            _ => (),
+        }
+    }
+}
+
+/// The speed of the USB connection to the chip.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UsbSpeed {
+    /// 12Mbit/s, as on the FT2232D.
+    Full,
+    /// 480Mbit/s, as on the H-series chips.
+    High,
+}
+
+impl UsbSpeed {
+    /// The largest bulk packet in bytes.
+    pub fn packet_size(self) -> usize {
+        match self {
+            UsbSpeed::Full => 64,
+            UsbSpeed::High => 512,
+        }
+    }
+}
+
+/// The cost of running a command list, see
+/// [`CommandList::estimate`](crate::command::CommandList::estimate).
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Estimate {
+    /// Clock cycles, counting clocking that may stop early on GPIOL1 in full.
+    pub cycles: u64,
+    /// Time the MPSSE spends running the commands.
+    pub wire_time: Duration,
+    pub bytes_sent: usize,
+    pub bytes_received: usize,
+}
+
+impl Estimate {
+    /// The number of bulk packets needed to send the commands and receive the response.
+    ///
+    /// Each packet from the chip starts with two modem status bytes, which leave less room for
+    /// the response.
+    pub fn packets(&self, speed: UsbSpeed) -> usize {
+        let size = speed.packet_size();
+
+        self.bytes_sent.div_ceil(size) + self.bytes_received.div_ceil(size - 2)
+    }
+}
+
+#[cfg(test)]
+mod clock_tests {
+    use super::*;
+
+    #[test]
+    fn follows_clock_commands() {
+        let mut model = ClockModel::default();
+        model.apply(&Command::SetClockDivideBy5 { enable: false });
+        model.apply(&Command::SetClockDivisor { divisor: 29 });
+
+        assert_eq!(model.frequency(), 1_000_000.0);
+        assert_eq!(model.bit_time(), Duration::from_micros(1));
+
+        model.apply(&Command::SetThreePhaseClocking { enable: true });
+        assert_eq!(model.bit_time(), Duration::from_nanos(1500));
+    }
+}
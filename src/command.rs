@@ -1,5 +1,7 @@
+use crate::clock::{ClockModel, Estimate};
+
 /// Edge of the clock on which to action data.
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ClockEdge {
     /// Action the data on the rising edge of the clock (from Low to High).
     Rising,
@@ -8,18 +10,18 @@ pub enum ClockEdge {
 }
 
 /// The order of the bits in which to read/write the data.
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum BitDirection {
     /// Read/write the least significant bit first
     ///
-    /// ```no_run
+    /// ```text
     /// 0x02 = 0, 1, 0, 0, 0, 0, 0, 0
     ///        ^ first
     /// ```
     LsbFirst,
     /// Read/write the most significant bit first
     ///
-    /// ```no_run
+    /// ```text
     /// 0x02 = 0, 0, 0, 0, 0, 0, 1, 0
     ///        ^ first
     /// ```
@@ -49,30 +51,48 @@ impl Default for FullDataShiftOptions {
     }
 }
 
-impl Into<u8> for FullDataShiftOptions {
-    fn into(self) -> u8 {
+impl FullDataShiftOptions {
+    /// Options for shifting data in and out at once, writing on the given clock edge and reading
+    /// on the opposite one.
+    fn read_write(options: DataShiftOptions) -> Self {
+        FullDataShiftOptions {
+            write_clock_direction: options.clock_direction,
+            read_clock_direction: match options.clock_direction {
+                ClockEdge::Rising => ClockEdge::Falling,
+                ClockEdge::Falling => ClockEdge::Rising,
+            },
+            bit_direction: options.bit_direction,
+            write_tdi: true,
+            read_tdo: true,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<FullDataShiftOptions> for u8 {
+    fn from(options: FullDataShiftOptions) -> u8 {
         let mut byte = 0;
-        byte |= match self.write_clock_direction {
+        byte |= match options.write_clock_direction {
             ClockEdge::Rising => 0x00,
             ClockEdge::Falling => 0x01,
         };
-        byte |= match self.read_clock_direction {
+        byte |= match options.read_clock_direction {
             ClockEdge::Rising => 0x00,
             ClockEdge::Falling => 0x04,
         };
-        byte |= match self.bit_direction {
+        byte |= match options.bit_direction {
             BitDirection::MsbFirst => 0x00,
             BitDirection::LsbFirst => 0x08,
         };
-        byte |= match self.write_tdi {
+        byte |= match options.write_tdi {
             false => 0x00,
             true => 0x10,
         };
-        byte |= match self.read_tdo {
+        byte |= match options.read_tdo {
             false => 0x00,
             true => 0x20,
         };
-        byte |= match self.write_tms {
+        byte |= match options.write_tms {
             false => 0x00,
             true => 0x40,
         };
@@ -81,25 +101,25 @@ impl Into<u8> for FullDataShiftOptions {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct DataShiftOptions {
     pub clock_direction: ClockEdge,
     pub bit_direction: BitDirection,
 }
 
-impl Into<FullDataShiftOptions> for DataShiftOptions {
-    fn into(self) -> FullDataShiftOptions {
+impl From<DataShiftOptions> for FullDataShiftOptions {
+    fn from(options: DataShiftOptions) -> FullDataShiftOptions {
         FullDataShiftOptions {
-            read_clock_direction: self.clock_direction,
-            bit_direction: self.bit_direction,
+            read_clock_direction: options.clock_direction,
+            bit_direction: options.bit_direction,
             ..Default::default()
         }
     }
 }
 
-impl Into<u8> for DataShiftOptions {
-    fn into(self) -> u8 {
-        Into::<FullDataShiftOptions>::into(self).into()
+impl From<DataShiftOptions> for u8 {
+    fn from(options: DataShiftOptions) -> u8 {
+        FullDataShiftOptions::from(options).into()
     }
 }
 
@@ -119,9 +139,17 @@ pub enum PinValue {
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct PinValueArray([PinValue; 8]);
 
-impl Into<u8> for PinValueArray {
-    fn into(self) -> u8 {
-        self.0
+impl PinValueArray {
+    /// The value of pin `index` of the range, counting from 0.
+    pub fn get(&self, index: usize) -> PinValue {
+        self.0[index]
+    }
+}
+
+impl From<PinValueArray> for u8 {
+    fn from(array: PinValueArray) -> u8 {
+        array
+            .0
             .iter()
             .enumerate()
             .map(|(i, val)| match val {
@@ -136,10 +164,10 @@ impl From<u8> for PinValueArray {
     fn from(value: u8) -> Self {
         let mut result = [PinValue::Low; 8];
         let value = value.reverse_bits();
-        for i in 0..8 {
+        for (i, pin) in result.iter_mut().enumerate() {
             match (value << i & 0x80) == 0x80 {
-                true => result[i] = PinValue::High,
-                false => result[i] = PinValue::Low,
+                true => *pin = PinValue::High,
+                false => *pin = PinValue::Low,
             }
         }
         PinValueArray(result)
@@ -180,9 +208,10 @@ pub enum PinDirection {
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct PinDirectionArray([PinDirection; 8]);
 
-impl Into<u8> for PinDirectionArray {
-    fn into(self) -> u8 {
-        self.0
+impl From<PinDirectionArray> for u8 {
+    fn from(array: PinDirectionArray) -> u8 {
+        array
+            .0
             .iter()
             .enumerate()
             .map(|(i, val)| match val {
@@ -197,10 +226,10 @@ impl From<u8> for PinDirectionArray {
     fn from(value: u8) -> Self {
         let mut result = [PinDirection::Input; 8];
         let value = value.reverse_bits();
-        for i in 0..8 {
+        for (i, pin) in result.iter_mut().enumerate() {
             match (value << i & 0x80) == 0x80 {
-                true => result[i] = PinDirection::Output,
-                false => result[i] = PinDirection::Input,
+                true => *pin = PinDirection::Output,
+                false => *pin = PinDirection::Input,
             }
         }
         PinDirectionArray(result)
@@ -250,6 +279,15 @@ pub enum Command {
         options: DataShiftOptions,
         bytes: Vec<u8>,
     },
+    ReadWriteDataShiftBits {
+        options: DataShiftOptions,
+        bits: u8,
+        length: u8,
+    },
+    ReadWriteDataShiftBytes {
+        options: DataShiftOptions,
+        bytes: Vec<u8>,
+    },
     SetBits {
         range: PinRange,
         value: PinValueArray,
@@ -264,12 +302,101 @@ pub enum Command {
     SetClockDivisor {
         divisor: u16,
     },
+    SetClockDivideBy5 {
+        enable: bool,
+    },
+    /// Wait until GPIOL1 is at `value`, however long that takes.
     WaitForIo {
         value: PinValue,
     },
+    /// Clock `length` bytes without moving data, stopping early once GPIOL1 is at `value`.
+    /// `length` must not be 0.
+    ClockBytesUntilIo {
+        value: PinValue,
+        length: u16,
+    },
+    ClockBits {
+        length: u8,
+    },
+    ClockBytes {
+        length: u16,
+    },
+    SetThreePhaseClocking {
+        enable: bool,
+    },
+    SetAdaptiveClocking {
+        enable: bool,
+    },
+    SetDriveOnlyZero {
+        low: u8,
+        high: u8,
+    },
+    SendImmediate,
+    /// An opcode the MPSSE doesn't recognise, which it answers with 0xFA then the opcode.
+    BadCommand {
+        opcode: u8,
+    },
+    WriteTmsBits {
+        clock_direction: ClockEdge,
+        bits: u8,
+        length: u8,
+    },
+    ReadWriteTmsBits {
+        clock_direction: ClockEdge,
+        bits: u8,
+        length: u8,
+    },
 }
 
 impl Command {
+    /// The number of bytes the command takes to send.
+    pub fn encoded_length(&self) -> usize {
+        match self {
+            Self::ReadDataShiftBits { .. } | Self::ClockBits { .. } => 2,
+            Self::WriteDataShiftBytes { bytes, .. }
+            | Self::ReadWriteDataShiftBytes { bytes, .. } => 3 + bytes.len(),
+            Self::WriteDataShiftBits { .. }
+            | Self::ReadDataShiftBytes { .. }
+            | Self::ReadWriteDataShiftBits { .. }
+            | Self::SetBits { .. }
+            | Self::SetClockDivisor { .. }
+            | Self::ClockBytesUntilIo { .. }
+            | Self::ClockBytes { .. }
+            | Self::SetDriveOnlyZero { .. }
+            | Self::WriteTmsBits { .. }
+            | Self::ReadWriteTmsBits { .. } => 3,
+            Self::ReadBits { .. }
+            | Self::SetLoopback { .. }
+            | Self::SetClockDivideBy5 { .. }
+            | Self::WaitForIo { .. }
+            | Self::SetThreePhaseClocking { .. }
+            | Self::SetAdaptiveClocking { .. }
+            | Self::SendImmediate
+            | Self::BadCommand { .. } => 1,
+        }
+    }
+
+    /// The number of clock cycles the command runs for.
+    ///
+    /// Clock For n x 8 Bits Or Until GPIOL1 counts its full length, and Wait On I/O counts as no
+    /// cycles, as neither can know when the pin will change.
+    pub fn clock_cycles(&self) -> u64 {
+        match self {
+            Self::ReadDataShiftBits { length, .. }
+            | Self::WriteDataShiftBits { length, .. }
+            | Self::ReadWriteDataShiftBits { length, .. }
+            | Self::WriteTmsBits { length, .. }
+            | Self::ReadWriteTmsBits { length, .. }
+            | Self::ClockBits { length } => *length as u64,
+            Self::WriteDataShiftBytes { bytes, .. }
+            | Self::ReadWriteDataShiftBytes { bytes, .. } => 8 * bytes.len() as u64,
+            Self::ReadDataShiftBytes { length, .. }
+            | Self::ClockBytes { length }
+            | Self::ClockBytesUntilIo { length, .. } => 8 * *length as u64,
+            _ => 0,
+        }
+    }
+
     pub fn expected_response_length(&self) -> usize {
         match self {
             Self::ReadDataShiftBits {
@@ -286,6 +413,12 @@ impl Command {
                 bytes: _,
             } => 0,
             Self::ReadDataShiftBytes { options: _, length } => length.to_owned() as usize,
+            Self::ReadWriteDataShiftBits {
+                options: _,
+                bits: _,
+                length: _,
+            } => 1,
+            Self::ReadWriteDataShiftBytes { options: _, bytes } => bytes.len(),
             Self::SetBits {
                 range: _,
                 value: _,
@@ -294,15 +427,37 @@ impl Command {
             Self::ReadBits { range: _ } => 1,
             Self::SetLoopback { enable: _ } => 0,
             Self::SetClockDivisor { divisor: _ } => 0,
-            Self::WaitForIo { value: _ } => 1,
+            Self::SetClockDivideBy5 { enable: _ } => 0,
+            Self::WaitForIo { value: _ } => 0,
+            Self::ClockBytesUntilIo {
+                value: _,
+                length: _,
+            } => 0,
+            Self::ClockBits { length: _ } => 0,
+            Self::ClockBytes { length: _ } => 0,
+            Self::SetThreePhaseClocking { enable: _ } => 0,
+            Self::SetAdaptiveClocking { enable: _ } => 0,
+            Self::SetDriveOnlyZero { low: _, high: _ } => 0,
+            Self::SendImmediate => 0,
+            Self::BadCommand { opcode: _ } => 2,
+            Self::WriteTmsBits {
+                clock_direction: _,
+                bits: _,
+                length: _,
+            } => 0,
+            Self::ReadWriteTmsBits {
+                clock_direction: _,
+                bits: _,
+                length: _,
+            } => 1,
         }
     }
 }
 
-impl Into<Vec<u8>> for Command {
-    fn into(self) -> Vec<u8> {
-        match self {
-            Self::WriteDataShiftBits {
+impl From<Command> for Vec<u8> {
+    fn from(command: Command) -> Vec<u8> {
+        match command {
+            Command::WriteDataShiftBits {
                 options,
                 bits,
                 length,
@@ -317,7 +472,7 @@ impl Into<Vec<u8>> for Command {
 
                 vec![opcode | 0x02, length - 1, bits]
             }
-            Self::ReadDataShiftBits { options, length } => {
+            Command::ReadDataShiftBits { options, length } => {
                 let full_options = FullDataShiftOptions {
                     write_clock_direction: options.clock_direction,
                     bit_direction: options.bit_direction,
@@ -328,7 +483,7 @@ impl Into<Vec<u8>> for Command {
 
                 vec![opcode | 0x02, length - 1]
             }
-            Self::WriteDataShiftBytes { options, bytes } => {
+            Command::WriteDataShiftBytes { options, bytes } => {
                 let full_options = FullDataShiftOptions {
                     write_clock_direction: options.clock_direction,
                     bit_direction: options.bit_direction,
@@ -343,7 +498,7 @@ impl Into<Vec<u8>> for Command {
 
                 result
             }
-            Self::ReadDataShiftBytes { options, length } => {
+            Command::ReadDataShiftBytes { options, length } => {
                 let full_options = FullDataShiftOptions {
                     write_clock_direction: options.clock_direction,
                     bit_direction: options.bit_direction,
@@ -357,7 +512,25 @@ impl Into<Vec<u8>> for Command {
 
                 result
             }
-            Self::SetBits {
+            Command::ReadWriteDataShiftBits {
+                options,
+                bits,
+                length,
+            } => {
+                let opcode: u8 = FullDataShiftOptions::read_write(options).into();
+
+                vec![opcode | 0x02, length - 1, bits]
+            }
+            Command::ReadWriteDataShiftBytes { options, bytes } => {
+                let opcode: u8 = FullDataShiftOptions::read_write(options).into();
+
+                let mut result = vec![opcode];
+                result.extend_from_slice(&((bytes.len() - 1) as u16).to_le_bytes());
+                result.extend(bytes);
+
+                result
+            }
+            Command::SetBits {
                 range,
                 value,
                 direction,
@@ -369,7 +542,7 @@ impl Into<Vec<u8>> for Command {
 
                 vec![opcode, value.into(), direction.into()]
             }
-            Self::ReadBits { range } => {
+            Command::ReadBits { range } => {
                 let opcode = match range {
                     PinRange::Low => 0x81,
                     PinRange::High => 0x83,
@@ -377,7 +550,7 @@ impl Into<Vec<u8>> for Command {
 
                 vec![opcode]
             }
-            Self::SetLoopback { enable } => {
+            Command::SetLoopback { enable } => {
                 let opcode = match enable {
                     true => 0x84,
                     false => 0x85,
@@ -390,10 +563,83 @@ impl Into<Vec<u8>> for Command {
                 result.extend_from_slice(&divisor.to_le_bytes());
                 result
             }
-            Self::WaitForIo { value } => match value {
+            Command::SetClockDivideBy5 { enable } => {
+                let opcode = match enable {
+                    true => 0x8B,
+                    false => 0x8A,
+                };
+
+                vec![opcode]
+            }
+            Command::WaitForIo { value } => match value {
                 PinValue::High => vec![0x88],
                 PinValue::Low => vec![0x89],
             },
+            Command::ClockBytesUntilIo { value, length } => {
+                let mut result = match value {
+                    PinValue::High => vec![0x9C],
+                    PinValue::Low => vec![0x9D],
+                };
+                result.extend_from_slice(&(length - 1).to_le_bytes());
+                result
+            }
+            Command::ClockBits { length } => vec![0x8E, length - 1],
+            Command::ClockBytes { length } => {
+                let mut result = vec![0x8F];
+                result.extend_from_slice(&(length - 1).to_le_bytes());
+                result
+            }
+            Command::SetThreePhaseClocking { enable } => {
+                let opcode = match enable {
+                    true => 0x8C,
+                    false => 0x8D,
+                };
+
+                vec![opcode]
+            }
+            Command::SetAdaptiveClocking { enable } => {
+                let opcode = match enable {
+                    true => 0x96,
+                    false => 0x97,
+                };
+
+                vec![opcode]
+            }
+            Command::SetDriveOnlyZero { low, high } => vec![0x9E, low, high],
+            Command::SendImmediate => vec![0x87],
+            Command::BadCommand { opcode } => vec![opcode],
+            Command::WriteTmsBits {
+                clock_direction,
+                bits,
+                length,
+            } => {
+                let full_options = FullDataShiftOptions {
+                    write_clock_direction: clock_direction,
+                    bit_direction: BitDirection::LsbFirst,
+                    write_tms: true,
+                    ..Default::default()
+                };
+                let opcode: u8 = full_options.into();
+
+                vec![opcode | 0x02, length - 1, bits]
+            }
+            Command::ReadWriteTmsBits {
+                clock_direction,
+                bits,
+                length,
+            } => {
+                let full_options = FullDataShiftOptions {
+                    write_tdi: false,
+                    write_tms: true,
+                    ..FullDataShiftOptions::read_write(DataShiftOptions {
+                        clock_direction,
+                        bit_direction: BitDirection::LsbFirst,
+                    })
+                };
+                let opcode: u8 = full_options.into();
+
+                vec![opcode | 0x02, length - 1, bits]
+            }
         }
     }
 }
@@ -428,17 +674,122 @@ impl IntoIterator for CommandList {
     }
 }
 
-impl Into<Vec<u8>> for CommandList {
-    fn into(self) -> Vec<u8> {
-        self.into_iter().collect()
+impl From<CommandList> for Vec<u8> {
+    fn from(list: CommandList) -> Vec<u8> {
+        list.into_iter().collect()
     }
 }
 
 impl CommandList {
-    pub fn expected_response_length(self) -> usize {
+    pub fn expected_response_length(&self) -> usize {
         self.0
             .iter()
             .map(|cmd| cmd.expected_response_length())
-            .fold(0, |acc, cur| acc + cur)
+            .sum()
+    }
+
+    /// Estimate how long the commands take to run and how much USB traffic they need, starting
+    /// from the clock settings in `clock`.
+    ///
+    /// Each command takes the model's command time, plus its clock cycles at the clock settings
+    /// in force when it runs. USB transfer time isn't included.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use mpsse::clock::{ClockModel, UsbSpeed};
+    /// use mpsse::Builder;
+    ///
+    /// let commands = Builder::new()
+    ///     .set_divisor(5)
+    ///     .then()
+    ///     .read_data(1000)
+    ///     .then()
+    ///     .into_command_list();
+    /// let estimate = commands.estimate(&ClockModel::default());
+    ///
+    /// assert_eq!(estimate.cycles, 8000);
+    /// assert_eq!(estimate.wire_time, Duration::from_micros(8000) + Duration::from_nanos(100));
+    /// assert_eq!((estimate.bytes_sent, estimate.bytes_received), (6, 1000));
+    /// assert_eq!(estimate.packets(UsbSpeed::High), 1 + 2);
+    /// assert_eq!(estimate.packets(UsbSpeed::Full), 1 + 17);
+    /// ```
+    pub fn estimate(&self, clock: &ClockModel) -> Estimate {
+        let mut clock = *clock;
+        let mut estimate = Estimate::default();
+
+        for command in self.0.iter() {
+            let cycles = command.clock_cycles();
+            estimate.cycles += cycles;
+            estimate.wire_time += clock.command_time;
+            estimate.wire_time += clock.clock_time(cycles);
+            estimate.bytes_sent += command.encoded_length();
+            estimate.bytes_received += command.expected_response_length();
+            clock.apply(command);
+        }
+
+        estimate
+    }
+}
+
+#[cfg(test)]
+mod encoded_length_tests {
+    use super::*;
+
+    #[test]
+    fn matches_encoding() {
+        let options = DataShiftOptions {
+            clock_direction: ClockEdge::Rising,
+            bit_direction: BitDirection::MsbFirst,
+        };
+        let commands = vec![
+            Command::ReadDataShiftBits { options, length: 3 },
+            Command::WriteDataShiftBits {
+                options,
+                bits: 0x5,
+                length: 3,
+            },
+            Command::WriteDataShiftBytes {
+                options,
+                bytes: vec![1, 2, 3],
+            },
+            Command::ReadDataShiftBytes { options, length: 4 },
+            Command::ReadWriteDataShiftBytes {
+                options,
+                bytes: vec![1],
+            },
+            Command::SetBits {
+                range: PinRange::High,
+                value: 0.into(),
+                direction: 0.into(),
+            },
+            Command::ReadBits {
+                range: PinRange::Low,
+            },
+            Command::SetClockDivisor { divisor: 5 },
+            Command::SetClockDivideBy5 { enable: false },
+            Command::WaitForIo {
+                value: PinValue::High,
+            },
+            Command::ClockBytesUntilIo {
+                value: PinValue::Low,
+                length: 2,
+            },
+            Command::ClockBits { length: 4 },
+            Command::ClockBytes { length: 2 },
+            Command::SetDriveOnlyZero { low: 0, high: 0 },
+            Command::SendImmediate,
+            Command::BadCommand { opcode: 0xAA },
+            Command::ReadWriteTmsBits {
+                clock_direction: ClockEdge::Falling,
+                bits: 1,
+                length: 1,
+            },
+        ];
+
+        for command in commands {
+            let length = command.encoded_length();
+            let bytes: Vec<u8> = command.into();
+            assert_eq!(length, bytes.len(), "{:02X?}", bytes);
+        }
     }
 }
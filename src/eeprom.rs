@@ -0,0 +1,298 @@
+//! 24Cxx serial EEPROMs, over the [I2C layer](crate::i2c).
+//!
+//! Parts up to 24C16 take a 1-byte memory address, with the higher address bits sent as block
+//! select bits in the device address. Larger parts take a 2-byte address. Writes are split at
+//! page boundaries, and each page is followed by acknowledge polling until the write finishes.
+//!
+//! ```no_run
+//! use mpsse::eeprom::{Eeprom, Error, Part};
+//! use mpsse::i2c::I2c;
+//! use mpsse::transport::Transport;
+//!
+//! // Store calibration data at the start of a 24C02.
+//! fn store<T: Transport>(i2c: I2c<T>, calibration: &[u8]) -> Result<(), Error<T::Error>> {
+//!     let mut eeprom = Eeprom::new(i2c, Part::C02, 0x50);
+//!     eeprom.write(0, calibration)?;
+//!     assert_eq!(eeprom.read(0, calibration.len())?, calibration);
+//!     Ok(())
+//! }
+//! ```
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::i2c::{self, I2c};
+use crate::transport::Transport;
+
+/// Time allowed for a page write to finish, which parts specify as at most 5 or 10ms.
+pub const WRITE_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// A 24Cxx part.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Part {
+    /// 24C01: 128 bytes.
+    C01,
+    /// 24C02: 256 bytes.
+    C02,
+    /// 24C04: 512 bytes.
+    C04,
+    /// 24C08: 1 KiB.
+    C08,
+    /// 24C16: 2 KiB.
+    C16,
+    /// 24C32: 4 KiB.
+    C32,
+    /// 24C64: 8 KiB.
+    C64,
+    /// 24C128: 16 KiB.
+    C128,
+    /// 24C256: 32 KiB.
+    C256,
+    /// 24C512: 64 KiB.
+    C512,
+}
+
+impl Part {
+    /// The size in bytes.
+    pub fn size(self) -> u32 {
+        match self {
+            Part::C01 => 128,
+            Part::C02 => 256,
+            Part::C04 => 512,
+            Part::C08 => 1024,
+            Part::C16 => 2048,
+            Part::C32 => 4096,
+            Part::C64 => 8192,
+            Part::C128 => 16384,
+            Part::C256 => 32768,
+            Part::C512 => 65536,
+        }
+    }
+
+    /// The largest number of bytes written at once. Writes wrap around within a page.
+    pub fn page_size(self) -> u32 {
+        match self {
+            Part::C01 | Part::C02 => 8,
+            Part::C04 | Part::C08 | Part::C16 => 16,
+            Part::C32 | Part::C64 => 32,
+            Part::C128 | Part::C256 => 64,
+            Part::C512 => 128,
+        }
+    }
+
+    /// The number of memory address bytes sent after the device address.
+    pub fn address_bytes(self) -> usize {
+        match self.size() > 2048 {
+            true => 2,
+            false => 1,
+        }
+    }
+}
+
+/// Error returned by the [`Eeprom`] driver.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The I2C driver failed.
+    I2c(i2c::Error<E>),
+    /// The EEPROM did not finish a write in time.
+    Busy,
+    /// An access went past the end of the EEPROM.
+    OutOfRange(u32),
+}
+
+impl<E> From<i2c::Error<E>> for Error<E> {
+    fn from(err: i2c::Error<E>) -> Self {
+        Error::I2c(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::I2c(err) => write!(f, "{}", err),
+            Error::Busy => write!(f, "the EEPROM did not finish writing"),
+            Error::OutOfRange(address) => write!(f, "address {:#x} is past the end", address),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+/// A 24Cxx EEPROM.
+#[derive(Debug)]
+pub struct Eeprom<T> {
+    i2c: I2c<T>,
+    part: Part,
+    address: u8,
+    write_timeout: Duration,
+}
+
+impl<T: Transport> Eeprom<T> {
+    /// Use the EEPROM at 7-bit device `address`, usually 0x50 plus its address pins.
+    ///
+    /// For parts that use block select bits, those bits of `address` are ignored.
+    pub fn new(i2c: I2c<T>, part: Part, address: u8) -> Self {
+        Eeprom {
+            i2c,
+            part,
+            address,
+            write_timeout: WRITE_TIMEOUT,
+        }
+    }
+
+    /// Set how long to poll for a write to finish.
+    pub fn with_write_timeout(self, write_timeout: Duration) -> Self {
+        Eeprom {
+            write_timeout,
+            ..self
+        }
+    }
+
+    /// Get the underlying I2C driver.
+    pub fn i2c(&mut self) -> &mut I2c<T> {
+        &mut self.i2c
+    }
+
+    /// Get back the underlying I2C driver.
+    pub fn into_inner(self) -> I2c<T> {
+        self.i2c
+    }
+
+    /// The part this driver was created for.
+    pub fn part(&self) -> Part {
+        self.part
+    }
+
+    /// The device address and memory address bytes for `address`.
+    fn addresses(&self, address: u32) -> (u8, Vec<u8>) {
+        match self.part.address_bytes() {
+            2 => (self.address, (address as u16).to_be_bytes().to_vec()),
+            _ => {
+                let blocks = (self.part.size().max(256) / 256 - 1) as u8;
+                let device = self.address & !blocks | (address >> 8) as u8 & blocks;
+                (device, vec![address as u8])
+            }
+        }
+    }
+
+    fn check_range(&self, address: u32, length: usize) -> Result<(), Error<T::Error>> {
+        match address as u64 + length as u64 > self.part.size() as u64 {
+            true => Err(Error::OutOfRange(address)),
+            false => Ok(()),
+        }
+    }
+
+    /// Read `length` bytes starting at `address`.
+    pub fn read(&mut self, address: u32, length: usize) -> Result<Vec<u8>, Error<T::Error>> {
+        self.check_range(address, length)?;
+
+        // Parts with block select bits may wrap around within a block, so each block is read
+        // with its own device address.
+        let block = match self.part.address_bytes() {
+            1 => 256,
+            _ => self.part.size(),
+        };
+        let mut data = Vec::with_capacity(length);
+        while data.len() < length {
+            let start = address + data.len() as u32;
+            let chunk = ((block - start % block) as usize).min(length - data.len());
+            let (device, memory) = self.addresses(start);
+            data.extend(self.i2c.write_read(device, &memory, chunk)?);
+        }
+
+        Ok(data)
+    }
+
+    /// Write `data` starting at `address`, one page at a time.
+    pub fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error<T::Error>> {
+        self.check_range(address, data.len())?;
+
+        let page_size = self.part.page_size();
+        let mut offset = 0;
+        while offset < data.len() {
+            let start = address + offset as u32;
+            let chunk = ((page_size - start % page_size) as usize).min(data.len() - offset);
+            let (device, mut bytes) = self.addresses(start);
+            bytes.extend_from_slice(&data[offset..offset + chunk]);
+
+            self.i2c.write(device, &bytes)?;
+            self.wait_ready(device)?;
+            offset += chunk;
+        }
+
+        Ok(())
+    }
+
+    /// Poll the device address until the EEPROM acknowledges it, which it does once the last
+    /// write has finished.
+    fn wait_ready(&mut self, device: u8) -> Result<(), Error<T::Error>> {
+        let start = Instant::now();
+        loop {
+            match self.i2c.write(device, &[]) {
+                Ok(()) => return Ok(()),
+                Err(i2c::Error::Nack { index: 0 }) if start.elapsed() < self.write_timeout => (),
+                Err(i2c::Error::Nack { index: 0 }) => return Err(Error::Busy),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod eeprom_tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    /// The bytes written by each write of a single byte, as seen by the mock.
+    fn written_bytes(written: &[u8]) -> Vec<u8> {
+        written
+            .windows(4)
+            .filter(|command| command[..3] == [0x11, 0x00, 0x00])
+            .map(|command| command[3])
+            .collect()
+    }
+
+    #[test]
+    fn writes_split_at_pages() {
+        let mut responses = vec![0x00; 4];
+        // The first acknowledge poll finds the EEPROM busy.
+        responses.extend_from_slice(&[0x01, 0x00]);
+        responses.extend_from_slice(&[0x00; 10]);
+        responses.push(0x00);
+
+        let i2c = I2c::new(MockTransport::with_responses(&responses));
+        let mut eeprom = Eeprom::new(i2c, Part::C02, 0x50);
+        eeprom
+            .write(0x06, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10])
+            .unwrap();
+
+        assert_eq!(
+            written_bytes(&eeprom.i2c().transport().written),
+            vec![0xA0, 0x06, 1, 2, 0xA0, 0xA0, 0xA0, 0x08, 3, 4, 5, 6, 7, 8, 9, 10, 0xA0]
+        );
+    }
+
+    #[test]
+    fn reads_use_block_select() {
+        let mut responses = vec![0x00; 3];
+        responses.extend_from_slice(&[0xAA, 0xBB]);
+        responses.extend_from_slice(&[0x00; 3]);
+        responses.push(0xCC);
+
+        let i2c = I2c::new(MockTransport::with_responses(&responses));
+        let mut eeprom = Eeprom::new(i2c, Part::C16, 0x50);
+
+        assert_eq!(eeprom.read(0x2FE, 3).unwrap(), vec![0xAA, 0xBB, 0xCC]);
+        assert_eq!(
+            written_bytes(&eeprom.i2c().transport().written),
+            vec![0xA4, 0xFE, 0xA5, 0xA6, 0x00, 0xA7]
+        );
+    }
+
+    #[test]
+    fn large_parts_use_two_address_bytes() {
+        let i2c = I2c::new(MockTransport::with_responses(&[]));
+        let eeprom = Eeprom::new(i2c, Part::C512, 0x51);
+
+        assert_eq!(eeprom.addresses(0x1234), (0x51, vec![0x12, 0x34]));
+    }
+}
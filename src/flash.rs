@@ -0,0 +1,835 @@
+//! SPI NOR flash memory, over the [SPI](crate::spi) layer.
+//!
+//! The flash's size, erase types and addressing mode are read from its Serial Flash
+//! Discoverable Parameters (SFDP, JESD216). Parts without SFDP can be described with a
+//! [`Geometry`] instead.
+//!
+//! ```no_run
+//! use mpsse::flash::{Error, Flash};
+//! use mpsse::spi::Spi;
+//! use mpsse::transport::Transport;
+//!
+//! // Replace the first sector of the flash.
+//! fn update<T: Transport>(spi: Spi<T>, data: &[u8]) -> Result<(), Error<T::Error>> {
+//!     let mut flash = Flash::probe(spi)?;
+//!     flash.erase_sector(0)?;
+//!     flash.program(0, data)?;
+//!     assert_eq!(flash.fast_read(0, data.len())?, data);
+//!     Ok(())
+//! }
+//! ```
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::image::Image;
+use crate::spi::{self, Spi};
+use crate::transport::Transport;
+
+/// Read the JEDEC manufacturer and device ID.
+pub const RDID: u8 = 0x9F;
+/// Read SFDP.
+pub const RDSFDP: u8 = 0x5A;
+/// Read data.
+pub const READ: u8 = 0x03;
+/// Read data with a dummy byte, at higher clock rates.
+pub const FAST_READ: u8 = 0x0B;
+/// Program up to a page.
+pub const PAGE_PROGRAM: u8 = 0x02;
+/// Set the write enable latch.
+pub const WRITE_ENABLE: u8 = 0x06;
+/// Read the status register.
+pub const READ_STATUS: u8 = 0x05;
+/// Erase the whole chip.
+pub const CHIP_ERASE: u8 = 0xC7;
+/// Enter 4-byte address mode.
+pub const ENTER_4_BYTE: u8 = 0xB7;
+
+/// Status register bit: a write, program or erase is in progress.
+const WIP: u8 = 0x01;
+
+const SFDP_SIGNATURE: &[u8] = b"SFDP";
+const BASIC_PARAMETERS: u16 = 0xFF00;
+
+/// Time allowed for a page program.
+pub const PROGRAM_TIMEOUT: Duration = Duration::from_millis(100);
+/// Time allowed for a sector or block erase.
+pub const ERASE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time allowed for a chip erase.
+pub const CHIP_ERASE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How long to wait between status register reads.
+const POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+/// How much data is read back at once while verifying.
+const VERIFY_CHUNK: usize = 0x10000;
+
+/// Error returned by the [`Flash`] driver.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The SPI driver failed.
+    Spi(spi::Error<E>),
+    /// The flash stayed busy for longer than its timeout.
+    Busy,
+    /// The flash has no valid SFDP basic parameter table.
+    Sfdp,
+    /// An erase address was not aligned to the erase size.
+    Alignment(u32),
+    /// An access went past the end of the flash.
+    OutOfRange(u32),
+}
+
+impl<E> From<spi::Error<E>> for Error<E> {
+    fn from(err: spi::Error<E>) -> Self {
+        Error::Spi(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Spi(err) => write!(f, "{}", err),
+            Error::Busy => write!(f, "the flash stayed busy"),
+            Error::Sfdp => write!(f, "the flash has no valid SFDP parameters"),
+            Error::Alignment(address) => write!(f, "address {:#x} is not aligned", address),
+            Error::OutOfRange(address) => write!(f, "address {:#x} is past the end", address),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+/// The JEDEC ID returned by [`RDID`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct JedecId {
+    pub manufacturer: u8,
+    pub memory_type: u8,
+    pub capacity: u8,
+}
+
+/// Which address lengths the flash accepts.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Addressing {
+    ThreeByte,
+    /// 3-byte by default, with 4-byte addressing after [`ENTER_4_BYTE`].
+    ThreeOrFourByte,
+    FourByte,
+}
+
+/// An erase instruction and the size it erases.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EraseType {
+    pub size: u32,
+    pub opcode: u8,
+}
+
+/// The layout of a flash.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Geometry {
+    /// The size in bytes.
+    pub size: u32,
+    pub page_size: u32,
+    pub addressing: Addressing,
+    /// The supported erase types, smallest first.
+    pub erase_types: Vec<EraseType>,
+}
+
+impl Geometry {
+    /// Decode the SFDP basic flash parameter table.
+    ///
+    /// Returns `None` if the table is too short or describes a flash larger than 4 GiB.
+    pub fn from_basic_parameters(table: &[u8]) -> Option<Self> {
+        let dwords: Vec<u32> = table
+            .chunks_exact(4)
+            .map(|dword| u32::from_le_bytes([dword[0], dword[1], dword[2], dword[3]]))
+            .collect();
+        if dwords.len() < 9 {
+            return None;
+        }
+
+        let addressing = match dwords[0] >> 17 & 0x3 {
+            0 => Addressing::ThreeByte,
+            1 => Addressing::ThreeOrFourByte,
+            2 => Addressing::FourByte,
+            _ => return None,
+        };
+
+        let bits = match dwords[1] & 0x8000_0000 {
+            0 => dwords[1] as u64 + 1,
+            _ => 1u64.checked_shl(dwords[1] & 0x7FFF_FFFF)?,
+        };
+        let size = u32::try_from(bits / 8).ok()?;
+
+        let mut erase_types: Vec<EraseType> = [dwords[7], dwords[8]]
+            .iter()
+            .flat_map(|dword| [*dword as u16, (*dword >> 16) as u16])
+            .filter(|erase| erase & 0xFF != 0)
+            .map(|erase| EraseType {
+                size: 1 << (erase & 0x1F),
+                opcode: (erase >> 8) as u8,
+            })
+            .collect();
+        erase_types.sort_by_key(|erase| erase.size);
+
+        // The page size was added in JESD216A; older tables assume 256 bytes.
+        let page_size = match dwords.get(10) {
+            Some(dword) => 1 << (dword >> 4 & 0xF),
+            None => 256,
+        };
+
+        Some(Geometry {
+            size,
+            page_size,
+            addressing,
+            erase_types,
+        })
+    }
+}
+
+/// Find the basic flash parameter table in an SFDP header and its parameter headers, returning
+/// its address and length in bytes.
+pub fn basic_parameters_location(header: &[u8]) -> Option<(u32, usize)> {
+    if header.len() < 8 || &header[..4] != SFDP_SIGNATURE {
+        return None;
+    }
+
+    let headers = header[6] as usize + 1;
+    header[8..]
+        .chunks_exact(8)
+        .take(headers)
+        .find(|parameter| u16::from_le_bytes([parameter[0], parameter[7]]) == BASIC_PARAMETERS)
+        .map(|parameter| {
+            let pointer = u32::from_le_bytes([parameter[4], parameter[5], parameter[6], 0]);
+            (pointer, parameter[3] as usize * 4)
+        })
+}
+
+/// A stage of [`Flash::program_image`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Stage {
+    /// Reading the sectors the image touches, to work out what needs erasing.
+    Read,
+    Erase,
+    Program,
+    /// Reading the image back.
+    Verify,
+}
+
+/// How far [`Flash::program_image`] has got, counted in bytes for the current stage.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Progress {
+    pub stage: Stage,
+    pub done: usize,
+    pub total: usize,
+}
+
+/// A run of bytes that read back differently from the image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub address: u32,
+    pub expected: Vec<u8>,
+    pub actual: Vec<u8>,
+}
+
+/// What [`Flash::program_image`] did.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// The erases done, as address and size.
+    pub erased: Vec<(u32, u32)>,
+    /// The number of pages programmed.
+    pub programmed: usize,
+    /// The number of pages skipped because they already held the right data.
+    pub skipped: usize,
+    /// Differences found when reading the image back.
+    pub differences: Vec<Difference>,
+}
+
+impl Report {
+    /// Whether the image read back correctly.
+    pub fn is_verified(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+/// A sector touched by an image, with its current and wanted contents.
+struct SectorPlan {
+    address: u32,
+    current: Vec<u8>,
+    target: Vec<u8>,
+}
+
+impl SectorPlan {
+    /// Whether the sector must be erased: programming can only clear bits.
+    fn needs_erase(&self) -> bool {
+        self.target
+            .iter()
+            .zip(&self.current)
+            .any(|(target, current)| target & !current != 0)
+    }
+}
+
+/// Choose erases covering exactly `sectors`, using the largest erase type that fits each
+/// aligned run. `erase_types` must be sorted smallest first, with `sectors` aligned to the
+/// first.
+fn plan_erases(erase_types: &[EraseType], sectors: &BTreeSet<u32>) -> Vec<(u32, EraseType)> {
+    let sector_size = erase_types[0].size;
+    let mut erases = Vec::new();
+    let mut covered_to = 0u64;
+
+    for address in sectors.iter() {
+        if (*address as u64) < covered_to {
+            continue;
+        }
+
+        let erase = erase_types
+            .iter()
+            .rev()
+            .find(|erase| {
+                address.is_multiple_of(erase.size)
+                    && (0..erase.size / sector_size)
+                        .all(|i| sectors.contains(&(address + i * sector_size)))
+            })
+            .unwrap_or(&erase_types[0]);
+        erases.push((*address, *erase));
+        covered_to = *address as u64 + erase.size as u64;
+    }
+
+    erases
+}
+
+/// A SPI NOR flash.
+#[derive(Debug)]
+pub struct Flash<T> {
+    spi: Spi<T>,
+    geometry: Geometry,
+}
+
+impl<T: Transport> Flash<T> {
+    /// Use a flash with a known layout, switching to 4-byte addressing if it needs it.
+    pub fn new(spi: Spi<T>, geometry: Geometry) -> Result<Self, Error<T::Error>> {
+        let mut flash = Flash { spi, geometry };
+
+        if flash.geometry.addressing == Addressing::ThreeOrFourByte && flash.four_byte() {
+            flash.spi.write(&[ENTER_4_BYTE])?;
+        }
+
+        Ok(flash)
+    }
+
+    /// Read the flash's layout from SFDP.
+    pub fn probe(mut spi: Spi<T>) -> Result<Self, Error<T::Error>> {
+        spi.setup()?;
+
+        // The header and the first few parameter headers.
+        let header = read_sfdp(&mut spi, 0, 64)?;
+        let (pointer, length) = basic_parameters_location(&header).ok_or(Error::Sfdp)?;
+        let table = read_sfdp(&mut spi, pointer, length)?;
+        let geometry = Geometry::from_basic_parameters(&table).ok_or(Error::Sfdp)?;
+
+        Flash::new(spi, geometry)
+    }
+
+    /// Get the underlying SPI driver.
+    pub fn spi(&mut self) -> &mut Spi<T> {
+        &mut self.spi
+    }
+
+    /// Get back the underlying SPI driver.
+    pub fn into_inner(self) -> Spi<T> {
+        self.spi
+    }
+
+    /// The layout of the flash.
+    pub fn geometry(&self) -> &Geometry {
+        &self.geometry
+    }
+
+    /// Read the JEDEC ID.
+    pub fn jedec_id(&mut self) -> Result<JedecId, Error<T::Error>> {
+        let id = self.spi.write_read(&[RDID], 3)?;
+
+        Ok(JedecId {
+            manufacturer: id[0],
+            memory_type: id[1],
+            capacity: id[2],
+        })
+    }
+
+    /// Read from the SFDP address space.
+    pub fn read_sfdp(&mut self, address: u32, length: usize) -> Result<Vec<u8>, Error<T::Error>> {
+        read_sfdp(&mut self.spi, address, length)
+    }
+
+    /// Read the status register.
+    pub fn read_status(&mut self) -> Result<u8, Error<T::Error>> {
+        Ok(self.spi.write_read(&[READ_STATUS], 1)?[0])
+    }
+
+    /// Poll the status register until no write, program or erase is in progress.
+    pub fn wait_ready(&mut self, timeout: Duration) -> Result<(), Error<T::Error>> {
+        let start = Instant::now();
+        loop {
+            if self.read_status()? & WIP == 0 {
+                return Ok(());
+            }
+            if start.elapsed() > timeout {
+                return Err(Error::Busy);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Whether addresses are sent as 4 bytes.
+    fn four_byte(&self) -> bool {
+        match self.geometry.addressing {
+            Addressing::ThreeByte => false,
+            Addressing::ThreeOrFourByte => self.geometry.size > 1 << 24,
+            Addressing::FourByte => true,
+        }
+    }
+
+    /// An instruction followed by an address in the flash's addressing mode.
+    fn instruction(&self, opcode: u8, address: u32) -> Vec<u8> {
+        let address = address.to_be_bytes();
+        let mut instruction = vec![opcode];
+        match self.four_byte() {
+            true => instruction.extend_from_slice(&address),
+            false => instruction.extend_from_slice(&address[1..]),
+        }
+        instruction
+    }
+
+    fn check_range(&self, address: u32, length: usize) -> Result<(), Error<T::Error>> {
+        match address as u64 + length as u64 > self.geometry.size as u64 {
+            true => Err(Error::OutOfRange(address)),
+            false => Ok(()),
+        }
+    }
+
+    /// Read `length` bytes starting at `address`, using the standard read instruction.
+    pub fn read(&mut self, address: u32, length: usize) -> Result<Vec<u8>, Error<T::Error>> {
+        self.check_range(address, length)?;
+        let instruction = self.instruction(READ, address);
+        Ok(self.spi.write_read(&instruction, length)?)
+    }
+
+    /// Read `length` bytes starting at `address`, using the fast read instruction.
+    pub fn fast_read(&mut self, address: u32, length: usize) -> Result<Vec<u8>, Error<T::Error>> {
+        self.check_range(address, length)?;
+        let mut instruction = self.instruction(FAST_READ, address);
+        instruction.push(0x00);
+        Ok(self.spi.write_read(&instruction, length)?)
+    }
+
+    /// Program `data` starting at `address`, which must have been erased.
+    ///
+    /// The data is split so that no page program crosses a page boundary.
+    pub fn program(&mut self, address: u32, data: &[u8]) -> Result<(), Error<T::Error>> {
+        self.check_range(address, data.len())?;
+
+        let page_size = self.geometry.page_size as usize;
+        let mut offset = 0;
+        while offset < data.len() {
+            let page_address = address + offset as u32;
+            let length = (page_size - page_address as usize % page_size).min(data.len() - offset);
+
+            let mut instruction = self.instruction(PAGE_PROGRAM, page_address);
+            instruction.extend_from_slice(&data[offset..offset + length]);
+            self.write_enabled(&instruction, PROGRAM_TIMEOUT)?;
+
+            offset += length;
+        }
+
+        Ok(())
+    }
+
+    /// Set the write enable latch, send `instruction`, and wait for it to finish.
+    fn write_enabled(
+        &mut self,
+        instruction: &[u8],
+        timeout: Duration,
+    ) -> Result<(), Error<T::Error>> {
+        let builder = self
+            .spi
+            .builder()
+            .select()
+            .write(&[WRITE_ENABLE])
+            .deselect()
+            .select()
+            .write(instruction)
+            .deselect()
+            .then();
+        self.spi.execute(builder)?;
+
+        self.wait_ready(timeout)
+    }
+
+    /// Erase `erase.size` bytes at `address`, which must be aligned to it.
+    pub fn erase(&mut self, address: u32, erase: EraseType) -> Result<(), Error<T::Error>> {
+        if !address.is_multiple_of(erase.size) {
+            return Err(Error::Alignment(address));
+        }
+        self.check_range(address, erase.size as usize)?;
+
+        let instruction = self.instruction(erase.opcode, address);
+        self.write_enabled(&instruction, ERASE_TIMEOUT)
+    }
+
+    /// Erase the sector at `address`, using the smallest erase type.
+    pub fn erase_sector(&mut self, address: u32) -> Result<(), Error<T::Error>> {
+        let erase = *self.geometry.erase_types.first().ok_or(Error::Sfdp)?;
+        self.erase(address, erase)
+    }
+
+    /// Erase the block at `address`, using the largest erase type.
+    pub fn erase_block(&mut self, address: u32) -> Result<(), Error<T::Error>> {
+        let erase = *self.geometry.erase_types.last().ok_or(Error::Sfdp)?;
+        self.erase(address, erase)
+    }
+
+    /// Erase the whole chip.
+    pub fn erase_chip(&mut self) -> Result<(), Error<T::Error>> {
+        self.write_enabled(&[CHIP_ERASE], CHIP_ERASE_TIMEOUT)
+    }
+
+    /// Write `image` to the flash, preserving the rest of each sector it touches, then read it
+    /// back.
+    ///
+    /// Only the sectors whose data can't be reached by programming alone are erased, using the
+    /// largest erase types that fit. Pages that already hold the right data are skipped.
+    /// `progress` is called as each stage moves along.
+    ///
+    /// Differences found when reading back are listed in the report rather than returned as an
+    /// error; check [`Report::is_verified`].
+    pub fn program_image(
+        &mut self,
+        image: &Image,
+        mut progress: impl FnMut(Progress),
+    ) -> Result<Report, Error<T::Error>> {
+        let erase_types = self.geometry.erase_types.clone();
+        let sector_size = erase_types.first().ok_or(Error::Sfdp)?.size;
+        let page_size = self.geometry.page_size as usize;
+        let mut report = Report::default();
+
+        let mut sectors = BTreeSet::new();
+        for segment in image.segments() {
+            self.check_range(segment.address, segment.data.len())?;
+            let first = segment.address / sector_size;
+            let last = ((segment.end() - 1) / sector_size as u64) as u32;
+            sectors.extend((first..=last).map(|sector| sector * sector_size));
+        }
+
+        let total = sectors.len() * sector_size as usize;
+        let mut plans = Vec::new();
+        for address in sectors.iter() {
+            let current = self.fast_read(*address, sector_size as usize)?;
+            let mut target = current.clone();
+            image.overlay(*address, &mut target);
+            plans.push(SectorPlan {
+                address: *address,
+                current,
+                target,
+            });
+            progress(Progress {
+                stage: Stage::Read,
+                done: plans.len() * sector_size as usize,
+                total,
+            });
+        }
+
+        let erased: BTreeSet<u32> = plans
+            .iter()
+            .filter(|plan| plan.needs_erase())
+            .map(|plan| plan.address)
+            .collect();
+        let erases = plan_erases(&erase_types, &erased);
+        let total = erased.len() * sector_size as usize;
+        let mut done = 0;
+        for (address, erase) in erases {
+            self.erase(address, erase)?;
+            report.erased.push((address, erase.size));
+            done += erase.size as usize;
+            progress(Progress {
+                stage: Stage::Erase,
+                done,
+                total,
+            });
+        }
+
+        let mut pages = Vec::new();
+        for plan in plans.iter_mut() {
+            if erased.contains(&plan.address) {
+                plan.current.iter_mut().for_each(|byte| *byte = 0xFF);
+            }
+            let chunks = plan
+                .target
+                .chunks(page_size)
+                .zip(plan.current.chunks(page_size));
+            for (index, (target, current)) in chunks.enumerate() {
+                match target == current {
+                    true => report.skipped += 1,
+                    false => pages.push((plan.address + (index * page_size) as u32, target)),
+                }
+            }
+        }
+        let total = pages.len() * page_size;
+        for (index, (address, data)) in pages.iter().enumerate() {
+            self.program(*address, data)?;
+            report.programmed += 1;
+            progress(Progress {
+                stage: Stage::Program,
+                done: (index + 1) * page_size,
+                total,
+            });
+        }
+
+        let total = image.len();
+        let mut done = 0;
+        for segment in image.segments() {
+            for (index, expected) in segment.data.chunks(VERIFY_CHUNK).enumerate() {
+                let address = segment.address + (index * VERIFY_CHUNK) as u32;
+                let actual = self.fast_read(address, expected.len())?;
+                report
+                    .differences
+                    .extend(differences(address, expected, &actual));
+                done += expected.len();
+                progress(Progress {
+                    stage: Stage::Verify,
+                    done,
+                    total,
+                });
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Group the bytes where `actual` differs from `expected` into runs.
+fn differences(address: u32, expected: &[u8], actual: &[u8]) -> Vec<Difference> {
+    let mut differences: Vec<Difference> = Vec::new();
+    let mut previous = None;
+
+    for (offset, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+        if expected == actual {
+            continue;
+        }
+
+        match differences.last_mut() {
+            Some(difference) if previous == Some(offset - 1) => {
+                difference.expected.push(*expected);
+                difference.actual.push(*actual);
+            }
+            _ => differences.push(Difference {
+                address: address + offset as u32,
+                expected: vec![*expected],
+                actual: vec![*actual],
+            }),
+        }
+        previous = Some(offset);
+    }
+
+    differences
+}
+
+/// Read from the SFDP address space, which always uses 3-byte addresses and a dummy byte.
+fn read_sfdp<T: Transport>(
+    spi: &mut Spi<T>,
+    address: u32,
+    length: usize,
+) -> Result<Vec<u8>, Error<T::Error>> {
+    let address = address.to_be_bytes();
+    let instruction = [RDSFDP, address[1], address[2], address[3], 0x00];
+    Ok(spi.write_read(&instruction, length)?)
+}
+
+#[cfg(test)]
+mod flash_tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    /// The SFDP header and basic parameter table of a 16 MiB flash with 4 KiB, 32 KiB and
+    /// 64 KiB erases.
+    fn sfdp() -> Vec<u8> {
+        let mut sfdp = vec![0; 0x80];
+        sfdp[..16].copy_from_slice(&[
+            b'S', b'F', b'D', b'P', 0x06, 0x01, 0x00, 0xFF, 0x00, 0x06, 0x01, 0x10, 0x30, 0x00,
+            0x00, 0xFF,
+        ]);
+        let dwords: [u32; 11] = [
+            0xFFF1_20E5,
+            0x07FF_FFFF,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0x520F_200C,
+            0xD810,
+            0,
+            0x80,
+        ];
+        for (i, dword) in dwords.iter().enumerate() {
+            sfdp[0x30 + i * 4..0x34 + i * 4].copy_from_slice(&dword.to_le_bytes());
+        }
+        sfdp
+    }
+
+    #[test]
+    fn parses_sfdp() {
+        let sfdp = sfdp();
+        let (pointer, length) = basic_parameters_location(&sfdp).unwrap();
+        assert_eq!((pointer, length), (0x30, 64));
+
+        let geometry = Geometry::from_basic_parameters(&sfdp[0x30..0x70]).unwrap();
+        assert_eq!(
+            geometry,
+            Geometry {
+                size: 16 << 20,
+                page_size: 256,
+                addressing: Addressing::ThreeByte,
+                erase_types: vec![
+                    EraseType {
+                        size: 0x1000,
+                        opcode: 0x20
+                    },
+                    EraseType {
+                        size: 0x8000,
+                        opcode: 0x52
+                    },
+                    EraseType {
+                        size: 0x10000,
+                        opcode: 0xD8
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn program_splits_pages() {
+        let sfdp = sfdp();
+        let mut responses = sfdp[..64].to_vec();
+        responses.extend_from_slice(&sfdp[0x30..0x70]);
+        // One status read after each of the two page programs.
+        responses.extend_from_slice(&[0x00, 0x00]);
+
+        let mut flash = Flash::probe(Spi::new(MockTransport::with_responses(&responses))).unwrap();
+        flash.program(0x0000_01F0, &[0xA5; 0x20]).unwrap();
+
+        let written = &flash.spi().transport().written;
+        let programs: Vec<&[u8]> = written
+            .windows(7)
+            .filter(|command| command[0] == 0x11 && command[3] == PAGE_PROGRAM)
+            .collect();
+        assert_eq!(
+            programs,
+            vec![
+                &[0x11, 0x13, 0x00, 0x02, 0x00, 0x01, 0xF0][..],
+                &[0x11, 0x13, 0x00, 0x02, 0x00, 0x02, 0x00][..],
+            ]
+        );
+    }
+
+    #[test]
+    fn erases_aligned_blocks() {
+        let erase_types = [
+            EraseType {
+                size: 0x1000,
+                opcode: 0x20,
+            },
+            EraseType {
+                size: 0x10000,
+                opcode: 0xD8,
+            },
+        ];
+        let mut sectors: BTreeSet<u32> = (0x1F000..0x31000).step_by(0x1000).collect();
+        sectors.insert(0x40000);
+
+        let erases: Vec<(u32, u8)> = plan_erases(&erase_types, &sectors)
+            .iter()
+            .map(|(address, erase)| (*address, erase.opcode))
+            .collect();
+        assert_eq!(
+            erases,
+            vec![
+                (0x1F000, 0x20),
+                (0x20000, 0xD8),
+                (0x30000, 0x20),
+                (0x40000, 0x20)
+            ]
+        );
+    }
+
+    #[test]
+    fn program_image_reports_differences() {
+        let geometry = Geometry {
+            size: 1 << 20,
+            page_size: 256,
+            addressing: Addressing::ThreeByte,
+            erase_types: vec![EraseType {
+                size: 0x1000,
+                opcode: 0x20,
+            }],
+        };
+        let data: Vec<u8> = (1..=16).collect();
+        let image = Image::from_binary(0x1100, &data);
+
+        // The sector reads back as zeroes, so must be erased. Then every page of it differs
+        // from the erased state, and one byte fails to verify.
+        let mut responses = vec![0x00; 0x1000];
+        responses.push(0x00);
+        responses.extend_from_slice(&[0x00; 16]);
+        let mut readback = data.clone();
+        readback[3] = 0xFF;
+        responses.extend_from_slice(&readback);
+
+        let transport = MockTransport::with_responses(&responses);
+        let mut flash = Flash::new(Spi::new(transport), geometry).unwrap();
+        let mut stages = Vec::new();
+        let report = flash
+            .program_image(&image, |progress| {
+                if progress.done == progress.total {
+                    stages.push(progress.stage);
+                }
+            })
+            .unwrap();
+
+        assert_eq!(report.erased, vec![(0x1000, 0x1000)]);
+        assert_eq!((report.programmed, report.skipped), (16, 0));
+        assert_eq!(
+            report.differences,
+            vec![Difference {
+                address: 0x1103,
+                expected: vec![4],
+                actual: vec![0xFF],
+            }]
+        );
+        assert_eq!(
+            stages,
+            vec![Stage::Read, Stage::Erase, Stage::Program, Stage::Verify]
+        );
+    }
+
+    #[test]
+    fn erase_checks_alignment() {
+        let transport = MockTransport::with_responses(&[]);
+        let geometry = Geometry {
+            size: 1 << 20,
+            page_size: 256,
+            addressing: Addressing::ThreeByte,
+            erase_types: vec![EraseType {
+                size: 0x1000,
+                opcode: 0x20,
+            }],
+        };
+        let mut flash = Flash::new(Spi::new(transport), geometry).unwrap();
+
+        match flash.erase_sector(0x800) {
+            Err(Error::Alignment(0x800)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
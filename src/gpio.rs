@@ -0,0 +1,348 @@
+//! Individual GPIO pins, for the ADBUS and ACBUS pins the serial engine doesn't use.
+//!
+//! ADBUS0 to ADBUS3 belong to the serial engine, which leaves pins 4 to 7 (ADBUS4 to ADBUS7) and
+//! pins 8 to 15 (ACBUS0 to ACBUS7) free, numbered as for [`Builder::set_pin`]. A [`Gpio`] tracks
+//! the state of every pin, so each [`Pin`] handle can change its own pin without disturbing the
+//! others.
+//!
+//! With the `embedded-hal` feature, pins implement `OutputPin`, `StatefulOutputPin` and
+//! `InputPin`, so they can be handed to existing driver crates.
+//!
+//! ```no_run
+//! use mpsse::gpio::{Error, Gpio};
+//! use mpsse::transport::Transport;
+//!
+//! // Pulse a reset line on ADBUS4, then check an interrupt line on ACBUS1.
+//! fn reset<T: Transport>(transport: T) -> Result<bool, Error<T::Error>> {
+//!     let gpio = Gpio::new(transport);
+//!     let mut reset = gpio.pin(4);
+//!     let mut interrupt = gpio.pin(9);
+//!
+//!     reset.set_low()?;
+//!     reset.set_high()?;
+//!     interrupt.set_input()?;
+//!     interrupt.is_low()
+//! }
+//! ```
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::builder::{Builder, PinState};
+use crate::command::{PinDirection, PinRange, PinValue, PinValueArray};
+use crate::transport::{self, Transport};
+
+/// The lowest pin not used by the serial engine.
+pub const FIRST_PIN: u8 = 4;
+
+/// The highest pin number.
+pub const LAST_PIN: u8 = 15;
+
+/// Error returned by [`Gpio`] and its pins, which is the transport's.
+pub use crate::transport::Error;
+
+#[derive(Debug)]
+struct Inner<T> {
+    transport: T,
+    low: PinState,
+    high: PinState,
+}
+
+/// Shared access to the GPIO pins of an MPSSE.
+#[derive(Debug)]
+pub struct Gpio<T> {
+    inner: RefCell<Inner<T>>,
+    timeout: Duration,
+}
+
+impl<T: Transport> Gpio<T> {
+    /// Create a driver using the given transport, with the
+    /// [default timeout](transport::DEFAULT_TIMEOUT).
+    ///
+    /// Every pin is assumed to be an input driven low, as after the MPSSE is reset.
+    pub fn new(transport: T) -> Self {
+        Gpio {
+            inner: RefCell::new(Inner {
+                transport,
+                low: PinState::default(),
+                high: PinState::default(),
+            }),
+            timeout: transport::DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Set how long to wait for the device to respond.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Gpio { timeout, ..self }
+    }
+
+    /// Assume the pins of `range` are already in the given state, such as after setting up the
+    /// serial engine. Nothing is sent to the device.
+    pub fn with_pin_state(self, range: PinRange, state: PinState) -> Self {
+        {
+            let mut inner = self.inner.borrow_mut();
+            match range {
+                PinRange::Low => inner.low = state,
+                PinRange::High => inner.high = state,
+            }
+        }
+
+        self
+    }
+
+    /// The tracked state of the pins of `range`.
+    pub fn pin_state(&self, range: PinRange) -> PinState {
+        let inner = self.inner.borrow();
+        match range {
+            PinRange::Low => inner.low,
+            PinRange::High => inner.high,
+        }
+    }
+
+    /// Get back the underlying transport.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner().transport
+    }
+
+    /// Get a handle to pin `pin`.
+    ///
+    /// Panics if `pin` is used by the serial engine or is greater than [`LAST_PIN`].
+    pub fn pin(&self, pin: u8) -> Pin<'_, T> {
+        assert!(
+            (FIRST_PIN..=LAST_PIN).contains(&pin),
+            "pin {} is not a spare GPIO",
+            pin
+        );
+
+        Pin { gpio: self, pin }
+    }
+
+    /// Run the commands that `build` adds to a builder starting from the tracked pin state,
+    /// then keep the pin state it leaves behind.
+    fn execute<F>(&self, build: F) -> Result<Vec<u8>, Error<T::Error>>
+    where
+        F: FnOnce(Builder) -> Builder,
+    {
+        let mut inner = self.inner.borrow_mut();
+        let builder = build(
+            Builder::new()
+                .with_pin_state(PinRange::Low, inner.low)
+                .with_pin_state(PinRange::High, inner.high),
+        );
+        let low = builder.pin_state(PinRange::Low);
+        let high = builder.pin_state(PinRange::High);
+
+        let response = transport::flush(&mut inner.transport, builder, self.timeout)?;
+        inner.low = low;
+        inner.high = high;
+
+        Ok(response)
+    }
+
+    /// Read the current level of every pin in `range`.
+    pub fn read(&self, range: PinRange) -> Result<PinValueArray, Error<T::Error>> {
+        let response = self.execute(|builder| builder.read_pins(range).then())?;
+
+        Ok(PinValueArray::from(response[0]))
+    }
+}
+
+/// A single spare GPIO pin, see [`Gpio::pin`].
+#[derive(Debug)]
+pub struct Pin<'a, T> {
+    gpio: &'a Gpio<T>,
+    pin: u8,
+}
+
+impl<'a, T: Transport> Pin<'a, T> {
+    fn range(&self) -> PinRange {
+        match self.pin {
+            0..=7 => PinRange::Low,
+            _ => PinRange::High,
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        1 << (self.pin % 8)
+    }
+
+    /// The pin number, as for [`Builder::set_pin`].
+    pub fn number(&self) -> u8 {
+        self.pin
+    }
+
+    /// Make the pin an output, driving its last set value.
+    pub fn set_output(&mut self) -> Result<(), Error<T::Error>> {
+        let pin = self.pin;
+        self.gpio
+            .execute(|builder| builder.set_direction(pin, PinDirection::Output).then())?;
+        Ok(())
+    }
+
+    /// Make the pin an input.
+    pub fn set_input(&mut self) -> Result<(), Error<T::Error>> {
+        let pin = self.pin;
+        self.gpio
+            .execute(|builder| builder.set_direction(pin, PinDirection::Input).then())?;
+        Ok(())
+    }
+
+    /// Drive the pin to `value`, making it an output if it isn't one already.
+    pub fn set(&mut self, value: PinValue) -> Result<(), Error<T::Error>> {
+        let pin = self.pin;
+        let output = self.gpio.pin_state(self.range()).direction & self.bit() != 0;
+        self.gpio.execute(|builder| match output {
+            true => builder.set_pin(pin, value).then(),
+            false => builder
+                .set_pin(pin, value)
+                .then()
+                .set_direction(pin, PinDirection::Output)
+                .then(),
+        })?;
+        Ok(())
+    }
+
+    /// Drive the pin high.
+    pub fn set_high(&mut self) -> Result<(), Error<T::Error>> {
+        self.set(PinValue::High)
+    }
+
+    /// Drive the pin low.
+    pub fn set_low(&mut self) -> Result<(), Error<T::Error>> {
+        self.set(PinValue::Low)
+    }
+
+    /// The value the pin was last set to, without reading it back.
+    pub fn set_value(&self) -> PinValue {
+        match self.gpio.pin_state(self.range()).value & self.bit() != 0 {
+            true => PinValue::High,
+            false => PinValue::Low,
+        }
+    }
+
+    /// Read the pin's current level.
+    pub fn read(&mut self) -> Result<PinValue, Error<T::Error>> {
+        let values = self.gpio.read(self.range())?;
+        Ok(values.get((self.pin % 8) as usize))
+    }
+
+    /// Whether the pin currently reads high.
+    pub fn is_high(&mut self) -> Result<bool, Error<T::Error>> {
+        Ok(self.read()? == PinValue::High)
+    }
+
+    /// Whether the pin currently reads low.
+    pub fn is_low(&mut self) -> Result<bool, Error<T::Error>> {
+        Ok(self.read()? == PinValue::Low)
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+mod hal {
+    use super::{Error, Pin};
+    use crate::command::PinValue;
+    use crate::transport::Transport;
+    use embedded_hal::digital::StatefulOutputPin;
+    use embedded_hal::digital::{self, ErrorKind, ErrorType, InputPin, OutputPin};
+    use std::fmt;
+
+    impl<E: fmt::Debug> digital::Error for Error<E> {
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
+        }
+    }
+
+    impl<'a, T: Transport> ErrorType for Pin<'a, T>
+    where
+        T::Error: fmt::Debug,
+    {
+        type Error = Error<T::Error>;
+    }
+
+    impl<'a, T: Transport> OutputPin for Pin<'a, T>
+    where
+        T::Error: fmt::Debug,
+    {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            Pin::set_low(self)
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            Pin::set_high(self)
+        }
+    }
+
+    impl<'a, T: Transport> StatefulOutputPin for Pin<'a, T>
+    where
+        T::Error: fmt::Debug,
+    {
+        fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(self.set_value() == PinValue::High)
+        }
+
+        fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+            Ok(self.set_value() == PinValue::Low)
+        }
+    }
+
+    impl<'a, T: Transport> InputPin for Pin<'a, T>
+    where
+        T::Error: fmt::Debug,
+    {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            Pin::is_high(self)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            Pin::is_low(self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod gpio_tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn pins_share_state() {
+        let gpio = Gpio::new(MockTransport::with_responses(&[])).with_pin_state(
+            PinRange::Low,
+            PinState {
+                value: 0x08,
+                direction: 0x0B,
+            },
+        );
+        let mut reset = gpio.pin(4);
+        let mut enable = gpio.pin(10);
+
+        reset.set_high().unwrap();
+        enable.set_low().unwrap();
+        reset.set_low().unwrap();
+
+        assert_eq!(reset.set_value(), PinValue::Low);
+        assert_eq!(
+            gpio.into_inner().written,
+            vec![
+                0x80, 0x18, 0x0B, 0x80, 0x18, 0x1B, 0x87, // ADBUS4 high, then an output
+                0x82, 0x00, 0x00, 0x82, 0x00, 0x04, 0x87, // ACBUS2 low, then an output
+                0x80, 0x08, 0x1B, 0x87, // ADBUS4 low
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_decode_the_range() {
+        let gpio = Gpio::new(MockTransport::with_responses(&[0x20]));
+        let mut interrupt = gpio.pin(13);
+
+        assert!(interrupt.is_high().unwrap());
+        assert_eq!(gpio.into_inner().written, vec![0x83, 0x87]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn serial_engine_pins_are_reserved() {
+        let gpio = Gpio::new(MockTransport::with_responses(&[]));
+        gpio.pin(2);
+    }
+}
@@ -0,0 +1,586 @@
+//! I2C transactions built from MPSSE commands.
+//!
+//! The pins are wired as described in FTDI's AN_255: ADBUS0 is SCL, ADBUS1 drives SDA and ADBUS2
+//! reads SDA back, so ADBUS1 and ADBUS2 must be connected together.
+//!
+//! ```
+//! use mpsse::Builder;
+//! use mpsse::i2c::Direction;
+//!
+//! let commands = Builder::new()
+//!     .i2c()
+//!     .start()
+//!     .address(0x50, Direction::Write)
+//!     .write(&[0x00, 0x10])
+//!     .stop()
+//!     .build();
+//!
+//! assert_eq!(&commands[..3], &[0x80, 0x03, 0x03]);
+//! ```
+use std::fmt;
+use std::time::Duration;
+
+use crate::builder::Builder;
+use crate::command::{BitDirection, ClockEdge, Command, DataShiftOptions, PinRange};
+use crate::transport::{self, Transport};
+
+const SCL: u8 = 0x01;
+const SDA_OUT: u8 = 0x02;
+const SDA_IN: u8 = 0x04;
+
+/// Number of times each pin state is repeated, to hold it for long enough for slow targets.
+const HOLD_REPEATS: usize = 4;
+
+/// Number of clock pulses needed to finish any byte a target may be stuck in.
+const RECOVERY_PULSES: usize = 9;
+
+/// Whether an I2C transaction reads from or writes to the target.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+    Write,
+    Read,
+}
+
+/// Address of an I2C target.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Address {
+    /// A standard 7-bit address.
+    SevenBit(u8),
+    /// A 10-bit address, sent as a `11110xx` header followed by the low byte.
+    TenBit(u16),
+}
+
+impl From<u8> for Address {
+    fn from(address: u8) -> Self {
+        Address::SevenBit(address)
+    }
+}
+
+impl Address {
+    /// The bytes sent to address the target, split where a repeated START is needed.
+    ///
+    /// Reading from a 10-bit target needs the full address to be written first, followed by a
+    /// repeated START and the header again with the read bit set.
+    pub fn frames(&self, direction: Direction) -> Vec<Vec<u8>> {
+        let rw = match direction {
+            Direction::Write => 0x00,
+            Direction::Read => 0x01,
+        };
+
+        match *self {
+            Address::SevenBit(address) => vec![vec![address << 1 | rw]],
+            Address::TenBit(address) => {
+                let header = 0xF0 | ((address >> 7) as u8 & 0x06);
+                let low = address as u8;
+                match direction {
+                    Direction::Write => vec![vec![header, low]],
+                    Direction::Read => vec![vec![header, low], vec![header | rw]],
+                }
+            }
+        }
+    }
+
+    /// The number of bytes written (and so ACK bits read back) to address the target.
+    pub fn length(&self, direction: Direction) -> usize {
+        self.frames(direction).iter().map(|frame| frame.len()).sum()
+    }
+}
+
+/// Whether the target acknowledged a byte, given the response byte read back for it.
+pub fn is_ack(response: u8) -> bool {
+    response & 0x01 == 0
+}
+
+/// Whether SDA was released, given the response to a pin read during bus recovery.
+pub fn is_sda_released(response: u8) -> bool {
+    response & SDA_IN == SDA_IN
+}
+
+impl Builder {
+    /// Start building a sequence of I2C bus operations.
+    pub fn i2c(self) -> I2cBuilder {
+        I2cBuilder { parent: self }
+    }
+}
+
+/// Build a sequence of I2C bus operations.
+///
+/// Each written byte (including addresses) responds with one byte, whose lowest bit is the ACK
+/// bit (see [`is_ack`]). Each read byte responds with the byte itself.
+#[derive(Debug)]
+pub struct I2cBuilder {
+    parent: Builder,
+}
+
+impl I2cBuilder {
+    fn set_lines(mut self, value: u8, direction: u8, repeats: usize) -> Self {
+        for _ in 0..repeats {
+            self.parent
+                .set_bits(PinRange::Low, SCL | SDA_OUT | SDA_IN, value, direction);
+        }
+
+        self
+    }
+
+    /// Prepare the MPSSE for I2C, and leave the bus idle.
+    ///
+    /// This enables three-phase clocking, so that data is valid on both clock edges.
+    ///
+    /// ```
+    /// use mpsse::Builder;
+    ///
+    /// let commands = Builder::new().i2c().setup().build();
+    ///
+    /// assert_eq!(commands, vec![0x8C, 0x80, 0x03, 0x03])
+    /// ```
+    pub fn setup(mut self) -> Self {
+        self.parent
+            .commands
+            .push(Command::SetThreePhaseClocking { enable: true });
+
+        self.idle()
+    }
+
+    /// Drive SCL and SDA high, leaving the bus idle.
+    pub fn idle(self) -> Self {
+        self.set_lines(SCL | SDA_OUT, SCL | SDA_OUT, 1)
+    }
+
+    /// Generate a START (or repeated START) condition.
+    pub fn start(self) -> Self {
+        self.set_lines(SCL | SDA_OUT, SCL | SDA_OUT, HOLD_REPEATS)
+            .set_lines(SCL, SCL | SDA_OUT, HOLD_REPEATS)
+            .set_lines(0, SCL | SDA_OUT, 1)
+    }
+
+    /// Generate a STOP condition.
+    pub fn stop(self) -> Self {
+        self.set_lines(0, SCL | SDA_OUT, HOLD_REPEATS)
+            .set_lines(SCL, SCL | SDA_OUT, HOLD_REPEATS)
+            .set_lines(SCL | SDA_OUT, SCL | SDA_OUT, HOLD_REPEATS)
+    }
+
+    /// Write a single byte, then read back the target's ACK bit.
+    ///
+    /// ```
+    /// use mpsse::Builder;
+    ///
+    /// let commands = Builder::new().i2c().write_byte(0xA5).build();
+    ///
+    /// assert_eq!(
+    ///     commands,
+    ///     vec![0x11, 0x00, 0x00, 0xA5, 0x80, 0x00, 0x01, 0x22, 0x00, 0x80, 0x02, 0x03]
+    /// )
+    /// ```
+    pub fn write_byte(mut self, byte: u8) -> Self {
+        self.parent.commands.push(Command::WriteDataShiftBytes {
+            options: DataShiftOptions {
+                clock_direction: ClockEdge::Falling,
+                bit_direction: BitDirection::MsbFirst,
+            },
+            bytes: vec![byte],
+        });
+
+        self = self.set_lines(0, SCL, 1);
+        self.parent.commands.push(Command::ReadDataShiftBits {
+            options: DataShiftOptions {
+                clock_direction: ClockEdge::Rising,
+                bit_direction: BitDirection::MsbFirst,
+            },
+            length: 1,
+        });
+
+        self.set_lines(SDA_OUT, SCL | SDA_OUT, 1)
+    }
+
+    /// Write each byte in turn, reading back an ACK bit for each.
+    pub fn write(self, bytes: &[u8]) -> Self {
+        bytes
+            .iter()
+            .fold(self, |builder, byte| builder.write_byte(*byte))
+    }
+
+    /// Read a single byte, then send an ACK (`ack = true`) or NACK back to the target.
+    ///
+    /// ```
+    /// use mpsse::Builder;
+    ///
+    /// let commands = Builder::new().i2c().read_byte(false).build();
+    ///
+    /// assert_eq!(
+    ///     commands,
+    ///     vec![0x80, 0x00, 0x01, 0x20, 0x00, 0x00, 0x80, 0x00, 0x03, 0x13, 0x00, 0xFF, 0x80, 0x02, 0x03]
+    /// )
+    /// ```
+    pub fn read_byte(mut self, ack: bool) -> Self {
+        self = self.set_lines(0, SCL, 1);
+        self.parent.commands.push(Command::ReadDataShiftBytes {
+            options: DataShiftOptions {
+                clock_direction: ClockEdge::Rising,
+                bit_direction: BitDirection::MsbFirst,
+            },
+            length: 1,
+        });
+
+        self = self.set_lines(0, SCL | SDA_OUT, 1);
+        self.parent.commands.push(Command::WriteDataShiftBits {
+            options: DataShiftOptions {
+                clock_direction: ClockEdge::Falling,
+                bit_direction: BitDirection::MsbFirst,
+            },
+            bits: match ack {
+                true => 0x00,
+                false => 0xFF,
+            },
+            length: 1,
+        });
+
+        self.set_lines(SDA_OUT, SCL | SDA_OUT, 1)
+    }
+
+    /// Read `length` bytes, acknowledging all but the last.
+    pub fn read(self, length: usize) -> Self {
+        (0..length).fold(self, |builder, i| builder.read_byte(i + 1 < length))
+    }
+
+    /// Write a target address with the read/write bit set for `direction`.
+    ///
+    /// A 7-bit address can be given as a plain `u8`. Reading from a 10-bit address generates a
+    /// repeated START partway through, see [`Address::frames`].
+    ///
+    /// ```
+    /// use mpsse::Builder;
+    /// use mpsse::i2c::{Address, Direction};
+    ///
+    /// let commands = Builder::new()
+    ///     .i2c()
+    ///     .address(Address::TenBit(0x2A5), Direction::Write)
+    ///     .build();
+    ///
+    /// assert_eq!(&commands[..4], &[0x11, 0x00, 0x00, 0xF4]);
+    /// assert_eq!(&commands[12..16], &[0x11, 0x00, 0x00, 0xA5]);
+    /// ```
+    pub fn address<A>(self, address: A, direction: Direction) -> Self
+    where
+        A: Into<Address>,
+    {
+        address.into().frames(direction).iter().enumerate().fold(
+            self,
+            |builder, (i, frame)| match i {
+                0 => builder.write(frame),
+                _ => builder.start().write(frame),
+            },
+        )
+    }
+
+    /// Enable or disable clock stretching.
+    ///
+    /// This uses adaptive clocking, so SCL must also be wired to GPIOL3 (ADBUS7) and driven
+    /// open-drain (see [`I2cBuilder::with_open_drain`]). Only H-series chips support this.
+    pub fn with_clock_stretching(mut self, enable: bool) -> Self {
+        self.parent
+            .commands
+            .push(Command::SetAdaptiveClocking { enable });
+
+        self
+    }
+
+    /// Only drive SCL and SDA low, letting the pull-ups take them high.
+    ///
+    /// This uses the Drive Only Zero command, which only exists on the FT232H.
+    pub fn with_open_drain(mut self, enable: bool) -> Self {
+        let mask = match enable {
+            true => SCL | SDA_OUT,
+            false => 0,
+        };
+        self.parent
+            .commands
+            .push(Command::SetDriveOnlyZero { low: mask, high: 0 });
+
+        self
+    }
+
+    /// Clock SCL nine times with SDA released, sampling the pins after each pulse, then STOP.
+    ///
+    /// This frees a target left holding SDA low partway through a byte. Each pulse responds with
+    /// one byte; use [`is_sda_released`] to check whether SDA came back up. To stop clocking as
+    /// soon as SDA is released, use [`I2c::recover_bus`] instead.
+    pub fn recover_bus(self) -> Self {
+        (0..RECOVERY_PULSES)
+            .fold(self, |builder, _| builder.recovery_pulse())
+            .stop()
+    }
+
+    fn recovery_pulse(mut self) -> Self {
+        self = self
+            .set_lines(0, SCL, HOLD_REPEATS)
+            .set_lines(SCL, SCL, HOLD_REPEATS);
+        self.parent.commands.push(Command::ReadBits {
+            range: PinRange::Low,
+        });
+
+        self
+    }
+
+    /// Commit this sequence to the parent Builder.
+    fn commit(self) -> Builder {
+        self.parent
+    }
+
+    builder_funcs!();
+}
+
+/// Error returned by the [`I2c`] driver.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// Running the commands failed, or timed out, for example because a target stretched the
+    /// clock for too long.
+    Transport(transport::Error<E>),
+    /// The target did not acknowledge the byte at `index`, where index 0 is the address.
+    Nack { index: usize },
+    /// SDA was still held low after bus recovery.
+    BusStuck,
+}
+
+impl<E> From<transport::Error<E>> for Error<E> {
+    fn from(err: transport::Error<E>) -> Self {
+        Error::Transport(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Transport(err) => write!(f, "{}", err),
+            Error::Nack { index } => write!(f, "byte {} was not acknowledged", index),
+            Error::BusStuck => write!(f, "SDA is still held low after bus recovery"),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+/// Run I2C transactions over a [`Transport`].
+#[derive(Debug)]
+pub struct I2c<T> {
+    transport: T,
+    timeout: Duration,
+}
+
+impl<T: Transport> I2c<T> {
+    /// Create a driver using the given transport, with the
+    /// [default timeout](transport::DEFAULT_TIMEOUT).
+    pub fn new(transport: T) -> Self {
+        I2c {
+            transport,
+            timeout: transport::DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Set how long to wait for the device to respond.
+    ///
+    /// While clock stretching is enabled, this bounds how long a target may hold SCL low.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        I2c { timeout, ..self }
+    }
+
+    /// Get the underlying transport.
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Get back the underlying transport.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Run the given commands, flushing and returning their response.
+    pub fn execute(&mut self, builder: Builder) -> Result<Vec<u8>, Error<T::Error>> {
+        Ok(transport::flush(
+            &mut self.transport,
+            builder,
+            self.timeout,
+        )?)
+    }
+
+    /// Prepare the MPSSE for I2C, and leave the bus idle.
+    pub fn setup(&mut self) -> Result<(), Error<T::Error>> {
+        self.execute(Builder::new().i2c().setup().then())?;
+        Ok(())
+    }
+
+    /// Enable or disable clock stretching, see [`I2cBuilder::with_clock_stretching`].
+    pub fn set_clock_stretching(&mut self, enable: bool) -> Result<(), Error<T::Error>> {
+        self.execute(Builder::new().i2c().with_clock_stretching(enable).then())?;
+        Ok(())
+    }
+
+    /// Write `bytes` to the target at `address`.
+    pub fn write<A>(&mut self, address: A, bytes: &[u8]) -> Result<(), Error<T::Error>>
+    where
+        A: Into<Address>,
+    {
+        let builder = Builder::new()
+            .i2c()
+            .start()
+            .address(address, Direction::Write)
+            .write(bytes)
+            .stop()
+            .then();
+
+        check_acks(&self.execute(builder)?)
+    }
+
+    /// Read `length` bytes from the target at `address`.
+    pub fn read<A>(&mut self, address: A, length: usize) -> Result<Vec<u8>, Error<T::Error>>
+    where
+        A: Into<Address>,
+    {
+        let address = address.into();
+        let builder = Builder::new()
+            .i2c()
+            .start()
+            .address(address, Direction::Read)
+            .read(length)
+            .stop()
+            .then();
+
+        let response = self.execute(builder)?;
+        let (acks, data) = response.split_at(address.length(Direction::Read));
+        check_acks(acks)?;
+
+        Ok(data.to_vec())
+    }
+
+    /// Write `bytes` to the target at `address`, then read `length` bytes after a repeated START.
+    pub fn write_read<A>(
+        &mut self,
+        address: A,
+        bytes: &[u8],
+        length: usize,
+    ) -> Result<Vec<u8>, Error<T::Error>>
+    where
+        A: Into<Address>,
+    {
+        let address = address.into();
+        let builder = Builder::new()
+            .i2c()
+            .start()
+            .address(address, Direction::Write)
+            .write(bytes)
+            .start()
+            .address(address, Direction::Read)
+            .read(length)
+            .stop()
+            .then();
+
+        let response = self.execute(builder)?;
+        let acks = address.length(Direction::Write) + bytes.len() + address.length(Direction::Read);
+        let (acks, data) = response.split_at(acks);
+        check_acks(acks)?;
+
+        Ok(data.to_vec())
+    }
+
+    /// Free a target holding SDA low, by clocking SCL up to nine times until SDA is released,
+    /// then generating a STOP condition.
+    pub fn recover_bus(&mut self) -> Result<(), Error<T::Error>> {
+        let read_sda = |builder: Builder| builder.read_pins(PinRange::Low).then();
+
+        let mut released = is_sda_released(self.execute(read_sda(Builder::new()))?[0]);
+        for _ in 0..RECOVERY_PULSES {
+            if released {
+                break;
+            }
+
+            let response = self.execute(Builder::new().i2c().recovery_pulse().then())?;
+            released = is_sda_released(response[0]);
+        }
+
+        self.execute(Builder::new().i2c().stop().then())?;
+
+        match released {
+            true => Ok(()),
+            false => Err(Error::BusStuck),
+        }
+    }
+}
+
+/// Check the ACK bits read back for each written byte.
+pub(crate) fn check_acks<E>(responses: &[u8]) -> Result<(), Error<E>> {
+    match responses.iter().position(|response| !is_ack(*response)) {
+        Some(index) => Err(Error::Nack { index }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod i2c_tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn write_checks_acks() {
+        let transport = MockTransport::with_responses(&[0x00, 0x00, 0x01]);
+        let mut i2c = I2c::new(transport);
+
+        match i2c.write(0x50, &[0x00, 0x01]) {
+            Err(Error::Nack { index: 2 }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn read_returns_data() {
+        let transport = MockTransport::with_responses(&[0x00, 0xDE, 0xAD]);
+        let mut i2c = I2c::new(transport);
+
+        assert_eq!(i2c.read(0x50, 2).unwrap(), vec![0xDE, 0xAD]);
+        assert!(i2c
+            .transport()
+            .written
+            .windows(4)
+            .any(|command| command == [0x11, 0x00, 0x00, 0xA1]));
+    }
+
+    #[test]
+    fn ten_bit_read_repeats_header() {
+        let frames = Address::TenBit(0x3FF).frames(Direction::Read);
+
+        assert_eq!(frames, vec![vec![0xF6, 0xFF], vec![0xF7]]);
+    }
+
+    #[test]
+    fn recovery_stops_once_sda_is_released() {
+        let transport = MockTransport::with_responses(&[0x00, 0x00, SDA_IN]);
+        let mut i2c = I2c::new(transport);
+
+        i2c.recover_bus().unwrap();
+
+        // Read SDA, clock SCL if it is still low, and STOP once it is released.
+        let read = vec![0x81, 0x87];
+        let pulse = [[0x80, 0x00, 0x01].repeat(4), [0x80, 0x01, 0x01].repeat(4)].concat();
+        let stop = [
+            [0x80, 0x00, 0x03].repeat(4),
+            [0x80, 0x01, 0x03].repeat(4),
+            [0x80, 0x03, 0x03].repeat(4),
+            vec![0x87],
+        ]
+        .concat();
+        assert_eq!(
+            i2c.transport().written,
+            [read.clone(), pulse.clone(), read.clone(), pulse, read, stop].concat()
+        );
+    }
+
+    #[test]
+    fn recovery_reports_stuck_bus() {
+        let transport = MockTransport::with_responses(&[0x00; 10]);
+        let mut i2c = I2c::new(transport);
+
+        match i2c.recover_bus() {
+            Err(Error::BusStuck) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
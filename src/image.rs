@@ -0,0 +1,355 @@
+//! Memory images loaded from raw binary, Intel HEX and Motorola S-record files.
+//!
+//! An [`Image`] is a set of non-overlapping [`Segment`]s of data at fixed addresses, ready to
+//! be written with [`Flash::program_image`](crate::flash::Flash::program_image).
+//!
+//! ```
+//! use mpsse::image::parse_ihex;
+//!
+//! let image = parse_ihex("
+//!     :0400000001020304F2
+//!     :00000001FF
+//! ").unwrap();
+//!
+//! assert_eq!(image.segments()[0].address, 0);
+//! assert_eq!(image.segments()[0].data, vec![1, 2, 3, 4]);
+//! ```
+use std::fmt;
+
+/// Error found while parsing an image file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The line the record is on, counting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Contiguous data starting at an address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    /// The address just past the end of the segment.
+    pub fn end(&self) -> u64 {
+        self.address as u64 + self.data.len() as u64
+    }
+}
+
+/// Data to be placed at fixed addresses.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Image {
+    /// Sorted by address, with no overlapping or adjacent segments.
+    segments: Vec<Segment>,
+}
+
+impl Image {
+    /// Create an empty image.
+    pub fn new() -> Self {
+        Image::default()
+    }
+
+    /// Create an image from a raw binary file loaded at `address`.
+    pub fn from_binary(address: u32, data: &[u8]) -> Self {
+        let mut image = Image::new();
+        image.insert(address, data);
+        image
+    }
+
+    /// The image's data, sorted by address.
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// The number of bytes in the image.
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|segment| segment.data.len()).sum()
+    }
+
+    /// Whether the image contains no data.
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Place `data` at `address`, replacing any data already there.
+    pub fn insert(&mut self, address: u32, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        let start = address as u64;
+        let end = start + data.len() as u64;
+
+        // Segments that overlap or touch the new data are merged into it.
+        let (merged, kept): (Vec<Segment>, Vec<Segment>) = self
+            .segments
+            .drain(..)
+            .partition(|segment| segment.address as u64 <= end && segment.end() >= start);
+
+        let merged_start = merged
+            .iter()
+            .map(|segment| segment.address as u64)
+            .fold(start, u64::min);
+        let merged_end = merged.iter().map(Segment::end).fold(end, u64::max);
+
+        let mut buffer = vec![0; (merged_end - merged_start) as usize];
+        for segment in merged.iter() {
+            let offset = (segment.address as u64 - merged_start) as usize;
+            buffer[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+        }
+        let offset = (start - merged_start) as usize;
+        buffer[offset..offset + data.len()].copy_from_slice(data);
+
+        self.segments = kept;
+        self.segments.push(Segment {
+            address: merged_start as u32,
+            data: buffer,
+        });
+        self.segments.sort_by_key(|segment| segment.address);
+    }
+
+    /// Copy the image's data over `buffer`, which holds memory starting at `address`.
+    ///
+    /// Returns whether the image has any data in that range.
+    pub fn overlay(&self, address: u32, buffer: &mut [u8]) -> bool {
+        let start = address as u64;
+        let end = start + buffer.len() as u64;
+        let mut covered = false;
+
+        for segment in self.segments.iter() {
+            let from = start.max(segment.address as u64);
+            let to = end.min(segment.end());
+            if from < to {
+                let source = (from - segment.address as u64) as usize;
+                let target = (from - start) as usize;
+                let length = (to - from) as usize;
+                buffer[target..target + length]
+                    .copy_from_slice(&segment.data[source..source + length]);
+                covered = true;
+            }
+        }
+
+        covered
+    }
+}
+
+/// Decode a record's hex digits into bytes, checking there are an even number of them.
+fn decode_hex(line: usize, digits: &str) -> Result<Vec<u8>, ParseError> {
+    let error = |message: &str| ParseError {
+        line,
+        message: message.to_string(),
+    };
+
+    if !digits.len().is_multiple_of(2) {
+        return Err(error("odd number of hex digits"));
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            digits
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| error("invalid hex digit"))
+        })
+        .collect()
+}
+
+/// Parse an Intel HEX file.
+///
+/// Data records are placed using extended segment and extended linear addresses. Start
+/// address records are ignored.
+pub fn parse_ihex(source: &str) -> Result<Image, ParseError> {
+    let mut image = Image::new();
+    let mut base = 0u32;
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let error = |message: &str| ParseError {
+            line,
+            message: message.to_string(),
+        };
+
+        let digits = text
+            .strip_prefix(':')
+            .ok_or_else(|| error("record does not start with ':'"))?;
+        let bytes = decode_hex(line, digits)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(error("record length does not match its byte count"));
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(error("bad checksum"));
+        }
+
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match (bytes[3], data.len()) {
+            (0x00, _) => image.insert(base.wrapping_add(offset), data),
+            (0x01, _) => return Ok(image),
+            (0x02, 2) => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            (0x04, 2) => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+            (0x03, 4) | (0x05, 4) => (),
+            (0x02..=0x05, _) => return Err(error("address record has the wrong length")),
+            (kind, _) => return Err(error(&format!("unknown record type {:02X}", kind))),
+        }
+    }
+
+    Err(ParseError {
+        line: source.lines().count(),
+        message: "missing end of file record".to_string(),
+    })
+}
+
+/// Parse a Motorola S-record file.
+///
+/// S1, S2 and S3 data records are loaded. Header, count and termination records are checked
+/// but otherwise ignored.
+pub fn parse_srec(source: &str) -> Result<Image, ParseError> {
+    let mut image = Image::new();
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let error = |message: &str| ParseError {
+            line,
+            message: message.to_string(),
+        };
+
+        let mut chars = text.chars();
+        if chars.next() != Some('S') {
+            return Err(error("record does not start with 'S'"));
+        }
+        let kind = chars
+            .next()
+            .and_then(|kind| kind.to_digit(10))
+            .ok_or_else(|| error("invalid record type"))?;
+        let bytes = decode_hex(line, chars.as_str())?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(error("record length does not match its byte count"));
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0xFF {
+            return Err(error("bad checksum"));
+        }
+
+        let address_length = match kind {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => return Err(error(&format!("unknown record type S{}", kind))),
+        };
+        let fields = &bytes[1..bytes.len() - 1];
+        if fields.len() < address_length {
+            return Err(error("record is too short for its address"));
+        }
+
+        let (address, data) = fields.split_at(address_length);
+        let address = address
+            .iter()
+            .fold(0u32, |address, byte| address << 8 | *byte as u32);
+        if let 1..=3 = kind {
+            image.insert(address, data);
+        }
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod image_tests {
+    use super::*;
+
+    #[test]
+    fn insert_merges_and_overrides() {
+        let mut image = Image::new();
+        image.insert(0x10, &[1, 2, 3, 4]);
+        image.insert(0x20, &[9]);
+        image.insert(0x12, &[5, 6, 7]);
+
+        assert_eq!(
+            image.segments(),
+            &[
+                Segment {
+                    address: 0x10,
+                    data: vec![1, 2, 5, 6, 7]
+                },
+                Segment {
+                    address: 0x20,
+                    data: vec![9]
+                },
+            ]
+        );
+
+        let mut buffer = [0xFF; 4];
+        assert!(image.overlay(0x13, &mut buffer));
+        assert_eq!(buffer, [6, 7, 0xFF, 0xFF]);
+        assert!(!image.overlay(0x18, &mut buffer));
+    }
+
+    #[test]
+    fn ihex_extended_linear_address() {
+        let image = parse_ihex(
+            ":020000040800F2\n\
+             :04000000DEADBEEFC4\n\
+             :00000001FF\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            image.segments(),
+            &[Segment {
+                address: 0x0800_0000,
+                data: vec![0xDE, 0xAD, 0xBE, 0xEF]
+            }]
+        );
+    }
+
+    #[test]
+    fn ihex_bad_checksum() {
+        let err = parse_ihex(":0400000001020304F3\n:00000001FF").unwrap_err();
+
+        assert_eq!(err.line, 1);
+        assert_eq!(err.message, "bad checksum");
+    }
+
+    #[test]
+    fn srec_data_records() {
+        let image = parse_srec(
+            "S00600004844521B\n\
+             S107010001020304ED\n\
+             S309000002000A0B0C0DC6\n\
+             S9030000FC\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            image.segments(),
+            &[
+                Segment {
+                    address: 0x100,
+                    data: vec![1, 2, 3, 4]
+                },
+                Segment {
+                    address: 0x200,
+                    data: vec![0x0A, 0x0B, 0x0C, 0x0D]
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,284 @@
+//! The standard MPSSE start-up sequence.
+//!
+//! [`Builder::init`] first checks that the MPSSE is in sync with the host, following FTDI's
+//! AN_135: the bad opcodes 0xAA and 0xAB must each be answered with 0xFA and the opcode. It then
+//! disables loopback, divide-by-5 (where that gives the requested clock), adaptive clocking and
+//! three-phase clocking, sets the clock divisor and the board's initial pin state, and sends a
+//! Send Immediate.
+//!
+//! ```
+//! use mpsse::board::Profile;
+//! use mpsse::init::{Chip, InitConfig};
+//! use mpsse::Builder;
+//!
+//! let config = InitConfig::new(Chip::Ft232h, Profile::find("ft232h").unwrap())
+//!     .with_frequency(1_000_000.0);
+//! let commands = Builder::new().init(config).build();
+//!
+//! assert_eq!(
+//!     commands,
+//!     vec![
+//!         0xAA, 0xAB, // Bad opcodes, to check synchronisation
+//!         0x85, 0x8A, 0x97, 0x8D, // Loopback, divide-by-5, adaptive and three-phase off
+//!         0x86, 0x1D, 0x00, // 60MHz / (2 * 30) = 1MHz
+//!         0x80, 0x08, 0x0B, 0x82, 0x00, 0x00, // Initial pin state
+//!         0x87,
+//!     ]
+//! );
+//! ```
+use std::fmt;
+use std::time::Duration;
+
+use crate::board::Profile;
+use crate::builder::Builder;
+use crate::command::{Command, PinRange};
+use crate::transport::{self, Transport};
+
+/// The bad opcodes sent to check synchronisation.
+const SYNC_OPCODES: [u8; 2] = [0xAA, 0xAB];
+
+/// The byte the MPSSE sends before echoing an opcode it doesn't recognise.
+pub const BAD_COMMAND: u8 = 0xFA;
+
+/// An FTDI chip with an MPSSE.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Chip {
+    Ft2232d,
+    Ft2232h,
+    Ft4232h,
+    Ft232h,
+}
+
+impl Chip {
+    /// Whether the chip is an H-series part, with a 60MHz clock and three-phase clocking.
+    pub fn is_h_series(self) -> bool {
+        self != Chip::Ft2232d
+    }
+
+    /// Whether the chip supports adaptive clocking.
+    pub fn has_adaptive_clocking(self) -> bool {
+        match self {
+            Chip::Ft2232h | Chip::Ft232h => true,
+            Chip::Ft2232d | Chip::Ft4232h => false,
+        }
+    }
+}
+
+/// Settings for [`Builder::init`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct InitConfig {
+    chip: Chip,
+    profile: Profile,
+    frequency: f64,
+    sync: bool,
+}
+
+impl InitConfig {
+    /// Initialise `chip` with the pin state of the board `profile`, at 1MHz.
+    pub fn new(chip: Chip, profile: Profile) -> Self {
+        InitConfig {
+            chip,
+            profile,
+            frequency: 1_000_000.0,
+            sync: true,
+        }
+    }
+
+    /// Set the target clock frequency in hz. The clock runs at the closest frequency that is no
+    /// faster, see [`frequency`](InitConfig::frequency).
+    pub fn with_frequency(self, frequency: f64) -> Self {
+        InitConfig { frequency, ..self }
+    }
+
+    /// Choose whether to send the bad opcodes that check synchronisation.
+    pub fn with_sync(self, sync: bool) -> Self {
+        InitConfig { sync, ..self }
+    }
+
+    /// Whether divide-by-5 is enabled, and the clock divisor.
+    fn clock(&self) -> (bool, u16) {
+        let divisor = |base: f64| (base / (2.0 * self.frequency)).ceil() - 1.0;
+
+        match self.chip.is_h_series() && divisor(60_000_000.0) <= u16::MAX as f64 {
+            true => (false, divisor(60_000_000.0).max(0.0) as u16),
+            false => (
+                true,
+                divisor(12_000_000.0).clamp(0.0, u16::MAX as f64) as u16,
+            ),
+        }
+    }
+
+    /// The clock frequency the MPSSE will actually run at.
+    pub fn frequency(&self) -> f64 {
+        let (divide_by_5, divisor) = self.clock();
+        let base = match divide_by_5 {
+            true => 12_000_000.0,
+            false => 60_000_000.0,
+        };
+
+        base / (2.0 * (1.0 + divisor as f64))
+    }
+}
+
+impl Builder {
+    /// Generate the standard start-up sequence for a chip and board.
+    ///
+    /// When synchronisation is checked, the response starts with four bytes to pass to
+    /// [`is_synchronised`].
+    pub fn init(self, config: InitConfig) -> InitBuilder {
+        InitBuilder {
+            parent: self,
+            config,
+        }
+    }
+}
+
+/// Build the standard start-up sequence.
+#[derive(Debug)]
+pub struct InitBuilder {
+    parent: Builder,
+    config: InitConfig,
+}
+
+impl InitBuilder {
+    /// Commit this sequence to the parent Builder.
+    fn commit(mut self) -> Builder {
+        let config = self.config;
+        let commands = &mut self.parent.commands;
+
+        if config.sync {
+            for opcode in SYNC_OPCODES.iter() {
+                commands.push(Command::BadCommand { opcode: *opcode });
+            }
+        }
+
+        commands.push(Command::SetLoopback { enable: false });
+        let (divide_by_5, divisor) = config.clock();
+        if config.chip.is_h_series() {
+            commands.push(Command::SetClockDivideBy5 {
+                enable: divide_by_5,
+            });
+        }
+        if config.chip.has_adaptive_clocking() {
+            commands.push(Command::SetAdaptiveClocking { enable: false });
+        }
+        if config.chip.is_h_series() {
+            commands.push(Command::SetThreePhaseClocking { enable: false });
+        }
+
+        let profile = &config.profile;
+        self.parent
+            .set_divisor(divisor)
+            .then()
+            .set_pins(PinRange::Low, profile.low.direction, profile.low.value)
+            .then()
+            .set_pins(PinRange::High, profile.high.direction, profile.high.value)
+            .then()
+            .send_immediate()
+            .then()
+    }
+
+    builder_funcs!();
+}
+
+/// Whether the start of the response to [`Builder::init`] shows the MPSSE echoing both bad
+/// opcodes, so that commands and responses are in sync.
+pub fn is_synchronised(response: &[u8]) -> bool {
+    let expected = SYNC_OPCODES
+        .iter()
+        .flat_map(|opcode| vec![BAD_COMMAND, *opcode]);
+
+    response.len() >= 4 && response.iter().take(4).copied().eq(expected)
+}
+
+/// Error returned by [`init`].
+#[derive(Debug)]
+pub enum Error<E> {
+    /// Running the commands failed.
+    Transport(transport::Error<E>),
+    /// The MPSSE did not echo the bad opcodes, so it is not in MPSSE mode or has stale data
+    /// waiting to be read.
+    NotSynchronised(Vec<u8>),
+}
+
+impl<E> From<transport::Error<E>> for Error<E> {
+    fn from(err: transport::Error<E>) -> Self {
+        Error::Transport(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Transport(err) => write!(f, "{}", err),
+            Error::NotSynchronised(response) => {
+                write!(f, "the MPSSE is not in sync, it sent {:02X?}", response)
+            }
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+/// Run the start-up sequence over `transport`, checking synchronisation.
+pub fn init<T: Transport + ?Sized>(
+    transport: &mut T,
+    config: InitConfig,
+    timeout: Duration,
+) -> Result<(), Error<T::Error>> {
+    let commands = Builder::new()
+        .init(config.with_sync(true))
+        .then()
+        .into_command_list();
+    let response = transport::execute(transport, commands, timeout)?;
+
+    match is_synchronised(&response) {
+        true => Ok(()),
+        false => Err(Error::NotSynchronised(response)),
+    }
+}
+
+#[cfg(test)]
+mod init_tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn slow_clocks_use_divide_by_5() {
+        let config =
+            InitConfig::new(Chip::Ft2232h, Profile::find("ft232h").unwrap()).with_frequency(400.0);
+
+        assert_eq!(config.clock(), (true, 14999));
+        assert_eq!(config.frequency(), 400.0);
+
+        let config = config.with_frequency(30_000_000.0);
+        assert_eq!(config.clock(), (false, 0));
+    }
+
+    #[test]
+    fn ft2232d_skips_h_series_commands() {
+        let config = InitConfig::new(Chip::Ft2232d, Profile::find("ft232h").unwrap())
+            .with_frequency(6_000_000.0)
+            .with_sync(false);
+
+        assert_eq!(
+            Builder::new().init(config).build(),
+            vec![0x85, 0x86, 0x00, 0x00, 0x80, 0x08, 0x0B, 0x82, 0x00, 0x00, 0x87]
+        );
+    }
+
+    #[test]
+    fn init_checks_sync() {
+        let config = InitConfig::new(Chip::Ft232h, Profile::find("ft232h").unwrap());
+
+        let mut transport = MockTransport::with_responses(&[0xFA, 0xAA, 0xFA, 0xAB]);
+        init(&mut transport, config.clone(), Duration::from_millis(10)).unwrap();
+
+        // A leftover byte from before shifts the response.
+        let mut transport = MockTransport::with_responses(&[0x00, 0xFA, 0xAA, 0xFA]);
+        assert!(matches!(
+            init(&mut transport, config, Duration::from_millis(10)),
+            Err(Error::NotSynchronised(_))
+        ));
+    }
+}
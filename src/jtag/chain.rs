@@ -0,0 +1,362 @@
+//! Scan chain discovery and IDCODE decoding.
+use std::fmt;
+
+use super::{from_bits, Error, Jtag, TapState};
+use crate::transport::Transport;
+
+/// The most devices chain discovery will look for.
+pub const MAX_DEVICES: usize = 32;
+
+/// The longest total instruction register length chain discovery will measure.
+pub const MAX_IR_LENGTH: usize = 1024;
+
+/// The devices on a scan chain, described by the length of each one's instruction register.
+///
+/// Devices are numbered from the one closest to TDO, which is also the order their IDCODEs
+/// come out in during [discovery](Jtag::discover_chain). Scans for a selected device put every
+/// other device in BYPASS, so drivers can act as if their device were alone on the chain.
+///
+/// ```
+/// use mpsse::jtag::Chain;
+///
+/// // An MCU nearest TDO, then a CPLD, then an FPGA nearest TDI.
+/// let chain = Chain::new(vec![4, 8, 6]);
+///
+/// assert_eq!(chain.pad_ir(1, &[false; 8]), [vec![true; 4], vec![false; 8], vec![true; 6]].concat());
+/// assert_eq!(chain.pad_dr(1, &[true; 32]).len(), 34);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chain {
+    ir_lengths: Vec<usize>,
+}
+
+impl Chain {
+    /// Describe a chain with the given instruction register lengths, starting nearest TDO.
+    pub fn new(ir_lengths: Vec<usize>) -> Self {
+        Chain { ir_lengths }
+    }
+
+    /// The instruction register length of each device, starting nearest TDO.
+    pub fn ir_lengths(&self) -> &[usize] {
+        &self.ir_lengths
+    }
+
+    /// The number of devices on the chain.
+    pub fn len(&self) -> usize {
+        self.ir_lengths.len()
+    }
+
+    /// Whether the chain has no devices.
+    pub fn is_empty(&self) -> bool {
+        self.ir_lengths.is_empty()
+    }
+
+    /// The total length of all the instruction registers in the chain.
+    pub fn ir_length(&self) -> usize {
+        self.ir_lengths.iter().sum()
+    }
+
+    /// Pad an instruction for device `index` with the all-ones BYPASS instruction for every
+    /// other device.
+    ///
+    /// Panics if `bits` is not as long as the device's instruction register.
+    pub fn pad_ir(&self, index: usize, bits: &[bool]) -> Vec<bool> {
+        assert_eq!(
+            bits.len(),
+            self.ir_lengths[index],
+            "instruction length does not match device {}",
+            index
+        );
+        let before: usize = self.ir_lengths[..index].iter().sum();
+        let after: usize = self.ir_lengths[index + 1..].iter().sum();
+
+        [vec![true; before], bits.to_vec(), vec![true; after]].concat()
+    }
+
+    /// Pad data for device `index` with a bit for every other device's BYPASS register.
+    pub fn pad_dr(&self, index: usize, bits: &[bool]) -> Vec<bool> {
+        let after = self.len() - index - 1;
+
+        [vec![false; index], bits.to_vec(), vec![false; after]].concat()
+    }
+
+    /// Take device `index`'s bits out of those captured by a padded instruction register scan.
+    ///
+    /// Panics if `captured` is not as long as the whole chain's instruction register.
+    pub fn unpad_ir(&self, index: usize, captured: &[bool]) -> Vec<bool> {
+        assert_eq!(
+            captured.len(),
+            self.ir_length(),
+            "captured length does not match the chain"
+        );
+        let before: usize = self.ir_lengths[..index].iter().sum();
+
+        captured[before..before + self.ir_lengths[index]].to_vec()
+    }
+
+    /// Take device `index`'s bits out of those captured by a padded data register scan.
+    pub fn unpad_dr(&self, index: usize, captured: &[bool]) -> Vec<bool> {
+        let after = self.len() - index - 1;
+
+        captured[index..captured.len() - after].to_vec()
+    }
+}
+
+/// A JEDEC JEP106 manufacturer code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Jep106 {
+    /// The number of continuation codes, i.e. the bank number counting from 0.
+    pub bank: u8,
+    /// The 7-bit manufacturer ID within the bank.
+    pub id: u8,
+}
+
+impl Jep106 {
+    /// The manufacturer's name, for a selection of manufacturers commonly found on JTAG chains.
+    pub fn name(&self) -> Option<&'static str> {
+        let name = match (self.bank, self.id) {
+            (0, 0x01) => "AMD",
+            (0, 0x04) => "Fujitsu",
+            (0, 0x09) => "Intel",
+            (0, 0x0E) => "Freescale",
+            (0, 0x15) => "NXP",
+            (0, 0x17) => "Texas Instruments",
+            (0, 0x1F) => "Atmel",
+            (0, 0x20) => "STMicroelectronics",
+            (0, 0x21) => "Lattice",
+            (0, 0x24) => "IBM",
+            (0, 0x29) => "Microchip",
+            (0, 0x2C) => "Micron",
+            (0, 0x2F) => "Actel",
+            (0, 0x34) => "Cypress",
+            (0, 0x41) => "Infineon",
+            (0, 0x42) => "Macronix",
+            (0, 0x49) => "Xilinx",
+            (0, 0x6E) => "Altera",
+            (4, 0x3B) => "ARM",
+            (9, 0x09) => "SiFive",
+            _ => return None,
+        };
+
+        Some(name)
+    }
+}
+
+impl fmt::Display for Jep106 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "unknown ({}:{:#04x})", self.bank, self.id),
+        }
+    }
+}
+
+/// A 32-bit JTAG device identification code.
+///
+/// ```
+/// use mpsse::jtag::IdCode;
+///
+/// let idcode = IdCode(0x4BA00477);
+///
+/// assert_eq!(idcode.manufacturer().name(), Some("ARM"));
+/// assert_eq!(idcode.part(), 0xBA00);
+/// assert_eq!(idcode.version(), 0x4);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IdCode(pub u32);
+
+impl IdCode {
+    /// The manufacturer, from bits 11 to 1.
+    pub fn manufacturer(&self) -> Jep106 {
+        Jep106 {
+            bank: (self.0 >> 8 & 0x0F) as u8,
+            id: (self.0 >> 1 & 0x7F) as u8,
+        }
+    }
+
+    /// The part number, from bits 27 to 12.
+    pub fn part(&self) -> u16 {
+        (self.0 >> 12) as u16
+    }
+
+    /// The version, from bits 31 to 28.
+    pub fn version(&self) -> u8 {
+        (self.0 >> 28) as u8
+    }
+
+    /// Whether this looks like a real IDCODE: bit 0 must be set, and the manufacturer ID can't
+    /// be the JEP106 continuation code.
+    pub fn is_valid(&self) -> bool {
+        self.0 & 1 == 1 && self.manufacturer().id != 0x7F
+    }
+}
+
+impl fmt::Display for IdCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:#010x} ({}, part {:#06x}, version {})",
+            self.0,
+            self.manufacturer(),
+            self.part(),
+            self.version()
+        )
+    }
+}
+
+/// The devices found on a scan chain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discovery {
+    /// Each device's IDCODE, or `None` for devices that came up in BYPASS, starting from the
+    /// device closest to TDO.
+    pub devices: Vec<Option<IdCode>>,
+    /// The total length of all the instruction registers in the chain.
+    pub ir_length: usize,
+}
+
+/// Split the data registers read after a reset into devices, until the ones shifted in from TDI
+/// come back out.
+fn parse_idcodes(bits: &[bool]) -> Option<Vec<Option<IdCode>>> {
+    let mut devices = Vec::new();
+    let mut index = 0;
+
+    while index < bits.len() {
+        if !bits[index] {
+            devices.push(None);
+            index += 1;
+            continue;
+        }
+
+        let idcode = bits.get(index..index + 32)?;
+        let idcode = from_bits(idcode) as u32;
+        if idcode == 0xFFFF_FFFF {
+            return Some(devices);
+        }
+
+        devices.push(Some(IdCode(idcode)));
+        index += 32;
+    }
+
+    None
+}
+
+/// Find the length of the chain's instruction registers, after shifting `MAX_IR_LENGTH` zeros
+/// followed by as many ones through them.
+fn parse_ir_length(bits: &[bool]) -> Option<usize> {
+    bits[MAX_IR_LENGTH..]
+        .iter()
+        .position(|bit| *bit)
+        .filter(|length| *length > 0)
+}
+
+impl<T: Transport> Jtag<T> {
+    /// Find the devices on the scan chain.
+    ///
+    /// This resets the TAP controllers, which loads IDCODE (or BYPASS, for devices without
+    /// one) into every data register, then shifts them out with ones following behind. It then
+    /// measures the total instruction register length by shifting zeros and then ones through,
+    /// which leaves every device in BYPASS.
+    ///
+    /// Any selected device is ignored while discovering the chain.
+    pub fn discover_chain(&mut self) -> Result<Discovery, Error<T::Error>> {
+        let device = self.device.take();
+        let discovery = self.discover();
+        self.device = device;
+
+        discovery
+    }
+
+    fn discover(&mut self) -> Result<Discovery, Error<T::Error>> {
+        self.reset()?;
+
+        let ones = vec![true; 32 * (MAX_DEVICES + 1)];
+        let bits = self.scan_dr(&ones, TapState::RunTestIdle)?;
+        let devices = parse_idcodes(&bits).ok_or(Error::BrokenChain)?;
+
+        let mut pattern = vec![false; MAX_IR_LENGTH];
+        pattern.extend_from_slice(&[true; MAX_IR_LENGTH]);
+        let bits = self.scan_ir(&pattern, TapState::RunTestIdle)?;
+        let ir_length = parse_ir_length(&bits).ok_or(Error::BrokenChain)?;
+
+        match devices.is_empty() || ir_length < devices.len() {
+            true => Err(Error::BrokenChain),
+            false => Ok(Discovery { devices, ir_length }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod padding_tests {
+    use super::*;
+
+    #[test]
+    fn unpad_reverses_pad() {
+        let chain = Chain::new(vec![4, 8, 6]);
+        let ir = [true, false, true, false, false, true, true, false];
+        let dr = vec![false, true, true];
+
+        for index in 0..chain.len() {
+            let ir = &ir[..chain.ir_lengths()[index]];
+            assert_eq!(chain.unpad_ir(index, &chain.pad_ir(index, ir)), ir);
+            assert_eq!(chain.unpad_dr(index, &chain.pad_dr(index, &dr)), dr);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn pad_ir_checks_length() {
+        Chain::new(vec![4, 8, 6]).pad_ir(1, &[false; 6]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unpad_ir_checks_length() {
+        Chain::new(vec![4, 8, 6]).unpad_ir(1, &[false; 17]);
+    }
+}
+
+#[cfg(test)]
+mod chain_tests {
+    use super::*;
+    use crate::jtag::to_bits;
+
+    #[test]
+    fn idcodes_and_bypass() {
+        let mut bits = to_bits(0x0362_D093, 32);
+        bits.push(false);
+        bits.extend(to_bits(0x4BA0_0477, 32));
+        bits.extend(vec![true; 64]);
+
+        assert_eq!(
+            parse_idcodes(&bits),
+            Some(vec![
+                Some(IdCode(0x0362_D093)),
+                None,
+                Some(IdCode(0x4BA0_0477))
+            ])
+        );
+    }
+
+    #[test]
+    fn stuck_tdo_is_broken() {
+        assert_eq!(parse_idcodes(&[false; 100]), None);
+        assert_eq!(parse_idcodes(&[true; 20]), None);
+    }
+
+    #[test]
+    fn ir_length() {
+        let mut bits = vec![true, false, false, false, true, false];
+        bits.extend(vec![false; MAX_IR_LENGTH + 4]);
+        bits.extend(vec![true; MAX_IR_LENGTH - 10]);
+
+        assert_eq!(parse_ir_length(&bits), Some(10));
+    }
+
+    #[test]
+    fn manufacturer_names() {
+        assert_eq!(IdCode(0x0362_D093).manufacturer().name(), Some("Xilinx"));
+        assert_eq!(IdCode(0x020F_10DD).manufacturer().name(), Some("Altera"));
+        assert_eq!(IdCode(0x0127_0043).manufacturer().name(), Some("Lattice"));
+        assert!(!IdCode(0x0000_00FF).is_valid());
+    }
+}
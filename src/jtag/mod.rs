@@ -0,0 +1,931 @@
+//! JTAG (IEEE 1149.1) support.
+//!
+//! The [`Builder`] tracks the state of the TAP controller as commands are added, so moving
+//! between states always takes the shortest TMS sequence.
+//!
+//! ```
+//! use mpsse::Builder;
+//! use mpsse::jtag::TapState;
+//!
+//! let commands = Builder::new()
+//!     .reset_tap()
+//!     .then()
+//!     .goto(TapState::ShiftDR)
+//!     .build();
+//!
+//! assert_eq!(commands, vec![0x4B, 0x04, 0x1F, 0x4B, 0x03, 0x02]);
+//! ```
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+
+use crate::builder::Builder;
+use crate::command::{BitDirection, ClockEdge, Command, DataShiftOptions};
+use crate::transport::{self, Transport};
+
+mod chain;
+
+pub use chain::{Chain, Discovery, IdCode, Jep106};
+
+/// The most TMS bits a single TMS shifting command can clock out.
+const MAX_TMS_BITS: usize = 7;
+
+/// The most bytes a single data shifting command can clock out.
+const MAX_SHIFT_BYTES: usize = 0x10000;
+
+/// The most bytes a single clock-only command can clock.
+const MAX_CLOCK_BYTES: usize = 0xFFFF;
+
+/// JTAG writes TDI and TMS on the falling edge, and reads TDO on the rising edge.
+const SHIFT_OPTIONS: DataShiftOptions = DataShiftOptions {
+    clock_direction: ClockEdge::Falling,
+    bit_direction: BitDirection::LsbFirst,
+};
+
+/// A state of the TAP controller.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TapState {
+    TestLogicReset,
+    RunTestIdle,
+    SelectDRScan,
+    CaptureDR,
+    ShiftDR,
+    Exit1DR,
+    PauseDR,
+    Exit2DR,
+    UpdateDR,
+    SelectIRScan,
+    CaptureIR,
+    ShiftIR,
+    Exit1IR,
+    PauseIR,
+    Exit2IR,
+    UpdateIR,
+}
+
+impl TapState {
+    /// Every TAP controller state.
+    pub const ALL: [TapState; 16] = [
+        TapState::TestLogicReset,
+        TapState::RunTestIdle,
+        TapState::SelectDRScan,
+        TapState::CaptureDR,
+        TapState::ShiftDR,
+        TapState::Exit1DR,
+        TapState::PauseDR,
+        TapState::Exit2DR,
+        TapState::UpdateDR,
+        TapState::SelectIRScan,
+        TapState::CaptureIR,
+        TapState::ShiftIR,
+        TapState::Exit1IR,
+        TapState::PauseIR,
+        TapState::Exit2IR,
+        TapState::UpdateIR,
+    ];
+
+    /// The state the TAP controller moves to after a clock with the given TMS level.
+    pub fn next(self, tms: bool) -> TapState {
+        use TapState::*;
+
+        match (self, tms) {
+            (TestLogicReset, false) => RunTestIdle,
+            (TestLogicReset, true) => TestLogicReset,
+            (RunTestIdle, false) => RunTestIdle,
+            (RunTestIdle, true) => SelectDRScan,
+            (SelectDRScan, false) => CaptureDR,
+            (SelectDRScan, true) => SelectIRScan,
+            (CaptureDR, false) => ShiftDR,
+            (CaptureDR, true) => Exit1DR,
+            (ShiftDR, false) => ShiftDR,
+            (ShiftDR, true) => Exit1DR,
+            (Exit1DR, false) => PauseDR,
+            (Exit1DR, true) => UpdateDR,
+            (PauseDR, false) => PauseDR,
+            (PauseDR, true) => Exit2DR,
+            (Exit2DR, false) => ShiftDR,
+            (Exit2DR, true) => UpdateDR,
+            (UpdateDR, false) => RunTestIdle,
+            (UpdateDR, true) => SelectDRScan,
+            (SelectIRScan, false) => CaptureIR,
+            (SelectIRScan, true) => TestLogicReset,
+            (CaptureIR, false) => ShiftIR,
+            (CaptureIR, true) => Exit1IR,
+            (ShiftIR, false) => ShiftIR,
+            (ShiftIR, true) => Exit1IR,
+            (Exit1IR, false) => PauseIR,
+            (Exit1IR, true) => UpdateIR,
+            (PauseIR, false) => PauseIR,
+            (PauseIR, true) => Exit2IR,
+            (Exit2IR, false) => ShiftIR,
+            (Exit2IR, true) => UpdateIR,
+            (UpdateIR, false) => RunTestIdle,
+            (UpdateIR, true) => SelectDRScan,
+        }
+    }
+
+    /// Whether the TAP controller can stay in this state while clocking.
+    pub fn is_stable(self) -> bool {
+        matches!(
+            self,
+            TapState::TestLogicReset
+                | TapState::RunTestIdle
+                | TapState::ShiftDR
+                | TapState::PauseDR
+                | TapState::ShiftIR
+                | TapState::PauseIR
+        )
+    }
+
+    /// The shortest sequence of TMS levels that moves from this state to `target`.
+    ///
+    /// ```
+    /// use mpsse::jtag::TapState;
+    ///
+    /// assert_eq!(
+    ///     TapState::RunTestIdle.path_to(TapState::ShiftIR),
+    ///     vec![true, true, false, false]
+    /// );
+    /// assert!(TapState::ShiftDR.path_to(TapState::ShiftDR).is_empty());
+    /// ```
+    pub fn path_to(self, target: TapState) -> Vec<bool> {
+        let mut paths: Vec<(TapState, Vec<bool>)> = vec![(self, Vec::new())];
+        let mut queue = VecDeque::new();
+        queue.push_back(0);
+
+        while let Some(index) = queue.pop_front() {
+            let (state, path) = paths[index].clone();
+            if state == target {
+                return path;
+            }
+
+            for tms in [false, true].iter() {
+                let next = state.next(*tms);
+                if paths.iter().all(|(seen, _)| *seen != next) {
+                    let mut next_path = path.clone();
+                    next_path.push(*tms);
+                    paths.push((next, next_path));
+                    queue.push_back(paths.len() - 1);
+                }
+            }
+        }
+
+        unreachable!("every TAP state is reachable from every other")
+    }
+}
+
+/// Split the lowest `length` bits of `value` into a bit vector, least significant bit first.
+///
+/// ```
+/// use mpsse::jtag::to_bits;
+///
+/// assert_eq!(to_bits(0b1101, 4), vec![true, false, true, true]);
+/// ```
+pub fn to_bits(value: u64, length: usize) -> Vec<bool> {
+    (0..length).map(|i| i < 64 && value >> i & 1 == 1).collect()
+}
+
+/// Join a bit vector, least significant bit first, into a value.
+///
+/// ```
+/// use mpsse::jtag::from_bits;
+///
+/// assert_eq!(from_bits(&[true, false, true, true]), 0b1101);
+/// ```
+pub fn from_bits(bits: &[bool]) -> u64 {
+    bits.iter()
+        .take(64)
+        .enumerate()
+        .fold(0, |acc, (i, bit)| acc | (*bit as u64) << i)
+}
+
+/// Pack a bit vector into bytes, least significant bit first.
+fn pack_bits(bits: &[bool]) -> Vec<u8> {
+    bits.chunks(8).map(|chunk| from_bits(chunk) as u8).collect()
+}
+
+/// The number of response bytes a captured scan of `length` bits produces.
+pub fn scan_response_length(length: usize) -> usize {
+    match length {
+        0 => 0,
+        _ => (length - 1).div_ceil(8) + 1,
+    }
+}
+
+/// Decode the TDO bits captured by a scan of `length` bits from its response.
+///
+/// ```
+/// use mpsse::jtag::decode_scan;
+///
+/// // 8 bits are captured as a 7 bit shift, then a TMS shift for the last bit.
+/// let bits = decode_scan(&[0xB4, 0x80], 8);
+///
+/// assert_eq!(bits, vec![false, true, false, true, true, false, true, true]);
+/// ```
+pub fn decode_scan(response: &[u8], length: usize) -> Vec<bool> {
+    if length == 0 {
+        return Vec::new();
+    }
+
+    let bytes = (length - 1) / 8;
+    let remainder = (length - 1) % 8;
+
+    let mut bits: Vec<bool> = response[..bytes]
+        .iter()
+        .flat_map(|byte| to_bits(*byte as u64, 8))
+        .collect();
+    let mut index = bytes;
+    if remainder != 0 {
+        bits.extend(to_bits(
+            (response[index] >> (8 - remainder)) as u64,
+            remainder,
+        ));
+        index += 1;
+    }
+    bits.push(response[index] & 0x80 == 0x80);
+
+    bits
+}
+
+/// Add clock-only commands pulsing TCK `cycles` times, leaving TMS and TDI where they are.
+pub(crate) fn push_clocks(commands: &mut Vec<Command>, cycles: usize) {
+    let mut bytes = cycles / 8;
+    while bytes > 0 {
+        let length = bytes.min(MAX_CLOCK_BYTES);
+        commands.push(Command::ClockBytes {
+            length: length as u16,
+        });
+        bytes -= length;
+    }
+
+    if !cycles.is_multiple_of(8) {
+        commands.push(Command::ClockBits {
+            length: (cycles % 8) as u8,
+        });
+    }
+}
+
+/// Add TMS shifting commands clocking out `tms` with TDI held at `tdi`.
+pub(crate) fn push_tms(commands: &mut Vec<Command>, tms: &[bool], tdi: bool) {
+    for chunk in tms.chunks(MAX_TMS_BITS) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u8, |acc, (i, bit)| acc | (*bit as u8) << i);
+
+        commands.push(Command::WriteTmsBits {
+            clock_direction: ClockEdge::Falling,
+            bits: bits | (tdi as u8) << 7,
+            length: chunk.len() as u8,
+        });
+    }
+}
+
+impl Builder {
+    /// The state the TAP controller will be in after the current command list, if known.
+    ///
+    /// This is unknown until the TAP has been reset, or moved to a state with `.goto()`.
+    pub fn tap_state(&self) -> Option<TapState> {
+        self.tap_state
+    }
+
+    /// Reset the TAP controller, by clocking TMS high five times.
+    ///
+    /// This will generate TMS shifting commands, leaving the TAP in Test-Logic-Reset.
+    ///
+    /// ```
+    /// use mpsse::Builder;
+    /// use mpsse::jtag::TapState;
+    ///
+    /// let builder = Builder::new().reset_tap().then();
+    /// assert_eq!(builder.tap_state(), Some(TapState::TestLogicReset));
+    /// assert_eq!(builder.build(), vec![0x4B, 0x04, 0x1F]);
+    /// ```
+    pub fn reset_tap(self) -> GotoBuilder {
+        GotoBuilder {
+            parent: self,
+            target: TapState::TestLogicReset,
+            reset: true,
+            tdi: false,
+        }
+    }
+
+    /// Address one device on a multi-device scan chain.
+    ///
+    /// Every later scan is padded so that the other devices are in BYPASS, and only the
+    /// selected device sees the given instruction and data.
+    ///
+    /// * `chain` - The devices on the scan chain.
+    /// * `index` - Which device to address, counting from the one nearest TDO.
+    ///
+    /// Panics if `index` is not a device on `chain`.
+    ///
+    /// ```
+    /// use mpsse::Builder;
+    /// use mpsse::jtag::Chain;
+    ///
+    /// let builder = Builder::new()
+    ///     .select_device(Chain::new(vec![4, 6]), 1)
+    ///     .scan_dr(&[true; 8], true)
+    ///     .then();
+    ///
+    /// // 8 data bits, plus one for the device in BYPASS.
+    /// assert_eq!(builder.expected_response_length(), 2);
+    /// ```
+    pub fn select_device(self, chain: Chain, index: usize) -> Self {
+        assert!(
+            index < chain.len(),
+            "device {} is not on a chain of {} devices",
+            index,
+            chain.len()
+        );
+
+        Builder {
+            jtag_device: Some((chain, index)),
+            ..self
+        }
+    }
+
+    /// Stop padding scans, and address the whole chain directly.
+    pub fn deselect_device(self) -> Self {
+        Builder {
+            jtag_device: None,
+            ..self
+        }
+    }
+
+    /// Shift `bits` into the instruction register, first bit first.
+    ///
+    /// This moves the TAP to Shift-IR, shifts all but the last bit with data shifting commands,
+    /// then shifts the last bit with a TMS shifting command to leave Shift-IR. By default the TAP
+    /// ends in Run-Test/Idle and TDO is not captured.
+    ///
+    /// If a device is selected, `bits` must be as long as its instruction register, or the scan
+    /// panics when committed.
+    ///
+    /// ```
+    /// use mpsse::Builder;
+    /// use mpsse::jtag::TapState;
+    ///
+    /// let commands = Builder::new()
+    ///     .reset_tap()
+    ///     .then()
+    ///     .scan_ir(&[false, true, true, true])
+    ///     .build();
+    ///
+    /// assert_eq!(
+    ///     commands,
+    ///     vec![0x4B, 0x04, 0x1F, 0x4B, 0x04, 0x06, 0x1B, 0x02, 0x06, 0x4B, 0x00, 0x81, 0x4B, 0x01, 0x01]
+    /// );
+    /// ```
+    pub fn scan_ir(self, bits: &[bool]) -> ScanBuilder {
+        ScanBuilder {
+            parent: self,
+            register: Register::Instruction,
+            bits: bits.to_vec(),
+            capture: false,
+            end_state: TapState::RunTestIdle,
+        }
+    }
+
+    /// Shift `bits` into the selected data register, first bit first.
+    ///
+    /// This works the same way as `.scan_ir()`, but on the data register.
+    ///
+    /// * `capture` - Whether to capture the bits shifted out of TDO. The response can be
+    ///   decoded with [`decode_scan`].
+    ///
+    /// ```
+    /// use mpsse::Builder;
+    /// use mpsse::jtag::{decode_scan, TapState};
+    ///
+    /// let builder = Builder::new()
+    ///     .reset_tap()
+    ///     .then()
+    ///     .scan_dr(&[true; 12], true)
+    ///     .with_end_state(TapState::PauseDR)
+    ///     .then();
+    ///
+    /// assert_eq!(builder.tap_state(), Some(TapState::PauseDR));
+    /// assert_eq!(builder.expected_response_length(), 3);
+    /// assert_eq!(decode_scan(&[0xA5, 0x60, 0x00], 12).len(), 12);
+    /// ```
+    pub fn scan_dr(self, bits: &[bool], capture: bool) -> ScanBuilder {
+        ScanBuilder {
+            parent: self,
+            register: Register::Data,
+            bits: bits.to_vec(),
+            capture,
+            end_state: TapState::RunTestIdle,
+        }
+    }
+
+    /// Move the TAP controller to the given state along the shortest path.
+    ///
+    /// This will generate TMS shifting commands. If the current state is unknown, the TAP is
+    /// reset first.
+    ///
+    /// * `state` - The state to move to.
+    ///
+    /// ```
+    /// use mpsse::Builder;
+    /// use mpsse::jtag::TapState;
+    ///
+    /// let commands = Builder::new()
+    ///     .reset_tap()
+    ///     .then()
+    ///     .goto(TapState::RunTestIdle)
+    ///     .then()
+    ///     .goto(TapState::RunTestIdle)
+    ///     .build();
+    ///
+    /// assert_eq!(commands, vec![0x4B, 0x04, 0x1F, 0x4B, 0x00, 0x00]);
+    /// ```
+    pub fn goto(self, state: TapState) -> GotoBuilder {
+        GotoBuilder {
+            parent: self,
+            target: state,
+            reset: false,
+            tdi: false,
+        }
+    }
+}
+
+/// Build TMS shifting commands to move the TAP controller between states.
+#[derive(Debug)]
+pub struct GotoBuilder {
+    parent: Builder,
+    target: TapState,
+    reset: bool,
+    tdi: bool,
+}
+
+impl GotoBuilder {
+    /// Set the level TDI is held at while moving between states.
+    ///
+    /// By default, TDI is held low.
+    ///
+    /// ```
+    /// use mpsse::Builder;
+    ///
+    /// let commands = Builder::new()
+    ///     .reset_tap()
+    ///     .with_tdi(true)
+    ///     .build();
+    ///
+    /// assert_eq!(commands, vec![0x4B, 0x04, 0x9F]);
+    /// ```
+    pub fn with_tdi(self, tdi: bool) -> Self {
+        GotoBuilder { tdi, ..self }
+    }
+
+    /// Commit this command to the parent Builder.
+    fn commit(mut self) -> Builder {
+        let mut tms = Vec::new();
+        let current = match (self.reset, self.parent.tap_state) {
+            (false, Some(state)) => state,
+            _ => {
+                tms.extend_from_slice(&[true; 5]);
+                TapState::TestLogicReset
+            }
+        };
+        tms.extend(current.path_to(self.target));
+
+        push_tms(&mut self.parent.commands, &tms, self.tdi);
+        self.parent.tap_state = Some(self.target);
+
+        self.parent
+    }
+
+    builder_funcs!();
+}
+
+/// Which register a scan shifts through.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Register {
+    Instruction,
+    Data,
+}
+
+/// Build an instruction or data register scan.
+#[derive(Debug)]
+pub struct ScanBuilder {
+    parent: Builder,
+    register: Register,
+    bits: Vec<bool>,
+    capture: bool,
+    end_state: TapState,
+}
+
+impl ScanBuilder {
+    /// Set whether to capture the bits shifted out of TDO.
+    pub fn with_capture(self, capture: bool) -> Self {
+        ScanBuilder { capture, ..self }
+    }
+
+    /// Set the state to leave the TAP in once the scan is done.
+    ///
+    /// By default, the scan ends in Run-Test/Idle. This should be one of the stable states.
+    pub fn with_end_state(self, end_state: TapState) -> Self {
+        ScanBuilder { end_state, ..self }
+    }
+
+    /// Commit this scan to the parent Builder.
+    fn commit(self) -> Builder {
+        let shift_state = match self.register {
+            Register::Instruction => TapState::ShiftIR,
+            Register::Data => TapState::ShiftDR,
+        };
+        let mut parent = self.parent.goto(shift_state).then();
+
+        let bits = match (&parent.jtag_device, self.register) {
+            (Some((chain, index)), Register::Instruction) => chain.pad_ir(*index, &self.bits),
+            (Some((chain, index)), Register::Data) => chain.pad_dr(*index, &self.bits),
+            (None, _) => self.bits,
+        };
+
+        if let Some((last, body)) = bits.split_last() {
+            let commands = &mut parent.commands;
+            let bytes = body.len() / 8;
+
+            for chunk in pack_bits(&body[..bytes * 8]).chunks(MAX_SHIFT_BYTES) {
+                commands.push(match self.capture {
+                    true => Command::ReadWriteDataShiftBytes {
+                        options: SHIFT_OPTIONS,
+                        bytes: chunk.to_vec(),
+                    },
+                    false => Command::WriteDataShiftBytes {
+                        options: SHIFT_OPTIONS,
+                        bytes: chunk.to_vec(),
+                    },
+                });
+            }
+
+            let remainder = &body[bytes * 8..];
+            if !remainder.is_empty() {
+                let bits = from_bits(remainder) as u8;
+                let length = remainder.len() as u8;
+                commands.push(match self.capture {
+                    true => Command::ReadWriteDataShiftBits {
+                        options: SHIFT_OPTIONS,
+                        bits,
+                        length,
+                    },
+                    false => Command::WriteDataShiftBits {
+                        options: SHIFT_OPTIONS,
+                        bits,
+                        length,
+                    },
+                });
+            }
+
+            let bits = 0x01 | (*last as u8) << 7;
+            commands.push(match self.capture {
+                true => Command::ReadWriteTmsBits {
+                    clock_direction: ClockEdge::Falling,
+                    bits,
+                    length: 1,
+                },
+                false => Command::WriteTmsBits {
+                    clock_direction: ClockEdge::Falling,
+                    bits,
+                    length: 1,
+                },
+            });
+            parent.tap_state = Some(shift_state.next(true));
+        }
+
+        parent.goto(self.end_state).then()
+    }
+
+    builder_funcs!();
+}
+
+/// Error returned by the [`Jtag`] driver.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// Running the commands failed.
+    Transport(transport::Error<E>),
+    /// The scan chain did not behave like a chain of JTAG devices, for example because TDO is
+    /// stuck or the chain is longer than expected.
+    BrokenChain,
+    /// The selected device is not on the chain.
+    DeviceIndex(usize),
+}
+
+impl<E> From<transport::Error<E>> for Error<E> {
+    fn from(err: transport::Error<E>) -> Self {
+        Error::Transport(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Transport(err) => write!(f, "{}", err),
+            Error::BrokenChain => write!(f, "the JTAG scan chain is broken"),
+            Error::DeviceIndex(index) => write!(f, "device {} is not on the scan chain", index),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+/// Run JTAG operations over a [`Transport`], keeping track of the TAP state between transfers.
+#[derive(Debug)]
+pub struct Jtag<T> {
+    transport: T,
+    timeout: Duration,
+    tap_state: Option<TapState>,
+    device: Option<(Chain, usize)>,
+}
+
+impl<T: Transport> Jtag<T> {
+    /// Create a driver using the given transport, with the
+    /// [default timeout](transport::DEFAULT_TIMEOUT).
+    ///
+    /// The TAP state is unknown until the first reset or `.goto()`.
+    pub fn new(transport: T) -> Self {
+        Jtag {
+            transport,
+            timeout: transport::DEFAULT_TIMEOUT,
+            tap_state: None,
+            device: None,
+        }
+    }
+
+    /// Set how long to wait for the device to respond.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Jtag { timeout, ..self }
+    }
+
+    /// Get the underlying transport.
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Get back the underlying transport.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// The state the TAP controller is in, if known.
+    pub fn tap_state(&self) -> Option<TapState> {
+        self.tap_state
+    }
+
+    /// Address one device on a multi-device scan chain, see [`Builder::select_device`].
+    ///
+    /// Scans run through this driver then only see the selected device's bits.
+    pub fn select_device(&mut self, chain: Chain, index: usize) -> Result<(), Error<T::Error>> {
+        if index >= chain.len() {
+            return Err(Error::DeviceIndex(index));
+        }
+
+        self.device = Some((chain, index));
+        Ok(())
+    }
+
+    /// Stop padding scans, and address the whole chain directly.
+    pub fn deselect_device(&mut self) {
+        self.device = None;
+    }
+
+    /// Start a command list that carries on from the TAP's current state, with the selected
+    /// device (if any) addressed.
+    pub fn builder(&self) -> Builder {
+        Builder {
+            tap_state: self.tap_state,
+            jtag_device: self.device.clone(),
+            ..Builder::new()
+        }
+    }
+
+    /// Run commands started with `.builder()`, flushing and returning their response.
+    pub fn execute(&mut self, builder: Builder) -> Result<Vec<u8>, Error<T::Error>> {
+        let tap_state = builder.tap_state();
+        let response = transport::flush(&mut self.transport, builder, self.timeout);
+
+        // If the transfer failed, some of the commands may not have run.
+        self.tap_state = match response {
+            Ok(_) => tap_state,
+            Err(_) => None,
+        };
+
+        Ok(response?)
+    }
+
+    /// Reset the TAP controller to Test-Logic-Reset.
+    pub fn reset(&mut self) -> Result<(), Error<T::Error>> {
+        self.execute(self.builder().reset_tap().then())?;
+        Ok(())
+    }
+
+    /// Move the TAP controller to `state`.
+    pub fn goto(&mut self, state: TapState) -> Result<(), Error<T::Error>> {
+        self.execute(self.builder().goto(state).then())?;
+        Ok(())
+    }
+
+    /// Shift `bits` into the instruction register, returning the bits shifted out.
+    pub fn scan_ir(
+        &mut self,
+        bits: &[bool],
+        end_state: TapState,
+    ) -> Result<Vec<bool>, Error<T::Error>> {
+        let builder = self
+            .builder()
+            .scan_ir(bits)
+            .with_capture(true)
+            .with_end_state(end_state)
+            .then();
+
+        let length = match &self.device {
+            Some((chain, index)) => chain.ir_length() - chain.ir_lengths()[*index] + bits.len(),
+            None => bits.len(),
+        };
+        let captured = decode_scan(&self.execute(builder)?, length);
+
+        Ok(match &self.device {
+            Some((chain, index)) => chain.unpad_ir(*index, &captured),
+            None => captured,
+        })
+    }
+
+    /// Shift `bits` into the selected data register, returning the bits shifted out.
+    pub fn scan_dr(
+        &mut self,
+        bits: &[bool],
+        end_state: TapState,
+    ) -> Result<Vec<bool>, Error<T::Error>> {
+        let builder = self
+            .builder()
+            .scan_dr(bits, true)
+            .with_end_state(end_state)
+            .then();
+        self.execute_scan_dr(builder, bits)
+    }
+
+    /// Shift `bits` into the selected data register, then stay in Run-Test/Idle for `cycles`
+    /// clocks, returning the bits shifted out.
+    ///
+    /// This is for registers that start an operation on Update-DR and need time to finish it
+    /// before the next scan.
+    pub fn scan_dr_idle(
+        &mut self,
+        bits: &[bool],
+        cycles: usize,
+    ) -> Result<Vec<bool>, Error<T::Error>> {
+        let mut builder = self.builder().scan_dr(bits, true).then();
+        push_clocks(&mut builder.commands, cycles);
+        self.execute_scan_dr(builder, bits)
+    }
+
+    /// Run a builder ending with a captured data register scan of `bits`, returning the bits
+    /// shifted out.
+    fn execute_scan_dr(
+        &mut self,
+        builder: Builder,
+        bits: &[bool],
+    ) -> Result<Vec<bool>, Error<T::Error>> {
+        let response = self.execute(builder)?;
+        Ok(self.decode_scan_dr(&response, bits.len()))
+    }
+
+    /// Decode the bits captured by a data register scan of `length` bits from its response,
+    /// taking out the selected device's bits if the scan was padded.
+    pub(crate) fn decode_scan_dr(&self, response: &[u8], length: usize) -> Vec<bool> {
+        match &self.device {
+            Some((chain, index)) => {
+                let captured = decode_scan(response, length + chain.len() - 1);
+                chain.unpad_dr(*index, &captured)
+            }
+            None => decode_scan(response, length),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tap_state_tests {
+    use super::*;
+
+    #[test]
+    fn paths_reach_target() {
+        for from in TapState::ALL.iter() {
+            for to in TapState::ALL.iter() {
+                let end = from
+                    .path_to(*to)
+                    .iter()
+                    .fold(*from, |state, tms| state.next(*tms));
+                assert_eq!(end, *to);
+            }
+        }
+    }
+
+    #[test]
+    fn paths_are_shortest() {
+        assert_eq!(TapState::ShiftDR.path_to(TapState::ShiftIR).len(), 6);
+        assert_eq!(TapState::PauseIR.path_to(TapState::ShiftIR).len(), 2);
+        assert_eq!(
+            TapState::SelectIRScan
+                .path_to(TapState::TestLogicReset)
+                .len(),
+            1
+        );
+        assert_eq!(TapState::ShiftIR.path_to(TapState::TestLogicReset).len(), 5);
+    }
+
+    #[test]
+    fn unknown_state_resets_first() {
+        let builder = Builder::new().goto(TapState::ShiftDR).then();
+
+        assert_eq!(builder.tap_state(), Some(TapState::ShiftDR));
+        assert_eq!(builder.build(), vec![0x4B, 0x06, 0x5F, 0x4B, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn state_is_tracked_across_chain() {
+        let commands = Builder::new()
+            .reset_tap()
+            .then()
+            .goto(TapState::ShiftIR)
+            .then()
+            .goto(TapState::PauseIR)
+            .build();
+
+        assert_eq!(
+            commands,
+            vec![0x4B, 0x04, 0x1F, 0x4B, 0x04, 0x06, 0x4B, 0x01, 0x01]
+        );
+    }
+}
+
+#[cfg(test)]
+mod scan_tests {
+    use super::*;
+
+    #[test]
+    fn long_scan_uses_bytes_bits_and_tms() {
+        let bits = to_bits(0x1_2345, 17);
+        let commands = Builder::new()
+            .goto(TapState::ShiftDR)
+            .then()
+            .scan_dr(&bits, false)
+            .with_end_state(TapState::ShiftDR)
+            .build();
+
+        assert_eq!(
+            &commands[6..],
+            &[
+                0x19, 0x01, 0x00, 0x45, 0x23, // 16 bits
+                0x4B, 0x00, 0x81, // last bit, to Exit1-DR
+                0x4B, 0x02, 0x02 // back to Shift-DR
+            ]
+        );
+    }
+
+    #[test]
+    fn captured_scan_round_trips() {
+        let bits = to_bits(0xDEAD_BEEF, 32);
+        let builder = Builder::new().scan_dr(&bits, true).then();
+
+        assert_eq!(builder.expected_response_length(), scan_response_length(32));
+        assert_eq!(scan_response_length(32), 5);
+
+        // The chip shifts the 7 remaining bits in from the top of the byte, and the TMS bit into
+        // bit 7.
+        let response = [0xEF, 0xBE, 0xAD, 0x5E << 1, 0x80];
+        assert_eq!(decode_scan(&response, 32), bits);
+    }
+}
+
+#[cfg(test)]
+mod driver_tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn selected_device_scans_are_unpadded() {
+        let transport = MockTransport::with_responses(&[0xAB, 0x80]);
+        let mut jtag = Jtag::new(transport);
+        jtag.select_device(Chain::new(vec![4, 6]), 1).unwrap();
+
+        let captured = jtag.scan_dr(&[false; 8], TapState::RunTestIdle).unwrap();
+
+        assert_eq!(
+            captured,
+            vec![true, false, true, false, true, false, true, true]
+        );
+        assert_eq!(jtag.tap_state(), Some(TapState::RunTestIdle));
+    }
+
+    #[test]
+    fn unknown_device_is_rejected() {
+        let mut jtag = Jtag::new(MockTransport::default());
+
+        match jtag.select_device(Chain::new(vec![4, 6]), 2) {
+            Err(Error::DeviceIndex(2)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -20,12 +20,33 @@
 //! }
 //! ```
 
+pub mod adiv5;
+pub mod batch;
+pub mod board;
+#[macro_use]
 pub mod builder;
+pub mod bsdl;
+pub mod clock;
 pub mod command;
+pub mod eeprom;
+pub mod flash;
+pub mod gpio;
+pub mod i2c;
+pub mod image;
+pub mod init;
+pub mod jtag;
+pub mod mdio;
+pub mod microwire;
+pub mod riscv;
+pub mod smbus;
+pub mod spi;
+pub mod svf;
+pub mod swd;
+pub mod transport;
+pub mod xsvf;
 
 pub use command::{
-    BitDirection, ClockEdge, PinDirection, PinDirectionArray, PinRange, PinValue,
-    PinValueArray,
+    BitDirection, ClockEdge, PinDirection, PinDirectionArray, PinRange, PinValue, PinValueArray,
 };
 
-pub use builder::Builder;
+pub use builder::{Builder, PinState};
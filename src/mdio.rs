@@ -0,0 +1,358 @@
+//! MDIO (IEEE 802.3 clause 22 and clause 45) management frames built from MPSSE commands.
+//!
+//! The pins are wired like I2C: ADBUS0 is MDC, ADBUS1 drives MDIO and ADBUS2 reads MDIO back,
+//! so ADBUS1 and ADBUS2 must be connected together. MDIO needs a pull-up, as ADBUS1 is released
+//! for the turnaround of read frames while the PHY takes over the line.
+//!
+//! ```
+//! use mpsse::Builder;
+//!
+//! // Clause 22 read of register 2 of the PHY at address 1.
+//! let commands = Builder::new().mdio().read(1, 2).build();
+//!
+//! assert_eq!(
+//!     &commands[..8],
+//!     &[0x11, 0x04, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x60]
+//! );
+//! ```
+use std::time::Duration;
+
+use crate::builder::Builder;
+use crate::command::{BitDirection, ClockEdge, Command, DataShiftOptions, PinRange};
+use crate::transport::{self, Transport};
+
+const MDC: u8 = 0x01;
+const MDIO_OUT: u8 = 0x02;
+const MDIO_IN: u8 = 0x04;
+
+/// PHY register: PHY identifier, bits 31:16.
+pub const PHYID1: u8 = 2;
+/// PHY register: PHY identifier, bits 15:0.
+pub const PHYID2: u8 = 3;
+
+/// Number of PHY (or clause 45 port) addresses.
+pub const ADDRESSES: u8 = 32;
+
+/// Data is written on the falling edge of MDC, so it is stable when the PHY samples it on the
+/// rising edge.
+const WRITE_OPTIONS: DataShiftOptions = DataShiftOptions {
+    clock_direction: ClockEdge::Falling,
+    bit_direction: BitDirection::MsbFirst,
+};
+
+const READ_OPTIONS: DataShiftOptions = DataShiftOptions {
+    clock_direction: ClockEdge::Rising,
+    bit_direction: BitDirection::MsbFirst,
+};
+
+const PREAMBLE: [u8; 4] = [0xFF; 4];
+
+/// Start of frame bits.
+const CLAUSE_22: u16 = 0b01;
+const CLAUSE_45: u16 = 0b00;
+
+/// Operation codes. Clause 22 uses only read and write.
+const OP_ADDRESS: u16 = 0b00;
+const OP_WRITE: u16 = 0b01;
+const OP_READ_22: u16 = 0b10;
+const OP_READ_45: u16 = 0b11;
+
+/// Turnaround bits driven by the STA for write frames.
+const TURNAROUND: u16 = 0b10;
+
+impl Builder {
+    /// Start building a sequence of MDIO frames.
+    pub fn mdio(self) -> MdioBuilder {
+        MdioBuilder { parent: self }
+    }
+}
+
+/// Build a sequence of MDIO frames.
+///
+/// Each read frame responds with the two bytes of the register, most significant first.
+#[derive(Debug)]
+pub struct MdioBuilder {
+    parent: Builder,
+}
+
+/// The 14 bits of a frame before the turnaround: start, operation, and two 5-bit addresses.
+fn header(start: u16, op: u16, port: u8, register: u8) -> u16 {
+    start << 12 | op << 10 | (port as u16 & 0x1F) << 5 | register as u16 & 0x1F
+}
+
+impl MdioBuilder {
+    fn set_lines(mut self, drive: bool) -> Self {
+        let direction = match drive {
+            true => MDC | MDIO_OUT,
+            false => MDC,
+        };
+        self.parent
+            .set_bits(PinRange::Low, MDC | MDIO_OUT | MDIO_IN, MDIO_OUT, direction);
+
+        self
+    }
+
+    /// Idle with MDC low and MDIO driven high.
+    pub fn setup(self) -> Self {
+        self.set_lines(true)
+    }
+
+    /// Send a frame that writes `data` after the turnaround.
+    fn write_frame(mut self, header: u16, data: u16) -> Self {
+        let frame = (header as u32) << 18 | (TURNAROUND as u32) << 16 | data as u32;
+        let mut bytes = PREAMBLE.to_vec();
+        bytes.extend_from_slice(&frame.to_be_bytes());
+        self.parent.commands.push(Command::WriteDataShiftBytes {
+            options: WRITE_OPTIONS,
+            bytes,
+        });
+
+        self
+    }
+
+    /// Send a frame's header, release MDIO for the turnaround, then read the PHY's data.
+    fn read_frame(mut self, header: u16) -> Self {
+        let mut bytes = PREAMBLE.to_vec();
+        bytes.push((header >> 6) as u8);
+        self.parent.commands.push(Command::WriteDataShiftBytes {
+            options: WRITE_OPTIONS,
+            bytes,
+        });
+        self.parent.commands.push(Command::WriteDataShiftBits {
+            options: WRITE_OPTIONS,
+            bits: (header << 2) as u8,
+            length: 6,
+        });
+
+        self = self.set_lines(false);
+        self.parent.commands.push(Command::ClockBits { length: 2 });
+        self.parent.commands.push(Command::ReadDataShiftBytes {
+            options: READ_OPTIONS,
+            length: 2,
+        });
+
+        self.set_lines(true)
+    }
+
+    /// Clause 22: read register `register` of the PHY at `phy`.
+    pub fn read(self, phy: u8, register: u8) -> Self {
+        self.read_frame(header(CLAUSE_22, OP_READ_22, phy, register))
+    }
+
+    /// Clause 22: write `value` to register `register` of the PHY at `phy`.
+    pub fn write(self, phy: u8, register: u8, value: u16) -> Self {
+        self.write_frame(header(CLAUSE_22, OP_WRITE, phy, register), value)
+    }
+
+    /// Clause 45: set the register address for the next access to `device` at `port`.
+    pub fn address_45(self, port: u8, device: u8, register: u16) -> Self {
+        self.write_frame(header(CLAUSE_45, OP_ADDRESS, port, device), register)
+    }
+
+    /// Clause 45: read the addressed register of `device` at `port`.
+    pub fn read_45(self, port: u8, device: u8) -> Self {
+        self.read_frame(header(CLAUSE_45, OP_READ_45, port, device))
+    }
+
+    /// Clause 45: write `value` to the addressed register of `device` at `port`.
+    pub fn write_45(self, port: u8, device: u8, value: u16) -> Self {
+        self.write_frame(header(CLAUSE_45, OP_WRITE, port, device), value)
+    }
+
+    /// Commit this sequence to the parent Builder.
+    fn commit(self) -> Builder {
+        self.parent
+    }
+
+    builder_funcs!();
+}
+
+/// Error returned by the [`Mdio`] driver. MDIO frames can't fail on their own, so this is the
+/// transport's.
+pub use crate::transport::Error;
+
+/// A PHY found by [`Mdio::scan`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Phy {
+    pub address: u8,
+    /// The PHY identifier, from [`PHYID1`] and [`PHYID2`].
+    pub id: u32,
+}
+
+impl Phy {
+    /// The organizationally unique identifier (OUI) bits held in the PHY identifier.
+    pub fn oui(&self) -> u32 {
+        self.id >> 10
+    }
+
+    /// The manufacturer's model number.
+    pub fn model(&self) -> u8 {
+        (self.id >> 4) as u8 & 0x3F
+    }
+
+    /// The manufacturer's revision number.
+    pub fn revision(&self) -> u8 {
+        self.id as u8 & 0x0F
+    }
+}
+
+/// Run MDIO frames over a [`Transport`].
+#[derive(Debug)]
+pub struct Mdio<T> {
+    transport: T,
+    timeout: Duration,
+}
+
+impl<T: Transport> Mdio<T> {
+    /// Create a driver using the given transport, with the
+    /// [default timeout](transport::DEFAULT_TIMEOUT).
+    pub fn new(transport: T) -> Self {
+        Mdio {
+            transport,
+            timeout: transport::DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Set how long to wait for the device to respond.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Mdio { timeout, ..self }
+    }
+
+    /// Get the underlying transport.
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Get back the underlying transport.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Run the given commands, flushing and returning their response.
+    pub fn execute(&mut self, builder: Builder) -> Result<Vec<u8>, Error<T::Error>> {
+        transport::flush(&mut self.transport, builder, self.timeout)
+    }
+
+    /// Idle with MDC low and MDIO driven high.
+    pub fn setup(&mut self) -> Result<(), Error<T::Error>> {
+        self.execute(Builder::new().mdio().setup().then())?;
+        Ok(())
+    }
+
+    /// Clause 22: read register `register` of the PHY at `phy`.
+    pub fn read(&mut self, phy: u8, register: u8) -> Result<u16, Error<T::Error>> {
+        let response = self.execute(Builder::new().mdio().read(phy, register).then())?;
+        Ok(u16::from_be_bytes([response[0], response[1]]))
+    }
+
+    /// Clause 22: write `value` to register `register` of the PHY at `phy`.
+    pub fn write(&mut self, phy: u8, register: u8, value: u16) -> Result<(), Error<T::Error>> {
+        self.execute(Builder::new().mdio().write(phy, register, value).then())?;
+        Ok(())
+    }
+
+    /// Clause 45: read `register` of `device` at `port`.
+    pub fn read_45(&mut self, port: u8, device: u8, register: u16) -> Result<u16, Error<T::Error>> {
+        let builder = Builder::new()
+            .mdio()
+            .address_45(port, device, register)
+            .read_45(port, device)
+            .then();
+
+        let response = self.execute(builder)?;
+        Ok(u16::from_be_bytes([response[0], response[1]]))
+    }
+
+    /// Clause 45: write `value` to `register` of `device` at `port`.
+    pub fn write_45(
+        &mut self,
+        port: u8,
+        device: u8,
+        register: u16,
+        value: u16,
+    ) -> Result<(), Error<T::Error>> {
+        let builder = Builder::new()
+            .mdio()
+            .address_45(port, device, register)
+            .write_45(port, device, value)
+            .then();
+
+        self.execute(builder)?;
+        Ok(())
+    }
+
+    /// Read the clause 22 ID registers at every PHY address, in a single transfer, returning
+    /// the PHYs that answered.
+    ///
+    /// Addresses where MDIO stays pulled up (or is held low) read as all ones (or zeroes), and
+    /// are skipped.
+    pub fn scan(&mut self) -> Result<Vec<Phy>, Error<T::Error>> {
+        let builder = (0..ADDRESSES).fold(Builder::new().mdio(), |builder, address| {
+            builder.read(address, PHYID1).read(address, PHYID2)
+        });
+
+        let response = self.execute(builder.then())?;
+        Ok(response
+            .chunks(4)
+            .enumerate()
+            .map(|(address, id)| Phy {
+                address: address as u8,
+                id: u32::from_be_bytes([id[0], id[1], id[2], id[3]]),
+            })
+            .filter(|phy| phy.id != 0xFFFF_FFFF && phy.id != 0)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod mdio_tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn clause_22_write_frame() {
+        let commands = Builder::new().mdio().write(0x1F, 0x00, 0x8000).build();
+
+        // 01 01 11111 00000 10, then the data.
+        assert_eq!(
+            commands,
+            vec![0x11, 0x07, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x5F, 0x82, 0x80, 0x00]
+        );
+    }
+
+    #[test]
+    fn clause_45_read_releases_mdio() {
+        let transport = MockTransport::with_responses(&[0x12, 0x34]);
+        let mut mdio = Mdio::new(transport);
+
+        assert_eq!(mdio.read_45(0x02, 0x01, 0x0007).unwrap(), 0x1234);
+
+        // The address frame, then 00 11 00010 00001, the turnaround and the read.
+        let written = &mdio.transport().written;
+        assert_eq!(&written[7..11], &[0x01, 0x06, 0x00, 0x07]);
+        assert_eq!(
+            &written[11..30],
+            &[
+                0x11, 0x04, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x31, 0x13, 0x05, 0x04, 0x80, 0x02, 0x01,
+                0x8E, 0x01, 0x20, 0x01, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn scan_skips_empty_addresses() {
+        let mut responses = vec![0xFF; 128];
+        responses[4..8].copy_from_slice(&[0x00, 0x22, 0x16, 0x22]);
+        let mut mdio = Mdio::new(MockTransport::with_responses(&responses));
+
+        let phys = mdio.scan().unwrap();
+        assert_eq!(
+            phys,
+            vec![Phy {
+                address: 1,
+                id: 0x0022_1622
+            }]
+        );
+        assert_eq!(phys[0].model(), 0x22);
+    }
+}
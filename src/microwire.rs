@@ -0,0 +1,462 @@
+//! Microwire transactions and 93Cxx EEPROMs.
+//!
+//! The pins are wired like SPI: ADBUS0 is SK, ADBUS1 drives the EEPROM's DI, ADBUS2 reads its
+//! DO and ADBUS3 is an active-high chip select. Microwire instructions are a start bit, an
+//! opcode and an address that together are rarely a whole number of bytes, so they are sent
+//! with bit shifts.
+//!
+//! ```
+//! use mpsse::Builder;
+//!
+//! // READ word 0x12 of a 93C46 in x16 mode: start bit, opcode 10, then 6 address bits.
+//! let commands = Builder::new()
+//!     .microwire()
+//!     .select()
+//!     .write_bits(0b1_10_010010, 9)
+//!     .read(2)
+//!     .deselect()
+//!     .build();
+//!
+//! assert_eq!(&commands[3..9], &[0x13, 0x07, 0xC9, 0x13, 0x00, 0x00]);
+//! ```
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::builder::Builder;
+use crate::command::{BitDirection, ClockEdge, Command, DataShiftOptions, PinRange};
+use crate::transport::{self, Transport};
+
+const SK: u8 = 0x01;
+const DI: u8 = 0x02;
+const DO: u8 = 0x04;
+const CS: u8 = 0x08;
+
+/// Data is written on the falling edge of SK, so it is stable when the EEPROM samples it on
+/// the rising edge.
+const WRITE_OPTIONS: DataShiftOptions = DataShiftOptions {
+    clock_direction: ClockEdge::Falling,
+    bit_direction: BitDirection::MsbFirst,
+};
+
+/// The EEPROM changes DO after the rising edge of SK, so it is read on the falling edge. This
+/// needs a combined read and write command, writing zeroes on the rising edge.
+const READ_OPTIONS: DataShiftOptions = DataShiftOptions {
+    clock_direction: ClockEdge::Rising,
+    bit_direction: BitDirection::MsbFirst,
+};
+
+/// Time allowed for a write or erase to finish.
+pub const WRITE_TIMEOUT: Duration = Duration::from_millis(50);
+
+impl Builder {
+    /// Start building a sequence of Microwire operations.
+    pub fn microwire(self) -> MicrowireBuilder {
+        MicrowireBuilder { parent: self }
+    }
+}
+
+/// Build a sequence of Microwire operations.
+///
+/// Each read byte responds with the byte clocked in from DO, and each status read with the low
+/// pin states (see [`is_ready`]).
+#[derive(Debug)]
+pub struct MicrowireBuilder {
+    parent: Builder,
+}
+
+impl MicrowireBuilder {
+    fn set_lines(mut self, cs: bool) -> Self {
+        let value = match cs {
+            true => CS,
+            false => 0,
+        };
+        self.parent
+            .set_bits(PinRange::Low, SK | DI | DO | CS, value, SK | DI | CS);
+
+        self
+    }
+
+    /// Drive SK low with the chip deselected.
+    pub fn setup(self) -> Self {
+        self.deselect()
+    }
+
+    /// Drive chip select high.
+    pub fn select(self) -> Self {
+        self.set_lines(true)
+    }
+
+    /// Drive chip select low.
+    pub fn deselect(self) -> Self {
+        self.set_lines(false)
+    }
+
+    /// Write the low `length` bits of `value` to DI, most significant first.
+    pub fn write_bits(mut self, value: u32, length: u8) -> Self {
+        let mut remaining = length;
+        while remaining > 0 {
+            let chunk = remaining.min(8);
+            remaining -= chunk;
+            let bits = (value >> remaining) as u8 & (0xFF >> (8 - chunk));
+            self.parent.commands.push(Command::WriteDataShiftBits {
+                options: WRITE_OPTIONS,
+                bits: bits << (8 - chunk),
+                length: chunk,
+            });
+        }
+
+        self
+    }
+
+    /// Read `length` bytes from DO.
+    pub fn read(mut self, length: usize) -> Self {
+        if length > 0 {
+            self.parent.commands.push(Command::ReadWriteDataShiftBytes {
+                options: READ_OPTIONS,
+                bytes: vec![0; length],
+            });
+        }
+
+        self
+    }
+
+    /// Read the pins, to check DO for the ready/busy status while the chip is selected.
+    pub fn read_status(mut self) -> Self {
+        self.parent.commands.push(Command::ReadBits {
+            range: PinRange::Low,
+        });
+
+        self
+    }
+
+    /// Commit this sequence to the parent Builder.
+    fn commit(self) -> Builder {
+        self.parent
+    }
+
+    builder_funcs!();
+}
+
+/// Whether the EEPROM has finished writing, given the response to a status read.
+pub fn is_ready(response: u8) -> bool {
+    response & DO == DO
+}
+
+/// A 93Cxx part.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Part {
+    /// 93C46: 1 Kbit.
+    C46,
+    /// 93C56: 2 Kbit.
+    C56,
+    /// 93C66: 4 Kbit.
+    C66,
+    /// 93C76: 8 Kbit.
+    C76,
+    /// 93C86: 16 Kbit.
+    C86,
+}
+
+/// How the EEPROM's memory is organised, set by its ORG pin.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Organisation {
+    X8,
+    X16,
+}
+
+impl Organisation {
+    /// The number of bits in a word.
+    pub fn word_bits(self) -> u8 {
+        match self {
+            Organisation::X8 => 8,
+            Organisation::X16 => 16,
+        }
+    }
+}
+
+impl Part {
+    /// The size in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            Part::C46 => 128,
+            Part::C56 => 256,
+            Part::C66 => 512,
+            Part::C76 => 1024,
+            Part::C86 => 2048,
+        }
+    }
+
+    /// The number of address bits in each instruction.
+    ///
+    /// The 93C56 and 93C76 take the same number as the next size up, with the top bit unused.
+    pub fn address_bits(self, organisation: Organisation) -> u8 {
+        let x16 = match self {
+            Part::C46 => 6,
+            Part::C56 | Part::C66 => 8,
+            Part::C76 | Part::C86 => 10,
+        };
+        match organisation {
+            Organisation::X8 => x16 + 1,
+            Organisation::X16 => x16,
+        }
+    }
+
+    /// The number of words.
+    pub fn words(self, organisation: Organisation) -> usize {
+        self.size() * 8 / organisation.word_bits() as usize
+    }
+}
+
+const READ: u32 = 0b10;
+const WRITE: u32 = 0b01;
+const ERASE: u32 = 0b11;
+const EXTENDED: u32 = 0b00;
+
+/// Extended instructions, sent in the top two address bits.
+const EWDS: u32 = 0b00;
+const ERAL: u32 = 0b10;
+const EWEN: u32 = 0b11;
+
+/// Error returned by the [`Eeprom`] driver.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// Running the commands failed.
+    Transport(transport::Error<E>),
+    /// The EEPROM did not finish a write or erase in time.
+    Busy,
+    /// A word address was past the end of the EEPROM.
+    OutOfRange(usize),
+}
+
+impl<E> From<transport::Error<E>> for Error<E> {
+    fn from(err: transport::Error<E>) -> Self {
+        Error::Transport(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Transport(err) => write!(f, "{}", err),
+            Error::Busy => write!(f, "the EEPROM did not finish writing"),
+            Error::OutOfRange(address) => write!(f, "word {:#x} is past the end", address),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+/// A 93Cxx Microwire EEPROM, addressed in words of its organisation.
+#[derive(Debug)]
+pub struct Eeprom<T> {
+    transport: T,
+    timeout: Duration,
+    write_timeout: Duration,
+    part: Part,
+    organisation: Organisation,
+}
+
+impl<T: Transport> Eeprom<T> {
+    /// Create a driver using the given transport, with the
+    /// [default timeout](transport::DEFAULT_TIMEOUT).
+    pub fn new(transport: T, part: Part, organisation: Organisation) -> Self {
+        Eeprom {
+            transport,
+            timeout: transport::DEFAULT_TIMEOUT,
+            write_timeout: WRITE_TIMEOUT,
+            part,
+            organisation,
+        }
+    }
+
+    /// Set how long to wait for the device to respond.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Eeprom { timeout, ..self }
+    }
+
+    /// Set how long to poll for a write or erase to finish.
+    pub fn with_write_timeout(self, write_timeout: Duration) -> Self {
+        Eeprom {
+            write_timeout,
+            ..self
+        }
+    }
+
+    /// Get the underlying transport.
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Get back the underlying transport.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Run the given commands, flushing and returning their response.
+    pub fn execute(&mut self, builder: Builder) -> Result<Vec<u8>, Error<T::Error>> {
+        Ok(transport::flush(
+            &mut self.transport,
+            builder,
+            self.timeout,
+        )?)
+    }
+
+    /// Drive SK low with the chip deselected.
+    pub fn setup(&mut self) -> Result<(), Error<T::Error>> {
+        self.execute(Builder::new().microwire().setup().then())?;
+        Ok(())
+    }
+
+    /// Add an instruction to `builder`: the start bit, `opcode`, and `address`.
+    fn instruction(
+        &self,
+        builder: MicrowireBuilder,
+        opcode: u32,
+        address: u32,
+    ) -> MicrowireBuilder {
+        let address_bits = self.part.address_bits(self.organisation);
+        let value = 1 << (address_bits + 2) | opcode << address_bits | address;
+        builder.write_bits(value, address_bits + 3)
+    }
+
+    /// Add an extended instruction, which sets the top two address bits.
+    fn extended(&self, builder: MicrowireBuilder, instruction: u32) -> MicrowireBuilder {
+        let address_bits = self.part.address_bits(self.organisation);
+        self.instruction(builder, EXTENDED, instruction << (address_bits - 2))
+    }
+
+    fn check_range(&self, address: usize, length: usize) -> Result<(), Error<T::Error>> {
+        match address + length > self.part.words(self.organisation) {
+            true => Err(Error::OutOfRange(address)),
+            false => Ok(()),
+        }
+    }
+
+    /// Read `length` words starting at word `address`, in a single transfer.
+    pub fn read(&mut self, address: usize, length: usize) -> Result<Vec<u16>, Error<T::Error>> {
+        self.check_range(address, length)?;
+
+        let word_bytes = self.organisation.word_bits() as usize / 8;
+        let mut builder = Builder::new().microwire();
+        for word in address..address + length {
+            builder = self.instruction(builder.select(), READ, word as u32);
+            builder = builder.read(word_bytes).deselect();
+        }
+
+        let response = self.execute(builder.then())?;
+        Ok(response
+            .chunks(word_bytes)
+            .map(|word| word.iter().fold(0, |value, byte| value << 8 | *byte as u16))
+            .collect())
+    }
+
+    /// Allow writes and erases (EWEN). The EEPROM powers up with them disabled.
+    pub fn write_enable(&mut self) -> Result<(), Error<T::Error>> {
+        let builder = self.extended(Builder::new().microwire().select(), EWEN);
+        self.execute(builder.deselect().then())?;
+        Ok(())
+    }
+
+    /// Disallow writes and erases (EWDS).
+    pub fn write_disable(&mut self) -> Result<(), Error<T::Error>> {
+        let builder = self.extended(Builder::new().microwire().select(), EWDS);
+        self.execute(builder.deselect().then())?;
+        Ok(())
+    }
+
+    /// Write `value` to word `address`, which needs writes enabled.
+    pub fn write(&mut self, address: usize, value: u16) -> Result<(), Error<T::Error>> {
+        self.check_range(address, 1)?;
+
+        let builder = self.instruction(Builder::new().microwire().select(), WRITE, address as u32);
+        let builder = builder.write_bits(value as u32, self.organisation.word_bits());
+        self.execute(builder.deselect().then())?;
+        self.wait_ready()
+    }
+
+    /// Erase word `address` to all ones, which needs writes enabled.
+    pub fn erase(&mut self, address: usize) -> Result<(), Error<T::Error>> {
+        self.check_range(address, 1)?;
+
+        let builder = self.instruction(Builder::new().microwire().select(), ERASE, address as u32);
+        self.execute(builder.deselect().then())?;
+        self.wait_ready()
+    }
+
+    /// Erase the whole EEPROM (ERAL), which needs writes enabled.
+    pub fn erase_all(&mut self) -> Result<(), Error<T::Error>> {
+        let builder = self.extended(Builder::new().microwire().select(), ERAL);
+        self.execute(builder.deselect().then())?;
+        self.wait_ready()
+    }
+
+    /// Select the chip and poll DO until it goes high, which shows the last write or erase has
+    /// finished.
+    fn wait_ready(&mut self) -> Result<(), Error<T::Error>> {
+        let start = Instant::now();
+        loop {
+            let builder = Builder::new().microwire().select().read_status().deselect();
+            if is_ready(self.execute(builder.then())?[0]) {
+                return Ok(());
+            }
+            if start.elapsed() > self.write_timeout {
+                return Err(Error::Busy);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod microwire_tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn read_words() {
+        let transport = MockTransport::with_responses(&[0x12, 0x34, 0x56, 0x78]);
+        let mut eeprom = Eeprom::new(transport, Part::C46, Organisation::X16);
+
+        assert_eq!(eeprom.read(0x12, 2).unwrap(), vec![0x1234, 0x5678]);
+        assert_eq!(
+            &eeprom.transport().written[..17],
+            &[
+                0x80, 0x08, 0x0B, 0x13, 0x07, 0xC9, 0x13, 0x00, 0x00, 0x34, 0x01, 0x00, 0x00, 0x00,
+                0x80, 0x00, 0x0B
+            ]
+        );
+    }
+
+    #[test]
+    fn write_polls_until_ready() {
+        let transport = MockTransport::with_responses(&[0x00, 0x00, DO]);
+        let mut eeprom = Eeprom::new(transport, Part::C66, Organisation::X8);
+
+        eeprom.write(0x1FF, 0xA5).unwrap();
+
+        // Start bit, opcode 01 and 9 address bits, then 8 data bits.
+        let written = &eeprom.transport().written;
+        assert_eq!(
+            &written[3..12],
+            &[0x13, 0x07, 0xBF, 0x13, 0x03, 0xF0, 0x13, 0x07, 0xA5]
+        );
+        assert_eq!(&written[12..16], &[0x80, 0x00, 0x0B, 0x87]);
+
+        // Each poll selects the chip, reads DO, then deselects it.
+        let poll = [0x80, 0x08, 0x0B, 0x81, 0x80, 0x00, 0x0B, 0x87];
+        assert_eq!(&written[16..], &poll.repeat(3)[..]);
+    }
+
+    #[test]
+    fn extended_instructions() {
+        let builder = Builder::new().microwire();
+        let eeprom = Eeprom::new(
+            MockTransport::with_responses(&[]),
+            Part::C46,
+            Organisation::X8,
+        );
+
+        // EWEN on a 93C46 in x8 mode: 1 00 11xxxxx.
+        let commands = eeprom.extended(builder, EWEN).build();
+        assert_eq!(commands, vec![0x13, 0x07, 0x98, 0x13, 0x01, 0x00]);
+    }
+}
@@ -0,0 +1,495 @@
+//! RISC-V external debug (version 0.13) over JTAG.
+//!
+//! A [`Dtm`] is the JTAG Debug Transport Module, which gives access to the Debug Module
+//! Interface (DMI). A [`DebugModule`] uses it to halt and resume harts, access their registers
+//! with abstract commands, and access memory through the System Bus Access block.
+//!
+//! ```no_run
+//! use mpsse::jtag::Jtag;
+//! use mpsse::riscv::{self, DebugModule, Dtm, Error};
+//! use mpsse::transport::Transport;
+//!
+//! // Halt hart 0 and read its program counter.
+//! fn pc<T: Transport>(jtag: Jtag<T>) -> Result<u32, Error<T::Error>> {
+//!     let mut dm = DebugModule::new(Dtm::new(jtag)?)?;
+//!     dm.halt()?;
+//!     dm.read_register(riscv::DPC)
+//! }
+//! ```
+use std::fmt;
+
+use crate::jtag::{self, from_bits, to_bits, Jtag, TapState};
+use crate::transport::Transport;
+
+/// JTAG instruction: the device identification register.
+pub const IDCODE: u64 = 0x01;
+/// JTAG instruction: DTM control and status.
+pub const DTMCS: u64 = 0x10;
+/// JTAG instruction: Debug Module Interface access.
+pub const DMI: u64 = 0x11;
+/// Length of the DTM's instruction register.
+pub const IR_LENGTH: usize = 5;
+
+/// DM register: abstract data 0.
+pub const DATA0: u8 = 0x04;
+/// DM register: debug module control.
+pub const DMCONTROL: u8 = 0x10;
+/// DM register: debug module status.
+pub const DMSTATUS: u8 = 0x11;
+/// DM register: abstract control and status.
+pub const ABSTRACTCS: u8 = 0x16;
+/// DM register: abstract command.
+pub const COMMAND: u8 = 0x17;
+/// DM register: system bus access control and status.
+pub const SBCS: u8 = 0x38;
+/// DM register: system bus address, bits 31:0.
+pub const SBADDRESS0: u8 = 0x39;
+/// DM register: system bus data, bits 31:0.
+pub const SBDATA0: u8 = 0x3C;
+
+/// Abstract register number of `x0`; the other GPRs follow it.
+pub const GPR0: u16 = 0x1000;
+/// Abstract register number of the `dcsr` CSR.
+pub const DCSR: u16 = 0x7B0;
+/// Abstract register number of the `dpc` CSR, which holds the PC while halted.
+pub const DPC: u16 = 0x7B1;
+
+const DTMCS_DMIRESET: u32 = 1 << 16;
+
+const DMI_NOP: u64 = 0;
+const DMI_READ: u64 = 1;
+const DMI_WRITE: u64 = 2;
+const DMI_FAILED: u64 = 2;
+const DMI_BUSY: u64 = 3;
+
+const DMACTIVE: u32 = 1 << 0;
+const HALTREQ: u32 = 1 << 31;
+const RESUMEREQ: u32 = 1 << 30;
+
+const ANYHALTED: u32 = 1 << 8;
+const ALLHALTED: u32 = 1 << 9;
+const ALLRESUMEACK: u32 = 1 << 17;
+
+const ABSTRACT_BUSY: u32 = 1 << 12;
+const CMDERR: u32 = 0x7 << 8;
+
+const AARSIZE_32: u32 = 2 << 20;
+const TRANSFER: u32 = 1 << 17;
+const WRITE: u32 = 1 << 16;
+
+const SBBUSYERROR: u32 = 1 << 22;
+const SBBUSY: u32 = 1 << 21;
+const SBREADONADDR: u32 = 1 << 20;
+const SBACCESS_32: u32 = 2 << 17;
+const SBAUTOINCREMENT: u32 = 1 << 16;
+const SBREADONDATA: u32 = 1 << 15;
+const SBERROR: u32 = 0x7 << 12;
+
+/// Number of times a DMI access is retried after the DTM answers busy.
+pub const BUSY_RETRIES: usize = 16;
+
+/// Number of times a status register is read waiting for the Debug Module.
+const POLLS: usize = 100;
+
+/// Error returned by the RISC-V debug layer.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The JTAG driver failed.
+    Jtag(jtag::Error<E>),
+    /// The DTM or Debug Module implements a version other than 0.13.
+    Version(u8),
+    /// The DTM kept answering busy.
+    Busy,
+    /// A DMI access failed.
+    Failed,
+    /// An abstract command failed, with the given `cmderr`.
+    Command(u8),
+    /// A system bus access failed, with the given `sberror`.
+    SystemBus(u8),
+    /// The hart did not halt or resume, or the Debug Module stayed busy.
+    Timeout,
+}
+
+impl<E> From<jtag::Error<E>> for Error<E> {
+    fn from(err: jtag::Error<E>) -> Self {
+        Error::Jtag(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Jtag(err) => write!(f, "{}", err),
+            Error::Version(version) => write!(f, "unsupported debug version {}", version),
+            Error::Busy => write!(f, "the DTM stayed busy"),
+            Error::Failed => write!(f, "a DMI access failed"),
+            Error::Command(cmderr) => write!(f, "abstract command failed with error {}", cmderr),
+            Error::SystemBus(sberror) => {
+                write!(f, "system bus access failed with error {}", sberror)
+            }
+            Error::Timeout => write!(f, "timed out waiting for the Debug Module"),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+/// Encode a DMI scan.
+pub fn dmi_request(abits: usize, address: u8, data: u32, op: u64) -> Vec<bool> {
+    let value = (address as u64) << 34 | (data as u64) << 2 | op;
+    to_bits(value, abits + 34)
+}
+
+/// Decode a captured DMI scan into its data and status.
+pub fn dmi_response(captured: &[bool]) -> (u32, u64) {
+    let value = from_bits(&captured[..34]);
+    ((value >> 2) as u32, value & 0x3)
+}
+
+/// The RISC-V JTAG Debug Transport Module.
+#[derive(Debug)]
+pub struct Dtm<T> {
+    jtag: Jtag<T>,
+    abits: usize,
+    idle: usize,
+    instruction: Option<u64>,
+}
+
+impl<T: Transport> Dtm<T> {
+    /// Read `dtmcs` and check the DTM implements version 0.13.
+    ///
+    /// If `jtag` has a device selected, it must be the DTM.
+    pub fn new(jtag: Jtag<T>) -> Result<Self, Error<T::Error>> {
+        let mut dtm = Dtm {
+            jtag,
+            abits: 0,
+            idle: 0,
+            instruction: None,
+        };
+
+        let dtmcs = dtm.dtmcs()?;
+        match dtmcs & 0xF {
+            1 => (),
+            version => return Err(Error::Version(version as u8)),
+        }
+        dtm.abits = (dtmcs >> 4 & 0x3F) as usize;
+        dtm.idle = (dtmcs >> 12 & 0x7) as usize;
+
+        Ok(dtm)
+    }
+
+    /// Get the underlying JTAG driver.
+    pub fn jtag(&mut self) -> &mut Jtag<T> {
+        &mut self.jtag
+    }
+
+    /// Get back the underlying JTAG driver.
+    pub fn into_inner(self) -> Jtag<T> {
+        self.jtag
+    }
+
+    /// The width of DMI addresses.
+    pub fn abits(&self) -> usize {
+        self.abits
+    }
+
+    /// The number of Run-Test/Idle cycles spent after each DMI access.
+    ///
+    /// This starts at the DTM's hint, and grows every time it answers busy.
+    pub fn idle_cycles(&self) -> usize {
+        self.idle
+    }
+
+    fn select(&mut self, instruction: u64) -> Result<(), Error<T::Error>> {
+        if self.instruction != Some(instruction) {
+            // Forget the instruction until it is known to be loaded.
+            self.instruction = None;
+            self.jtag
+                .scan_ir(&to_bits(instruction, IR_LENGTH), TapState::RunTestIdle)?;
+            self.instruction = Some(instruction);
+        }
+
+        Ok(())
+    }
+
+    /// Read `dtmcs`.
+    pub fn dtmcs(&mut self) -> Result<u32, Error<T::Error>> {
+        self.select(DTMCS)?;
+        let captured = self.jtag.scan_dr(&to_bits(0, 32), TapState::RunTestIdle)?;
+        Ok(from_bits(&captured) as u32)
+    }
+
+    /// Clear the DTM's sticky error status, after a DMI access failed or was busy.
+    pub fn reset_dmi(&mut self) -> Result<(), Error<T::Error>> {
+        self.select(DTMCS)?;
+        self.jtag
+            .scan_dr(&to_bits(DTMCS_DMIRESET as u64, 32), TapState::RunTestIdle)?;
+        Ok(())
+    }
+
+    /// Run a DMI operation, then a nop to collect its result.
+    fn dmi(&mut self, address: u8, data: u32, op: u64) -> Result<u32, Error<T::Error>> {
+        for _ in 0..=BUSY_RETRIES {
+            self.select(DMI)?;
+            let request = dmi_request(self.abits, address, data, op);
+            self.jtag.scan_dr_idle(&request, self.idle)?;
+            let nop = dmi_request(self.abits, 0, 0, DMI_NOP);
+            let (value, status) = dmi_response(&self.jtag.scan_dr_idle(&nop, self.idle)?);
+
+            match status {
+                DMI_BUSY => {
+                    // The operation was too quick for the DM, so wait longer from now on.
+                    self.reset_dmi()?;
+                    self.idle += 1;
+                }
+                DMI_FAILED => {
+                    self.reset_dmi()?;
+                    return Err(Error::Failed);
+                }
+                _ => return Ok(value),
+            }
+        }
+
+        Err(Error::Busy)
+    }
+
+    /// Read a Debug Module register.
+    pub fn read(&mut self, address: u8) -> Result<u32, Error<T::Error>> {
+        self.dmi(address, 0, DMI_READ)
+    }
+
+    /// Write a Debug Module register.
+    pub fn write(&mut self, address: u8, value: u32) -> Result<(), Error<T::Error>> {
+        self.dmi(address, value, DMI_WRITE)?;
+        Ok(())
+    }
+}
+
+/// A RISC-V Debug Module, controlling one hart at a time.
+#[derive(Debug)]
+pub struct DebugModule<T> {
+    dtm: Dtm<T>,
+    hart: u32,
+}
+
+impl<T: Transport> DebugModule<T> {
+    /// Activate the Debug Module, and check it implements version 0.13. Hart 0 is selected.
+    pub fn new(dtm: Dtm<T>) -> Result<Self, Error<T::Error>> {
+        let mut dm = DebugModule { dtm, hart: 0 };
+
+        dm.dtm.write(DMCONTROL, DMACTIVE)?;
+        dm.poll(DMCONTROL, |dmcontrol| dmcontrol & DMACTIVE != 0)?;
+
+        match dm.dtm.read(DMSTATUS)? & 0xF {
+            2 => Ok(dm),
+            version => Err(Error::Version(version as u8)),
+        }
+    }
+
+    /// Get the underlying DTM.
+    pub fn dtm(&mut self) -> &mut Dtm<T> {
+        &mut self.dtm
+    }
+
+    /// Get back the underlying DTM.
+    pub fn into_inner(self) -> Dtm<T> {
+        self.dtm
+    }
+
+    /// The selected hart.
+    pub fn hart(&self) -> u32 {
+        self.hart
+    }
+
+    /// Select the hart that later operations apply to.
+    pub fn select_hart(&mut self, hart: u32) -> Result<(), Error<T::Error>> {
+        self.hart = hart;
+        self.control(0)
+    }
+
+    /// Write `dmcontrol` with the selected hart and the given request bits.
+    fn control(&mut self, requests: u32) -> Result<(), Error<T::Error>> {
+        let hartsel = (self.hart & 0x3FF) << 16 | (self.hart >> 10 & 0x3FF) << 6;
+        self.dtm.write(DMCONTROL, DMACTIVE | hartsel | requests)
+    }
+
+    /// Read `address` until `done` returns true for its value, returning the value.
+    fn poll(&mut self, address: u8, done: impl Fn(u32) -> bool) -> Result<u32, Error<T::Error>> {
+        for _ in 0..POLLS {
+            let value = self.dtm.read(address)?;
+            if done(value) {
+                return Ok(value);
+            }
+        }
+
+        Err(Error::Timeout)
+    }
+
+    /// Whether the selected hart is halted.
+    pub fn is_halted(&mut self) -> Result<bool, Error<T::Error>> {
+        Ok(self.dtm.read(DMSTATUS)? & ANYHALTED != 0)
+    }
+
+    /// Halt the selected hart.
+    pub fn halt(&mut self) -> Result<(), Error<T::Error>> {
+        self.control(HALTREQ)?;
+        let halted = self.poll(DMSTATUS, |dmstatus| dmstatus & ALLHALTED != 0);
+        self.control(0)?;
+        halted.map(|_| ())
+    }
+
+    /// Resume the selected hart.
+    pub fn resume(&mut self) -> Result<(), Error<T::Error>> {
+        self.control(RESUMEREQ)?;
+        let resumed = self.poll(DMSTATUS, |dmstatus| dmstatus & ALLRESUMEACK != 0);
+        self.control(0)?;
+        resumed.map(|_| ())
+    }
+
+    /// Run an abstract command, and wait for it to finish.
+    fn command(&mut self, command: u32) -> Result<(), Error<T::Error>> {
+        self.dtm.write(COMMAND, command)?;
+        let abstractcs = self.poll(ABSTRACTCS, |abstractcs| abstractcs & ABSTRACT_BUSY == 0)?;
+
+        match (abstractcs & CMDERR) >> 8 {
+            0 => Ok(()),
+            cmderr => {
+                self.dtm.write(ABSTRACTCS, CMDERR)?;
+                Err(Error::Command(cmderr as u8))
+            }
+        }
+    }
+
+    /// Read a 32-bit register of the selected hart, which must be halted.
+    ///
+    /// * `regno` - The abstract register number, such as [`GPR0`] + n or a CSR number.
+    pub fn read_register(&mut self, regno: u16) -> Result<u32, Error<T::Error>> {
+        self.command(AARSIZE_32 | TRANSFER | regno as u32)?;
+        self.dtm.read(DATA0)
+    }
+
+    /// Write a 32-bit register of the selected hart, which must be halted.
+    pub fn write_register(&mut self, regno: u16, value: u32) -> Result<(), Error<T::Error>> {
+        self.dtm.write(DATA0, value)?;
+        self.command(AARSIZE_32 | TRANSFER | WRITE | regno as u32)
+    }
+
+    /// Check and clear the system bus error flags after an access.
+    fn check_system_bus(&mut self) -> Result<(), Error<T::Error>> {
+        let sbcs = self.poll(SBCS, |sbcs| sbcs & SBBUSY == 0)?;
+
+        if sbcs & (SBBUSYERROR | SBERROR) != 0 {
+            self.dtm.write(SBCS, SBBUSYERROR | SBERROR)?;
+        }
+        match (sbcs & SBBUSYERROR != 0, (sbcs & SBERROR) >> 12) {
+            (true, _) => Err(Error::Busy),
+            (false, 0) => Ok(()),
+            (false, sberror) => Err(Error::SystemBus(sberror as u8)),
+        }
+    }
+
+    /// Read 32-bit words from memory through the system bus. The harts can keep running.
+    pub fn read_memory(&mut self, address: u32, count: usize) -> Result<Vec<u32>, Error<T::Error>> {
+        let mut words = Vec::with_capacity(count);
+        if count == 0 {
+            return Ok(words);
+        }
+
+        // Writing the address starts the first read, and reading the data starts the next,
+        // until the last read, which must not go past the end.
+        let sbcs = SBACCESS_32 | SBREADONADDR | SBAUTOINCREMENT;
+        self.dtm.write(SBCS, sbcs | SBREADONDATA)?;
+        self.dtm.write(SBADDRESS0, address)?;
+        for _ in 1..count {
+            words.push(self.dtm.read(SBDATA0)?);
+        }
+        self.dtm.write(SBCS, sbcs)?;
+        words.push(self.dtm.read(SBDATA0)?);
+
+        self.check_system_bus()?;
+        Ok(words)
+    }
+
+    /// Write 32-bit words to memory through the system bus. The harts can keep running.
+    pub fn write_memory(&mut self, address: u32, words: &[u32]) -> Result<(), Error<T::Error>> {
+        self.dtm.write(SBCS, SBACCESS_32 | SBAUTOINCREMENT)?;
+        self.dtm.write(SBADDRESS0, address)?;
+        for word in words {
+            self.dtm.write(SBDATA0, *word)?;
+        }
+
+        self.check_system_bus()
+    }
+}
+
+#[cfg(test)]
+mod riscv_tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    /// The response bytes for a captured scan of `length` bits.
+    fn capture(value: u64, length: usize) -> Vec<u8> {
+        let bytes = (length - 1) / 8;
+        let remainder = (length - 1) % 8;
+        let mut response = value.to_le_bytes()[..bytes].to_vec();
+        if remainder > 0 {
+            let bits = (value >> (bytes * 8)) & ((1 << remainder) - 1);
+            response.push((bits as u8) << (8 - remainder));
+        }
+        response.push(((value >> (length - 1)) as u8 & 1) << 7);
+        response
+    }
+
+    const ABITS: usize = 7;
+    const DTMCS_VALUE: u64 = 0x1071;
+
+    #[test]
+    fn dmi_requests() {
+        let request = dmi_request(ABITS, DMSTATUS, 0, DMI_READ);
+        assert_eq!(request.len(), 41);
+        assert_eq!(from_bits(&request), 0x11 << 34 | 1);
+
+        let response = to_bits(0x1234_5678 << 2 | DMI_BUSY, 41);
+        assert_eq!(dmi_response(&response), (0x1234_5678, DMI_BUSY));
+    }
+
+    #[test]
+    fn busy_access_is_retried() {
+        let mut responses = Vec::new();
+        // IR scan to DTMCS, then reading dtmcs.
+        responses.extend(capture(0x01, IR_LENGTH));
+        responses.extend(capture(DTMCS_VALUE, 32));
+        // IR scan to DMI, the read, and a nop answering busy.
+        responses.extend(capture(0x01, IR_LENGTH));
+        responses.extend(capture(0, 41));
+        responses.extend(capture(DMI_BUSY, 41));
+        // dmireset through DTMCS.
+        responses.extend(capture(0x01, IR_LENGTH));
+        responses.extend(capture(DTMCS_VALUE, 32));
+        // The read again, which succeeds.
+        responses.extend(capture(0x01, IR_LENGTH));
+        responses.extend(capture(0, 41));
+        responses.extend(capture(0x0040_0382 << 2, 41));
+
+        let jtag = Jtag::new(MockTransport::with_responses(&responses));
+        let mut dtm = Dtm::new(jtag).unwrap();
+        assert_eq!(dtm.abits(), ABITS);
+        assert_eq!(dtm.idle_cycles(), 1);
+
+        assert_eq!(dtm.read(DMSTATUS).unwrap(), 0x0040_0382);
+        assert_eq!(dtm.idle_cycles(), 2);
+        assert!(dtm.jtag().transport().responses.is_empty());
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut responses = capture(0x01, IR_LENGTH);
+        responses.extend(capture(0x1070, 32));
+
+        let jtag = Jtag::new(MockTransport::with_responses(&responses));
+        match Dtm::new(jtag) {
+            Err(Error::Version(0)) => (),
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,382 @@
+//! SMBus protocols on top of the [I2C layer](crate::i2c).
+//!
+//! When Packet Error Checking is enabled, a CRC-8 of every byte on the bus (addresses included)
+//! is appended to writes, and checked at the end of reads.
+use std::fmt;
+
+use crate::builder::Builder;
+use crate::i2c::{self, check_acks, Address, Direction, I2c};
+use crate::transport::Transport;
+
+/// Largest data block allowed by block reads and writes.
+pub const MAX_BLOCK_LENGTH: usize = 32;
+
+/// Calculate the SMBus Packet Error Code (CRC-8, polynomial `x^8 + x^2 + x + 1`) of `bytes`.
+///
+/// ```
+/// use mpsse::smbus::pec;
+///
+/// assert_eq!(pec(b"123456789"), 0xF4);
+/// ```
+pub fn pec(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| match crc & 0x80 {
+            0 => crc << 1,
+            _ => crc << 1 ^ 0x07,
+        })
+    })
+}
+
+/// Error returned by the [`SmBus`] driver.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The underlying I2C transaction failed.
+    I2c(i2c::Error<E>),
+    /// The Packet Error Code read back did not match the data.
+    Pec { expected: u8, received: u8 },
+    /// A block was empty or longer than [`MAX_BLOCK_LENGTH`].
+    BlockLength(usize),
+}
+
+impl<E> From<i2c::Error<E>> for Error<E> {
+    fn from(err: i2c::Error<E>) -> Self {
+        Error::I2c(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::I2c(err) => write!(f, "{}", err),
+            Error::Pec { expected, received } => write!(
+                f,
+                "packet error code was {:#04x}, expected {:#04x}",
+                received, expected
+            ),
+            Error::BlockLength(length) => write!(f, "block length {} is out of range", length),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+/// Run SMBus protocols over an [`I2c`] driver.
+#[derive(Debug)]
+pub struct SmBus<T> {
+    i2c: I2c<T>,
+    pec: bool,
+}
+
+impl<T: Transport> SmBus<T> {
+    /// Create a driver with Packet Error Checking disabled.
+    pub fn new(i2c: I2c<T>) -> Self {
+        SmBus { i2c, pec: false }
+    }
+
+    /// Enable or disable Packet Error Checking.
+    pub fn with_pec(self, pec: bool) -> Self {
+        SmBus { pec, ..self }
+    }
+
+    /// Get the underlying I2C driver.
+    pub fn i2c(&mut self) -> &mut I2c<T> {
+        &mut self.i2c
+    }
+
+    /// Get back the underlying I2C driver.
+    pub fn into_inner(self) -> I2c<T> {
+        self.i2c
+    }
+
+    /// Quick Command: address the target with the given direction, and nothing else.
+    pub fn quick_command<A>(
+        &mut self,
+        address: A,
+        direction: Direction,
+    ) -> Result<(), Error<T::Error>>
+    where
+        A: Into<Address>,
+    {
+        let builder = Builder::new()
+            .i2c()
+            .start()
+            .address(address, direction)
+            .stop()
+            .then();
+
+        Ok(check_acks(&self.i2c.execute(builder)?)?)
+    }
+
+    /// Send Byte: write a single byte with no command code.
+    pub fn send_byte<A>(&mut self, address: A, byte: u8) -> Result<(), Error<T::Error>>
+    where
+        A: Into<Address>,
+    {
+        self.write(address.into(), &[byte])
+    }
+
+    /// Receive Byte: read a single byte with no command code.
+    pub fn receive_byte<A>(&mut self, address: A) -> Result<u8, Error<T::Error>>
+    where
+        A: Into<Address>,
+    {
+        Ok(self.read(address.into(), &[], 1)?[0])
+    }
+
+    /// Write Byte: write a byte to the register selected by `command`.
+    pub fn write_byte<A>(
+        &mut self,
+        address: A,
+        command: u8,
+        byte: u8,
+    ) -> Result<(), Error<T::Error>>
+    where
+        A: Into<Address>,
+    {
+        self.write(address.into(), &[command, byte])
+    }
+
+    /// Write Word: write a little-endian word to the register selected by `command`.
+    pub fn write_word<A>(
+        &mut self,
+        address: A,
+        command: u8,
+        word: u16,
+    ) -> Result<(), Error<T::Error>>
+    where
+        A: Into<Address>,
+    {
+        let [low, high] = word.to_le_bytes();
+        self.write(address.into(), &[command, low, high])
+    }
+
+    /// Read Byte: read a byte from the register selected by `command`.
+    pub fn read_byte<A>(&mut self, address: A, command: u8) -> Result<u8, Error<T::Error>>
+    where
+        A: Into<Address>,
+    {
+        Ok(self.read(address.into(), &[command], 1)?[0])
+    }
+
+    /// Read Word: read a little-endian word from the register selected by `command`.
+    pub fn read_word<A>(&mut self, address: A, command: u8) -> Result<u16, Error<T::Error>>
+    where
+        A: Into<Address>,
+    {
+        let data = self.read(address.into(), &[command], 2)?;
+        Ok(u16::from_le_bytes([data[0], data[1]]))
+    }
+
+    /// Process Call: write a word to the register selected by `command`, then read a word back.
+    pub fn process_call<A>(
+        &mut self,
+        address: A,
+        command: u8,
+        word: u16,
+    ) -> Result<u16, Error<T::Error>>
+    where
+        A: Into<Address>,
+    {
+        let [low, high] = word.to_le_bytes();
+        let data = self.read(address.into(), &[command, low, high], 2)?;
+        Ok(u16::from_le_bytes([data[0], data[1]]))
+    }
+
+    /// Block Write: write 1 to 32 bytes, prefixed with their length, to `command`.
+    pub fn block_write<A>(
+        &mut self,
+        address: A,
+        command: u8,
+        data: &[u8],
+    ) -> Result<(), Error<T::Error>>
+    where
+        A: Into<Address>,
+    {
+        if data.is_empty() || data.len() > MAX_BLOCK_LENGTH {
+            return Err(Error::BlockLength(data.len()));
+        }
+
+        let mut bytes = vec![command, data.len() as u8];
+        bytes.extend_from_slice(data);
+        self.write(address.into(), &bytes)
+    }
+
+    /// Block Read: read a block of up to 32 bytes from `command`.
+    ///
+    /// The length comes from the target, so this takes two transfers: one up to and including
+    /// the length byte, and one for the rest of the block. The bus is held between the two.
+    pub fn block_read<A>(&mut self, address: A, command: u8) -> Result<Vec<u8>, Error<T::Error>>
+    where
+        A: Into<Address>,
+    {
+        let address = address.into();
+        let builder = Builder::new()
+            .i2c()
+            .start()
+            .address(address, Direction::Write)
+            .write_byte(command)
+            .start()
+            .address(address, Direction::Read)
+            .read_byte(true)
+            .then();
+
+        let response = self.i2c.execute(builder)?;
+        let (acks, count) = response.split_at(response.len() - 1);
+        check_acks(acks)?;
+
+        let count = count[0] as usize;
+        if count == 0 || count > MAX_BLOCK_LENGTH {
+            self.i2c
+                .execute(Builder::new().i2c().read_byte(false).stop().then())?;
+            return Err(Error::BlockLength(count));
+        }
+
+        let length = count + self.pec as usize;
+        let data = self
+            .i2c
+            .execute(Builder::new().i2c().read(length).stop().then())?;
+
+        let mut sent = read_header(address, &[command]);
+        sent.push(count as u8);
+        self.check_pec(sent, data)
+    }
+
+    /// Write `bytes` in a single transaction, appending a PEC if enabled.
+    fn write(&mut self, address: Address, bytes: &[u8]) -> Result<(), Error<T::Error>> {
+        let mut bytes = bytes.to_vec();
+        if self.pec {
+            let mut sent = address.frames(Direction::Write).concat();
+            sent.extend_from_slice(&bytes);
+            bytes.push(pec(&sent));
+        }
+
+        let builder = Builder::new()
+            .i2c()
+            .start()
+            .address(address, Direction::Write)
+            .write(&bytes)
+            .stop()
+            .then();
+
+        Ok(check_acks(&self.i2c.execute(builder)?)?)
+    }
+
+    /// Write `bytes` (if any), then read `length` bytes after a repeated START, checking the PEC
+    /// if enabled.
+    fn read(
+        &mut self,
+        address: Address,
+        bytes: &[u8],
+        length: usize,
+    ) -> Result<Vec<u8>, Error<T::Error>> {
+        let mut builder = Builder::new().i2c().start();
+        if !bytes.is_empty() {
+            builder = builder
+                .address(address, Direction::Write)
+                .write(bytes)
+                .start();
+        }
+        builder = builder
+            .address(address, Direction::Read)
+            .read(length + self.pec as usize)
+            .stop();
+        let sent = read_header(address, bytes);
+
+        let response = self.i2c.execute(builder.then())?;
+        let (acks, data) = response.split_at(response.len() - length - self.pec as usize);
+        check_acks(acks)?;
+
+        self.check_pec(sent, data.to_vec())
+    }
+
+    /// Check the PEC at the end of `data` against everything sent and received, if enabled.
+    fn check_pec(&self, mut sent: Vec<u8>, mut data: Vec<u8>) -> Result<Vec<u8>, Error<T::Error>> {
+        if !self.pec {
+            return Ok(data);
+        }
+
+        let received = data.pop().unwrap_or_default();
+        sent.extend_from_slice(&data);
+        let expected = pec(&sent);
+
+        match expected == received {
+            true => Ok(data),
+            false => Err(Error::Pec { expected, received }),
+        }
+    }
+}
+
+/// The bytes sent before reading: the write address and `bytes` (if any), then the read address.
+fn read_header(address: Address, bytes: &[u8]) -> Vec<u8> {
+    let mut sent = Vec::new();
+    if !bytes.is_empty() {
+        sent.extend(address.frames(Direction::Write).concat());
+        sent.extend_from_slice(bytes);
+    }
+    sent.extend(address.frames(Direction::Read).concat());
+    sent
+}
+
+#[cfg(test)]
+mod smbus_tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn write_byte_appends_pec() {
+        let transport = MockTransport::with_responses(&[0x00; 4]);
+        let mut smbus = SmBus::new(I2c::new(transport)).with_pec(true);
+
+        smbus.write_byte(0x5A, 0x01, 0x80).unwrap();
+
+        let expected = pec(&[0xB4, 0x01, 0x80]);
+        let written = &smbus.i2c().transport().written;
+        assert!(written
+            .windows(4)
+            .any(|command| command == [0x11, 0x00, 0x00, expected]));
+    }
+
+    #[test]
+    fn read_word_checks_pec() {
+        let good = pec(&[0xB4, 0x8B, 0xB5, 0x34, 0x12]);
+        let transport = MockTransport::with_responses(&[0x00, 0x00, 0x00, 0x34, 0x12, good]);
+        let mut smbus = SmBus::new(I2c::new(transport)).with_pec(true);
+
+        assert_eq!(smbus.read_word(0x5A, 0x8B).unwrap(), 0x1234);
+
+        let transport = MockTransport::with_responses(&[0x00, 0x00, 0x00, 0x34, 0x12, !good]);
+        let mut smbus = SmBus::new(I2c::new(transport)).with_pec(true);
+
+        match smbus.read_word(0x5A, 0x8B) {
+            Err(Error::Pec { .. }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn block_read_uses_length_byte() {
+        let transport = MockTransport::with_responses(&[0x00, 0x00, 0x00, 0x03, 0x0A, 0x0B, 0x0C]);
+        let mut smbus = SmBus::new(I2c::new(transport));
+
+        assert_eq!(
+            smbus.block_read(0x5A, 0x9A).unwrap(),
+            vec![0x0A, 0x0B, 0x0C]
+        );
+    }
+
+    #[test]
+    fn block_write_rejects_bad_lengths() {
+        let transport = MockTransport::with_responses(&[]);
+        let mut smbus = SmBus::new(I2c::new(transport));
+
+        match smbus.block_write(0x5A, 0x9A, &[]) {
+            Err(Error::BlockLength(0)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match smbus.block_write(0x5A, 0x9A, &[0; MAX_BLOCK_LENGTH + 1]) {
+            Err(Error::BlockLength(33)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(smbus.i2c().transport().written.is_empty());
+    }
+}
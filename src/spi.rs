@@ -0,0 +1,284 @@
+//! SPI transactions built from MPSSE commands.
+//!
+//! The pins are wired as described in FTDI's AN_114: ADBUS0 is SCK, ADBUS1 is MOSI, ADBUS2 is
+//! MISO and ADBUS3 is an active-low chip select. Data is written on the falling edge of SCK and
+//! read on the rising edge, most significant bit first, which suits SPI modes 0 and 3.
+//!
+//! ```
+//! use mpsse::Builder;
+//!
+//! let commands = Builder::new()
+//!     .spi()
+//!     .select()
+//!     .write(&[0x9F])
+//!     .read(3)
+//!     .deselect()
+//!     .build();
+//!
+//! assert_eq!(
+//!     commands,
+//!     vec![0x80, 0x00, 0x0B, 0x11, 0x00, 0x00, 0x9F, 0x20, 0x02, 0x00, 0x80, 0x08, 0x0B]
+//! );
+//! ```
+use std::time::Duration;
+
+use crate::builder::Builder;
+use crate::command::{BitDirection, ClockEdge, Command, DataShiftOptions, PinRange};
+use crate::transport::{self, Transport};
+
+const SCK: u8 = 0x01;
+const MOSI: u8 = 0x02;
+const MISO: u8 = 0x04;
+const CS: u8 = 0x08;
+
+/// Largest number of bytes a single read command can clock in.
+const MAX_READ_BYTES: usize = 0xFFFF;
+
+/// Largest number of bytes a single write command can clock out.
+const MAX_WRITE_BYTES: usize = 0x10000;
+
+const WRITE_OPTIONS: DataShiftOptions = DataShiftOptions {
+    clock_direction: ClockEdge::Falling,
+    bit_direction: BitDirection::MsbFirst,
+};
+
+const READ_OPTIONS: DataShiftOptions = DataShiftOptions {
+    clock_direction: ClockEdge::Rising,
+    bit_direction: BitDirection::MsbFirst,
+};
+
+/// The level SCK idles at between transactions.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Mode {
+    /// SPI mode 0: SCK idles low.
+    Mode0,
+    /// SPI mode 3: SCK idles high.
+    Mode3,
+}
+
+impl Builder {
+    /// Start building a sequence of SPI bus operations, in SPI mode 0.
+    pub fn spi(self) -> SpiBuilder {
+        SpiBuilder {
+            parent: self,
+            mode: Mode::Mode0,
+        }
+    }
+}
+
+/// Build a sequence of SPI bus operations.
+///
+/// Each read or transferred byte responds with the byte clocked in from MISO.
+#[derive(Debug)]
+pub struct SpiBuilder {
+    parent: Builder,
+    mode: Mode,
+}
+
+impl SpiBuilder {
+    /// Use SPI mode 3 rather than mode 0 for the following operations.
+    pub fn with_mode(self, mode: Mode) -> Self {
+        SpiBuilder { mode, ..self }
+    }
+
+    fn set_lines(mut self, cs: bool) -> Self {
+        let sck = match self.mode {
+            Mode::Mode0 => 0,
+            Mode::Mode3 => SCK,
+        };
+        let cs = match cs {
+            true => CS,
+            false => 0,
+        };
+        self.parent.set_bits(
+            PinRange::Low,
+            SCK | MOSI | MISO | CS,
+            sck | cs,
+            SCK | MOSI | CS,
+        );
+
+        self
+    }
+
+    /// Drive SCK to its idle level with the chip deselected.
+    ///
+    /// ```
+    /// use mpsse::Builder;
+    /// use mpsse::spi::Mode;
+    ///
+    /// let commands = Builder::new().spi().with_mode(Mode::Mode3).setup().build();
+    ///
+    /// assert_eq!(commands, vec![0x80, 0x09, 0x0B]);
+    /// ```
+    pub fn setup(self) -> Self {
+        self.deselect()
+    }
+
+    /// Pull chip select low.
+    pub fn select(self) -> Self {
+        self.set_lines(false)
+    }
+
+    /// Release chip select.
+    pub fn deselect(self) -> Self {
+        self.set_lines(true)
+    }
+
+    /// Write `bytes` to MOSI, ignoring MISO.
+    pub fn write(mut self, bytes: &[u8]) -> Self {
+        for chunk in bytes.chunks(MAX_WRITE_BYTES) {
+            self.parent.commands.push(Command::WriteDataShiftBytes {
+                options: WRITE_OPTIONS,
+                bytes: chunk.to_vec(),
+            });
+        }
+
+        self
+    }
+
+    /// Read `length` bytes from MISO.
+    pub fn read(mut self, length: usize) -> Self {
+        let mut remaining = length;
+        while remaining > 0 {
+            let chunk = remaining.min(MAX_READ_BYTES);
+            self.parent.commands.push(Command::ReadDataShiftBytes {
+                options: READ_OPTIONS,
+                length: chunk as u16,
+            });
+            remaining -= chunk;
+        }
+
+        self
+    }
+
+    /// Write `bytes` to MOSI while reading the same number of bytes from MISO.
+    pub fn transfer(mut self, bytes: &[u8]) -> Self {
+        for chunk in bytes.chunks(MAX_WRITE_BYTES) {
+            self.parent.commands.push(Command::ReadWriteDataShiftBytes {
+                options: WRITE_OPTIONS,
+                bytes: chunk.to_vec(),
+            });
+        }
+
+        self
+    }
+
+    /// Commit this sequence to the parent Builder.
+    fn commit(self) -> Builder {
+        self.parent
+    }
+
+    builder_funcs!();
+}
+
+/// Error returned by the [`Spi`] driver. SPI has no errors of its own, so this is the
+/// transport's.
+pub use crate::transport::Error;
+
+/// Run SPI transactions over a [`Transport`].
+#[derive(Debug)]
+pub struct Spi<T> {
+    transport: T,
+    timeout: Duration,
+    mode: Mode,
+}
+
+impl<T: Transport> Spi<T> {
+    /// Create a driver using the given transport, in SPI mode 0 with the
+    /// [default timeout](transport::DEFAULT_TIMEOUT).
+    pub fn new(transport: T) -> Self {
+        Spi {
+            transport,
+            timeout: transport::DEFAULT_TIMEOUT,
+            mode: Mode::Mode0,
+        }
+    }
+
+    /// Set how long to wait for the device to respond.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Spi { timeout, ..self }
+    }
+
+    /// Use SPI mode 3 rather than mode 0.
+    pub fn with_mode(self, mode: Mode) -> Self {
+        Spi { mode, ..self }
+    }
+
+    /// Get the underlying transport.
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Get back the underlying transport.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Start building SPI operations in this driver's mode.
+    pub fn builder(&self) -> SpiBuilder {
+        Builder::new().spi().with_mode(self.mode)
+    }
+
+    /// Run the given commands, flushing and returning their response.
+    pub fn execute(&mut self, builder: Builder) -> Result<Vec<u8>, Error<T::Error>> {
+        transport::flush(&mut self.transport, builder, self.timeout)
+    }
+
+    /// Drive SCK to its idle level with the chip deselected.
+    pub fn setup(&mut self) -> Result<(), Error<T::Error>> {
+        self.execute(self.builder().setup().then())?;
+        Ok(())
+    }
+
+    /// Select the chip, write `bytes`, then read `length` bytes and deselect it.
+    pub fn write_read(&mut self, bytes: &[u8], length: usize) -> Result<Vec<u8>, Error<T::Error>> {
+        let builder = self
+            .builder()
+            .select()
+            .write(bytes)
+            .read(length)
+            .deselect()
+            .then();
+
+        self.execute(builder)
+    }
+
+    /// Select the chip, write `bytes` and deselect it.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), Error<T::Error>> {
+        self.write_read(bytes, 0)?;
+        Ok(())
+    }
+
+    /// Select the chip, write `bytes` while reading as many back, and deselect it.
+    pub fn transfer(&mut self, bytes: &[u8]) -> Result<Vec<u8>, Error<T::Error>> {
+        let builder = self.builder().select().transfer(bytes).deselect().then();
+
+        self.execute(builder)
+    }
+}
+
+#[cfg(test)]
+mod spi_tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn long_reads_are_split() {
+        let builder = Builder::new().spi().read(0x10000).then();
+
+        assert_eq!(builder.expected_response_length(), 0x10000);
+        assert_eq!(builder.build(), vec![0x20, 0xFE, 0xFF, 0x20, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn transfer_returns_miso() {
+        let transport = MockTransport::with_responses(&[0x12, 0x34]);
+        let mut spi = Spi::new(transport).with_mode(Mode::Mode3);
+
+        assert_eq!(spi.transfer(&[0xAB, 0xCD]).unwrap(), vec![0x12, 0x34]);
+        assert_eq!(
+            &spi.transport().written[..8],
+            &[0x80, 0x01, 0x0B, 0x31, 0x01, 0x00, 0xAB, 0xCD]
+        );
+    }
+}
@@ -0,0 +1,853 @@
+//! Serial Vector Format (SVF) player.
+//!
+//! SVF files are parsed into [`Statement`]s, which a [`Compiler`] turns into commands on the
+//! [JTAG layer](crate::jtag). Scans with expected TDO values produce [`Check`]s, which verify
+//! the captured bits once the commands have run. [`play`] does all of this over a [`Jtag`]
+//! driver, batching statements into as few transfers as it can.
+//!
+//! ```
+//! use mpsse::svf::{parse, Statement};
+//! use mpsse::jtag::TapState;
+//!
+//! let statements = parse("
+//!     ! Read the IDCODE of a Xilinx part
+//!     ENDDR IDLE;
+//!     SIR 6 TDI (09);
+//!     SDR 32 TDI (00000000) TDO (f5059093) MASK (0fffffff);
+//! ").unwrap();
+//!
+//! assert_eq!(statements.len(), 3);
+//! assert_eq!(statements[0], (3, Statement::EndDr(TapState::RunTestIdle)));
+//! ```
+use std::fmt;
+
+use crate::builder::Builder;
+use crate::jtag::{self, decode_scan, push_clocks, scan_response_length, Jtag, TapState};
+use crate::transport::Transport;
+
+/// Clock frequency assumed for `RUNTEST` times until a `FREQUENCY` statement sets one. This is
+/// the fastest an H-series MPSSE can clock, so waits are never too short.
+const DEFAULT_FREQUENCY: f64 = 30_000_000.0;
+
+/// Statements are batched into a single transfer until they expect this many response bytes.
+const MAX_BATCH_RESPONSE: usize = 4096;
+
+/// Statements are batched into a single transfer until they generate this many commands.
+const MAX_BATCH_COMMANDS: usize = 1024;
+
+/// Error found while parsing or compiling an SVF file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The line the statement starts on, counting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Error returned when playing an SVF file.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The file could not be parsed.
+    Parse(ParseError),
+    /// The TDO bits captured by a scan did not match the file's expected values.
+    Mismatch {
+        /// The line the scan starts on, counting from 1.
+        line: usize,
+        expected: String,
+        captured: String,
+        mask: String,
+    },
+    /// Running the commands failed.
+    Jtag(jtag::Error<E>),
+}
+
+impl<E> From<ParseError> for Error<E> {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(err)
+    }
+}
+
+impl<E> From<jtag::Error<E>> for Error<E> {
+    fn from(err: jtag::Error<E>) -> Self {
+        Error::Jtag(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(err) => write!(f, "{}", err),
+            Error::Mismatch {
+                line,
+                expected,
+                captured,
+                mask,
+            } => write!(
+                f,
+                "line {}: TDO mismatch, expected {} captured {} (mask {})",
+                line, expected, captured, mask
+            ),
+            Error::Jtag(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+/// The arguments to a scan (`SIR`, `SDR`) or header/trailer (`HIR`, `TIR`, `HDR`, `TDR`).
+///
+/// Bit vectors are stored first bit shifted first, so bit 0 is the rightmost hex digit's lowest
+/// bit.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Scan {
+    pub length: usize,
+    pub tdi: Option<Vec<bool>>,
+    pub tdo: Option<Vec<bool>>,
+    pub mask: Option<Vec<bool>>,
+    pub smask: Option<Vec<bool>>,
+}
+
+/// A parsed SVF statement.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Sir(Scan),
+    Sdr(Scan),
+    Hir(Scan),
+    Tir(Scan),
+    Hdr(Scan),
+    Tdr(Scan),
+    EndIr(TapState),
+    EndDr(TapState),
+    RunTest {
+        run_state: Option<TapState>,
+        run_count: Option<usize>,
+        min_time: Option<f64>,
+        end_state: Option<TapState>,
+    },
+    State(Vec<TapState>),
+    Frequency(Option<f64>),
+    /// `TRST` is accepted, but ignored as the adapter has no TRST pin by default.
+    Trst,
+}
+
+/// Get the TAP state with the given SVF name.
+fn parse_state(name: &str) -> Option<TapState> {
+    let state = match name {
+        "RESET" => TapState::TestLogicReset,
+        "IDLE" => TapState::RunTestIdle,
+        "DRSELECT" => TapState::SelectDRScan,
+        "DRCAPTURE" => TapState::CaptureDR,
+        "DRSHIFT" => TapState::ShiftDR,
+        "DREXIT1" => TapState::Exit1DR,
+        "DRPAUSE" => TapState::PauseDR,
+        "DREXIT2" => TapState::Exit2DR,
+        "DRUPDATE" => TapState::UpdateDR,
+        "IRSELECT" => TapState::SelectIRScan,
+        "IRCAPTURE" => TapState::CaptureIR,
+        "IRSHIFT" => TapState::ShiftIR,
+        "IREXIT1" => TapState::Exit1IR,
+        "IRPAUSE" => TapState::PauseIR,
+        "IREXIT2" => TapState::Exit2IR,
+        "IRUPDATE" => TapState::UpdateIR,
+        _ => return None,
+    };
+
+    Some(state)
+}
+
+/// Whether SVF allows scans and `RUNTEST` to end in `state`: a stable state other than Shift-IR
+/// or Shift-DR.
+fn is_end_state(state: TapState) -> bool {
+    state.is_stable() && state != TapState::ShiftDR && state != TapState::ShiftIR
+}
+
+/// Parse a hex value into `length` bits, least significant bit first.
+fn parse_hex(hex: &str, length: usize) -> Option<Vec<bool>> {
+    let mut bits = Vec::with_capacity(length + 3);
+    for digit in hex.chars().rev().filter(|c| !c.is_whitespace()) {
+        let digit = digit.to_digit(16)?;
+        bits.extend((0..4).map(|i| digit >> i & 1 == 1));
+    }
+
+    bits.resize(length, false);
+    Some(bits)
+}
+
+/// Format bits, least significant bit first, as a hex value.
+pub(crate) fn to_hex(bits: &[bool]) -> String {
+    bits.chunks(4)
+        .rev()
+        .map(|digit| format!("{:x}", jtag::from_bits(digit)))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Hex(String),
+}
+
+/// Split a statement into words and parenthesised hex values.
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+
+        if c == '(' {
+            let mut hex = String::new();
+            loop {
+                match chars.next() {
+                    Some(')') => break,
+                    Some(c) => hex.push(c),
+                    None => return Err("unterminated '('".to_string()),
+                }
+            }
+            tokens.push(Token::Hex(hex));
+            continue;
+        }
+
+        let mut word = c.to_string();
+        while let Some(c) = chars.peek() {
+            if c.is_whitespace() || *c == '(' {
+                break;
+            }
+            word.push(*c);
+            chars.next();
+        }
+        tokens.push(Token::Word(word.to_uppercase()));
+    }
+
+    Ok(tokens)
+}
+
+/// Reads through a statement's tokens.
+struct Tokens {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+}
+
+impl Tokens {
+    fn word(&mut self) -> Result<String, String> {
+        match self.tokens.next() {
+            Some(Token::Word(word)) => Ok(word),
+            Some(Token::Hex(hex)) => Err(format!("unexpected ({})", hex)),
+            None => Err("unexpected end of statement".to_string()),
+        }
+    }
+
+    fn peek_word(&mut self) -> Option<&str> {
+        match self.tokens.peek() {
+            Some(Token::Word(word)) => Some(word),
+            _ => None,
+        }
+    }
+
+    fn hex(&mut self) -> Result<String, String> {
+        match self.tokens.next() {
+            Some(Token::Hex(hex)) => Ok(hex),
+            Some(Token::Word(word)) => Err(format!("expected a hex value, found {}", word)),
+            None => Err("expected a hex value".to_string()),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, String> {
+        let word = self.word()?;
+        word.parse()
+            .map_err(|_| format!("expected a number, found {}", word))
+    }
+
+    fn state(&mut self) -> Result<TapState, String> {
+        let word = self.word()?;
+        parse_state(&word).ok_or_else(|| format!("unknown state {}", word))
+    }
+
+    fn end_state(&mut self) -> Result<TapState, String> {
+        let state = self.state()?;
+        match is_end_state(state) {
+            true => Ok(state),
+            false => Err(format!("{:?} is not a valid end state", state)),
+        }
+    }
+
+    fn end(&mut self) -> Result<(), String> {
+        match self.tokens.next() {
+            None => Ok(()),
+            Some(Token::Word(word)) => Err(format!("unexpected {}", word)),
+            Some(Token::Hex(hex)) => Err(format!("unexpected ({})", hex)),
+        }
+    }
+}
+
+fn parse_scan(tokens: &mut Tokens) -> Result<Scan, String> {
+    let length = tokens.number()? as usize;
+    let mut scan = Scan {
+        length,
+        ..Default::default()
+    };
+
+    while let Some(word) = tokens.peek_word().map(String::from) {
+        tokens.word()?;
+        let hex = tokens.hex()?;
+        let bits = parse_hex(&hex, length).ok_or_else(|| format!("invalid hex value {}", hex))?;
+        match word.as_str() {
+            "TDI" => scan.tdi = Some(bits),
+            "TDO" => scan.tdo = Some(bits),
+            "MASK" => scan.mask = Some(bits),
+            "SMASK" => scan.smask = Some(bits),
+            _ => return Err(format!("unexpected {}", word)),
+        }
+    }
+
+    tokens.end()?;
+    Ok(scan)
+}
+
+fn parse_run_test(tokens: &mut Tokens) -> Result<Statement, String> {
+    let mut run_state = None;
+    if tokens.peek_word().and_then(parse_state).is_some() {
+        run_state = Some(tokens.end_state()?);
+    }
+
+    let mut run_count = None;
+    let mut min_time = None;
+    let number = tokens.number()?;
+    match tokens.word()?.as_str() {
+        "TCK" => run_count = Some(number as usize),
+        "SEC" => min_time = Some(number),
+        "SCK" => return Err("RUNTEST with SCK is not supported".to_string()),
+        word => return Err(format!("unexpected {}", word)),
+    }
+
+    if run_count.is_some()
+        && tokens
+            .peek_word()
+            .is_some_and(|word| word.parse::<f64>().is_ok())
+    {
+        min_time = Some(tokens.number()?);
+        if tokens.word()? != "SEC" {
+            return Err("expected SEC".to_string());
+        }
+    }
+
+    if tokens.peek_word() == Some("MAXIMUM") {
+        tokens.word()?;
+        tokens.number()?;
+        if tokens.word()? != "SEC" {
+            return Err("expected SEC".to_string());
+        }
+    }
+
+    let mut end_state = None;
+    if tokens.peek_word() == Some("ENDSTATE") {
+        tokens.word()?;
+        end_state = Some(tokens.end_state()?);
+    }
+
+    tokens.end()?;
+    Ok(Statement::RunTest {
+        run_state,
+        run_count,
+        min_time,
+        end_state,
+    })
+}
+
+fn parse_statement(text: &str) -> Result<Statement, String> {
+    let mut tokens = Tokens {
+        tokens: tokenize(text)?.into_iter().peekable(),
+    };
+
+    let statement = match tokens.word()?.as_str() {
+        "SIR" => Statement::Sir(parse_scan(&mut tokens)?),
+        "SDR" => Statement::Sdr(parse_scan(&mut tokens)?),
+        "HIR" => Statement::Hir(parse_scan(&mut tokens)?),
+        "TIR" => Statement::Tir(parse_scan(&mut tokens)?),
+        "HDR" => Statement::Hdr(parse_scan(&mut tokens)?),
+        "TDR" => Statement::Tdr(parse_scan(&mut tokens)?),
+        "ENDIR" => Statement::EndIr(tokens.end_state()?),
+        "ENDDR" => Statement::EndDr(tokens.end_state()?),
+        "RUNTEST" => return parse_run_test(&mut tokens),
+        "STATE" => {
+            let mut states = Vec::new();
+            while tokens.peek_word().is_some() {
+                states.push(tokens.state()?);
+            }
+            match states.last() {
+                Some(state) if state.is_stable() => Statement::State(states),
+                _ => return Err("STATE must end in a stable state".to_string()),
+            }
+        }
+        "FREQUENCY" => match tokens.peek_word() {
+            Some(_) => {
+                let frequency = tokens.number()?;
+                if tokens.word()? != "HZ" {
+                    return Err("expected HZ".to_string());
+                }
+                Statement::Frequency(Some(frequency))
+            }
+            None => Statement::Frequency(None),
+        },
+        "TRST" => {
+            tokens.word()?;
+            Statement::Trst
+        }
+        word => return Err(format!("{} is not supported", word)),
+    };
+
+    tokens.end()?;
+    Ok(statement)
+}
+
+/// Parse an SVF file into statements, each paired with the line it starts on.
+pub fn parse(source: &str) -> Result<Vec<(usize, Statement)>, ParseError> {
+    let mut statements = Vec::new();
+    let mut text = String::new();
+    let mut start = None;
+
+    for (index, line) in source.lines().enumerate() {
+        let line = match (line.find('!'), line.find("//")) {
+            (Some(a), Some(b)) => &line[..a.min(b)],
+            (Some(a), None) | (None, Some(a)) => &line[..a],
+            (None, None) => line,
+        };
+
+        for part in line.split_inclusive(';') {
+            if start.is_none() && !part.trim().is_empty() {
+                start = Some(index + 1);
+            }
+
+            match part.strip_suffix(';') {
+                Some(part) => {
+                    text.push_str(part);
+                    let line = start.take().unwrap_or(index + 1);
+                    let statement =
+                        parse_statement(&text).map_err(|message| ParseError { line, message })?;
+                    statements.push((line, statement));
+                    text.clear();
+                }
+                None => {
+                    text.push_str(part);
+                    text.push(' ');
+                }
+            }
+        }
+    }
+
+    match start {
+        Some(line) => Err(ParseError {
+            line,
+            message: "statement is missing its ';'".to_string(),
+        }),
+        None => Ok(statements),
+    }
+}
+
+/// A scan's bits, after filling in anything left out from the previous scan of that register.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Pattern {
+    tdi: Vec<bool>,
+    tdo: Option<Vec<bool>>,
+    mask: Vec<bool>,
+}
+
+impl Pattern {
+    /// Fill in `scan` from this pattern. TDI and MASK carry over while the length stays the
+    /// same, and TDO only carries over if `sticky_tdo` is set.
+    fn update(&self, scan: &Scan, sticky_tdo: bool) -> Pattern {
+        let same_length = self.tdi.len() == scan.length;
+        let previous = |bits: &Vec<bool>| match same_length {
+            true => Some(bits.clone()),
+            false => None,
+        };
+
+        Pattern {
+            tdi: scan
+                .tdi
+                .clone()
+                .or_else(|| previous(&self.tdi))
+                .unwrap_or_else(|| vec![false; scan.length]),
+            tdo: match (&scan.tdo, sticky_tdo && same_length) {
+                (Some(tdo), _) => Some(tdo.clone()),
+                (None, true) => self.tdo.clone(),
+                (None, false) => None,
+            },
+            mask: scan
+                .mask
+                .clone()
+                .or_else(|| previous(&self.mask))
+                .unwrap_or_else(|| vec![true; scan.length]),
+        }
+    }
+
+    /// The mask of bits to compare, which is empty if there is nothing to compare.
+    fn compare_mask(&self) -> Vec<bool> {
+        match self.tdo {
+            Some(_) => self.mask.clone(),
+            None => vec![false; self.tdi.len()],
+        }
+    }
+}
+
+/// Expected TDO bits for a scan, to check against the response once the commands have run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    /// The line the scan starts on, counting from 1.
+    pub line: usize,
+    offset: usize,
+    expected: Vec<bool>,
+    mask: Vec<bool>,
+}
+
+impl Check {
+    /// Compare the captured bits in `response` against the expected ones.
+    ///
+    /// * `response` - The whole response to the command list the scan was compiled into.
+    pub fn verify<E>(&self, response: &[u8]) -> Result<(), Error<E>> {
+        let length = self.expected.len();
+        let response = &response[self.offset..self.offset + scan_response_length(length)];
+        let captured = decode_scan(response, length);
+
+        let matches = captured
+            .iter()
+            .zip(&self.expected)
+            .zip(&self.mask)
+            .all(|((captured, expected), mask)| !mask || captured == expected);
+
+        match matches {
+            true => Ok(()),
+            false => Err(Error::Mismatch {
+                line: self.line,
+                expected: to_hex(&self.expected),
+                captured: to_hex(&captured),
+                mask: to_hex(&self.mask),
+            }),
+        }
+    }
+}
+
+/// Turns SVF statements into commands, keeping track of the state statements leave behind.
+#[derive(Debug, Clone)]
+pub struct Compiler {
+    hir: Pattern,
+    tir: Pattern,
+    hdr: Pattern,
+    tdr: Pattern,
+    sir: Pattern,
+    sdr: Pattern,
+    end_ir: TapState,
+    end_dr: TapState,
+    run_state: TapState,
+    run_end_state: TapState,
+    frequency: f64,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler {
+            hir: Pattern::default(),
+            tir: Pattern::default(),
+            hdr: Pattern::default(),
+            tdr: Pattern::default(),
+            sir: Pattern::default(),
+            sdr: Pattern::default(),
+            end_ir: TapState::RunTestIdle,
+            end_dr: TapState::RunTestIdle,
+            run_state: TapState::RunTestIdle,
+            run_end_state: TapState::RunTestIdle,
+            frequency: DEFAULT_FREQUENCY,
+        }
+    }
+}
+
+impl Compiler {
+    /// Create a compiler in the state SVF files start in.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add the commands for `statement` to `builder`.
+    ///
+    /// Returns the builder, and the check to make on its response if the statement is a scan
+    /// with expected TDO values.
+    pub fn compile(
+        &mut self,
+        builder: Builder,
+        line: usize,
+        statement: &Statement,
+    ) -> Result<(Builder, Option<Check>), ParseError> {
+        let mut builder = builder;
+
+        match statement {
+            Statement::Sir(scan) => {
+                self.sir = self.sir.update(scan, false);
+                let parts = [&self.hir, &self.sir, &self.tir];
+                return Ok(scan_parts(builder, line, &parts, true, self.end_ir));
+            }
+            Statement::Sdr(scan) => {
+                self.sdr = self.sdr.update(scan, false);
+                let parts = [&self.hdr, &self.sdr, &self.tdr];
+                return Ok(scan_parts(builder, line, &parts, false, self.end_dr));
+            }
+            Statement::Hir(scan) => self.hir = self.hir.update(scan, true),
+            Statement::Tir(scan) => self.tir = self.tir.update(scan, true),
+            Statement::Hdr(scan) => self.hdr = self.hdr.update(scan, true),
+            Statement::Tdr(scan) => self.tdr = self.tdr.update(scan, true),
+            Statement::EndIr(state) => self.end_ir = *state,
+            Statement::EndDr(state) => self.end_dr = *state,
+            Statement::RunTest {
+                run_state,
+                run_count,
+                min_time,
+                end_state,
+            } => {
+                if let Some(state) = run_state {
+                    if !is_end_state(*state) {
+                        return Err(ParseError {
+                            line,
+                            message: format!("{:?} is not a valid end state", state),
+                        });
+                    }
+                    self.run_state = *state;
+                    self.run_end_state = *state;
+                }
+                if let Some(state) = end_state {
+                    self.run_end_state = *state;
+                }
+
+                let timed = min_time.map_or(0, |time| (time * self.frequency).ceil() as usize);
+                let cycles = run_count.unwrap_or(0).max(timed);
+
+                builder = builder.goto(self.run_state).then();
+                push_clocks(&mut builder.commands, cycles);
+                builder = builder.goto(self.run_end_state).then();
+            }
+            Statement::State(states) => {
+                for state in states {
+                    builder = builder.goto(*state).then();
+                }
+            }
+            Statement::Frequency(frequency) => {
+                builder = builder
+                    .set_frequency(frequency.unwrap_or(DEFAULT_FREQUENCY))
+                    .then();
+                self.frequency = frequency.unwrap_or(DEFAULT_FREQUENCY);
+            }
+            Statement::Trst => (),
+        }
+
+        Ok((builder, None))
+    }
+}
+
+/// Scan the header, body and trailer of an SVF scan as one, returning a check if any part has
+/// expected TDO values.
+fn scan_parts(
+    builder: Builder,
+    line: usize,
+    parts: &[&Pattern],
+    instruction: bool,
+    end_state: TapState,
+) -> (Builder, Option<Check>) {
+    let tdi: Vec<bool> = parts.iter().flat_map(|part| part.tdi.clone()).collect();
+    if tdi.is_empty() {
+        return (builder.goto(end_state).then(), None);
+    }
+
+    let expected: Vec<bool> = parts
+        .iter()
+        .flat_map(|part| {
+            part.tdo
+                .clone()
+                .unwrap_or_else(|| vec![false; part.tdi.len()])
+        })
+        .collect();
+    let mask: Vec<bool> = parts.iter().flat_map(|part| part.compare_mask()).collect();
+    let capture = mask.iter().any(|bit| *bit);
+
+    let offset = builder.expected_response_length();
+    let builder = match instruction {
+        true => builder.scan_ir(&tdi).with_capture(capture),
+        false => builder.scan_dr(&tdi, capture),
+    }
+    .with_end_state(end_state)
+    .then();
+
+    let check = match capture {
+        true => Some(Check {
+            line,
+            offset,
+            expected,
+            mask,
+        }),
+        false => None,
+    };
+
+    (builder, check)
+}
+
+/// Run a batch of commands, then verify its checks.
+fn flush<T: Transport>(
+    jtag: &mut Jtag<T>,
+    builder: Builder,
+    checks: &mut Vec<Check>,
+) -> Result<(), Error<T::Error>> {
+    let response = jtag.execute(builder)?;
+    for check in checks.drain(..) {
+        check.verify(&response)?;
+    }
+
+    Ok(())
+}
+
+/// Play an SVF file over a JTAG driver, stopping at the first scan that doesn't match.
+///
+/// SVF files describe the whole scan chain, so any device selected on the driver is ignored.
+pub fn play<T: Transport>(jtag: &mut Jtag<T>, source: &str) -> Result<(), Error<T::Error>> {
+    let statements = parse(source)?;
+    let mut compiler = Compiler::new();
+    let mut builder = jtag.builder().deselect_device();
+    let mut checks = Vec::new();
+
+    for (line, statement) in statements.iter() {
+        let (next, check) = compiler.compile(builder, *line, statement)?;
+        builder = next;
+        checks.extend(check);
+
+        if builder.expected_response_length() >= MAX_BATCH_RESPONSE
+            || builder.commands.len() >= MAX_BATCH_COMMANDS
+        {
+            flush(jtag, builder, &mut checks)?;
+            builder = jtag.builder().deselect_device();
+        }
+    }
+
+    flush(jtag, builder, &mut checks)
+}
+
+#[cfg(test)]
+mod svf_tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn parses_multiline_statements() {
+        let statements = parse(
+            "TRST OFF;\n\
+             SDR 16 TDI (12\n\
+             34) // comment ; with a semicolon\n\
+             TDO (ABCD); RUNTEST IDLE 100 TCK 1.0E-3 SEC ENDSTATE DRPAUSE;",
+        )
+        .unwrap();
+
+        assert_eq!(statements[0], (1, Statement::Trst));
+        assert_eq!(
+            statements[1],
+            (
+                2,
+                Statement::Sdr(Scan {
+                    length: 16,
+                    tdi: Some(jtag::to_bits(0x1234, 16)),
+                    tdo: Some(jtag::to_bits(0xABCD, 16)),
+                    mask: None,
+                    smask: None,
+                })
+            )
+        );
+        assert_eq!(
+            statements[2],
+            (
+                4,
+                Statement::RunTest {
+                    run_state: Some(TapState::RunTestIdle),
+                    run_count: Some(100),
+                    min_time: Some(0.001),
+                    end_state: Some(TapState::PauseDR),
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn reports_line_of_bad_statement() {
+        let error = parse("SIR 8 TDI (00);\n\nENDIR DRSHIFT;").unwrap_err();
+
+        assert_eq!(error.line, 3);
+    }
+
+    #[test]
+    fn tdi_and_mask_carry_over() {
+        let first = Pattern::default().update(
+            &Scan {
+                length: 4,
+                tdi: Some(vec![true; 4]),
+                tdo: Some(vec![false; 4]),
+                mask: Some(vec![true, false, true, false]),
+                smask: None,
+            },
+            false,
+        );
+        let second = first.update(
+            &Scan {
+                length: 4,
+                ..Default::default()
+            },
+            false,
+        );
+
+        assert_eq!(second.tdi, vec![true; 4]);
+        assert_eq!(second.mask, vec![true, false, true, false]);
+        assert_eq!(second.tdo, None);
+    }
+
+    #[test]
+    fn headers_pad_scans() {
+        let mut compiler = Compiler::new();
+        let builder = Builder::new().reset_tap().then();
+        let hir = Statement::Hir(Scan {
+            length: 2,
+            tdi: Some(vec![true, true]),
+            ..Default::default()
+        });
+        let sir = Statement::Sir(Scan {
+            length: 4,
+            tdi: Some(jtag::to_bits(0x5, 4)),
+            ..Default::default()
+        });
+
+        let (builder, _) = compiler.compile(builder, 1, &hir).unwrap();
+        let (builder, check) = compiler.compile(builder, 2, &sir).unwrap();
+
+        assert_eq!(check, None);
+        assert_eq!(
+            &builder.build()[6..12],
+            &[0x1B, 0x04, 0x17, 0x4B, 0x00, 0x01]
+        );
+    }
+
+    #[test]
+    fn mismatch_reports_line() {
+        // An 8 bit scan responds with a 7 bit shift and a TMS shift.
+        let transport = MockTransport::with_responses(&[0x00, 0x00]);
+        let mut jtag = Jtag::new(transport);
+
+        let result = play(
+            &mut jtag,
+            "SIR 4 TDI (2);\nSDR 8 TDI (00) TDO (81) MASK (01);",
+        );
+
+        match result {
+            Err(Error::Mismatch { line: 2, .. }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
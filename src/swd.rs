@@ -0,0 +1,498 @@
+//! ARM Serial Wire Debug (SWD) built from MPSSE commands.
+//!
+//! ADBUS0 is SWCLK. ADBUS1 (TDI) drives SWDIO through a resistor of around 470 ohms, and ADBUS2
+//! (TDO) is connected to SWDIO directly to read it back. ADBUS1 is switched to an input while
+//! the target drives the line, so the resistor only matters if the two ever fight.
+//!
+//! Each transaction is a request packet, a turnaround, a 3-bit ACK from the target, and then a
+//! 32-bit data phase with parity, with a turnaround on whichever side the line changes hands.
+//!
+//! ```
+//! use mpsse::Builder;
+//! use mpsse::swd::Port;
+//!
+//! let commands = Builder::new()
+//!     .swd()
+//!     .setup()
+//!     .jtag_to_swd()
+//!     .request(Port::Debug, true, 0x0)
+//!     .build();
+//!
+//! // Setup leaves SWCLK and SWDIO low and driven.
+//! assert_eq!(&commands[..3], &[0x80, 0x00, 0x03]);
+//! // Reading DPIDR starts with the request packet 0xA5.
+//! assert_eq!(&commands[commands.len() - 11..commands.len() - 7], &[0x19, 0x00, 0x00, 0xA5]);
+//! ```
+use std::fmt;
+use std::time::Duration;
+
+use crate::builder::Builder;
+use crate::command::{BitDirection, ClockEdge, Command, DataShiftOptions, PinRange};
+use crate::transport::{self, Transport};
+
+const SWCLK: u8 = 0x01;
+const SWDIO_OUT: u8 = 0x02;
+const SWDIO_IN: u8 = 0x04;
+
+/// The sequence that switches a SWJ-DP from JTAG to SWD, sent least significant bit first.
+pub const JTAG_TO_SWD: u16 = 0xE79E;
+
+/// Bytes of ones sent for a line reset, which needs at least 50 cycles with SWDIO high.
+const LINE_RESET_BYTES: usize = 7;
+
+/// Address of the DP's ABORT register.
+const ABORT: u8 = 0x0;
+
+/// ABORT bit that clears the sticky overrun flag.
+const ORUNERRCLR: u32 = 1 << 4;
+
+/// Number of times a transaction is retried while the target answers WAIT.
+pub const WAIT_RETRIES: usize = 64;
+
+const WRITE_OPTIONS: DataShiftOptions = DataShiftOptions {
+    clock_direction: ClockEdge::Falling,
+    bit_direction: BitDirection::LsbFirst,
+};
+
+const READ_OPTIONS: DataShiftOptions = DataShiftOptions {
+    clock_direction: ClockEdge::Rising,
+    bit_direction: BitDirection::LsbFirst,
+};
+
+/// Which port a transaction addresses.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Port {
+    /// The Debug Port.
+    Debug,
+    /// The Access Port selected in the DP's SELECT register.
+    Access,
+}
+
+/// The target's acknowledgement of a request.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Ack {
+    Ok,
+    /// The target is busy, and the transaction should be retried.
+    Wait,
+    /// A sticky error flag is set in the DP, and must be cleared through ABORT.
+    Fault,
+}
+
+/// Build a request packet.
+///
+/// * `address` - The register address. Only bits 2 and 3 are sent, as `A[2:3]`.
+///
+/// ```
+/// use mpsse::swd::{request, Port};
+///
+/// // Read DPIDR.
+/// assert_eq!(request(Port::Debug, true, 0x0), 0xA5);
+/// // Write SELECT.
+/// assert_eq!(request(Port::Debug, false, 0x8), 0xB1);
+/// ```
+pub fn request(port: Port, read: bool, address: u8) -> u8 {
+    let fields = (port == Port::Access) as u8 | (read as u8) << 1 | (address & 0x0C);
+    let parity = fields.count_ones() as u8 & 1;
+
+    // Start bit, fields, parity, stop bit and park bit.
+    0x81 | fields << 1 | parity << 5
+}
+
+/// Even parity of a data word, as sent after it.
+pub fn parity(value: u32) -> bool {
+    value.count_ones() % 2 == 1
+}
+
+/// Decode the ACK from the response byte read for it.
+///
+/// Returns the raw 3 bits if they are not a valid ACK, which usually means nothing answered.
+pub fn decode_ack(response: u8) -> Result<Ack, u8> {
+    match response >> 5 {
+        0b001 => Ok(Ack::Ok),
+        0b010 => Ok(Ack::Wait),
+        0b100 => Ok(Ack::Fault),
+        bits => Err(bits),
+    }
+}
+
+/// Decode a read data phase from the five response bytes read for it, returning the data and
+/// whether its parity was correct.
+pub fn decode_data(response: &[u8]) -> (u32, bool) {
+    let value = u32::from_le_bytes([response[0], response[1], response[2], response[3]]);
+    let parity_bit = response[4] & 0x80 != 0;
+
+    (value, parity_bit == parity(value))
+}
+
+impl Builder {
+    /// Start building a sequence of SWD operations.
+    pub fn swd(self) -> SwdBuilder {
+        SwdBuilder { parent: self }
+    }
+}
+
+/// Build a sequence of SWD operations.
+///
+/// Each request responds with one byte holding the ACK (see [`decode_ack`]), and each read data
+/// phase responds with five bytes (see [`decode_data`]).
+#[derive(Debug)]
+pub struct SwdBuilder {
+    parent: Builder,
+}
+
+impl SwdBuilder {
+    /// Set SWCLK low, and either drive SWDIO low or release it to the target.
+    fn set_lines(mut self, drive: bool) -> Self {
+        let direction = match drive {
+            true => SWCLK | SWDIO_OUT,
+            false => SWCLK,
+        };
+        self.parent
+            .set_bits(PinRange::Low, SWCLK | SWDIO_OUT | SWDIO_IN, 0, direction);
+
+        self
+    }
+
+    /// Write `bytes`, least significant bit first.
+    fn write_bytes(mut self, bytes: &[u8]) -> Self {
+        self.parent.commands.push(Command::WriteDataShiftBytes {
+            options: WRITE_OPTIONS,
+            bytes: bytes.to_vec(),
+        });
+
+        self
+    }
+
+    /// Write the low `length` bits of `bits`, least significant bit first.
+    fn write_bits(mut self, bits: u8, length: u8) -> Self {
+        self.parent.commands.push(Command::WriteDataShiftBits {
+            options: WRITE_OPTIONS,
+            bits,
+            length,
+        });
+
+        self
+    }
+
+    /// Read `length` bits, which end up in the top of the response byte.
+    fn read_bits(mut self, length: u8) -> Self {
+        self.parent.commands.push(Command::ReadDataShiftBits {
+            options: READ_OPTIONS,
+            length,
+        });
+
+        self
+    }
+
+    /// Clock a turnaround cycle, where neither side drives SWDIO.
+    fn turnaround(mut self) -> Self {
+        self.parent.commands.push(Command::ClockBits { length: 1 });
+        self
+    }
+
+    /// Prepare the pins for SWD, with SWCLK low and SWDIO driven low.
+    pub fn setup(self) -> Self {
+        self.set_lines(true)
+    }
+
+    /// Send a line reset: at least 50 cycles with SWDIO high, then two idle cycles.
+    pub fn line_reset(self) -> Self {
+        self.set_lines(true)
+            .write_bytes(&[0xFF; LINE_RESET_BYTES])
+            .idle(2)
+    }
+
+    /// Switch a SWJ-DP from JTAG to SWD, leaving it after a line reset.
+    pub fn jtag_to_swd(self) -> Self {
+        self.set_lines(true)
+            .write_bytes(&[0xFF; LINE_RESET_BYTES])
+            .write_bytes(&JTAG_TO_SWD.to_le_bytes())
+            .line_reset()
+    }
+
+    /// Clock `cycles` idle cycles with SWDIO low.
+    pub fn idle(self, cycles: usize) -> Self {
+        let builder = match cycles / 8 {
+            0 => self,
+            bytes => self.write_bytes(&vec![0x00; bytes]),
+        };
+
+        match cycles % 8 {
+            0 => builder,
+            bits => builder.write_bits(0x00, bits as u8),
+        }
+    }
+
+    /// Send a request packet, then hand SWDIO to the target and read its ACK.
+    ///
+    /// After an OK, follow with [`.read_data()`](SwdBuilder::read_data) or
+    /// [`.write_data()`](SwdBuilder::write_data). After a WAIT or FAULT, follow with
+    /// [`.abandon()`](SwdBuilder::abandon).
+    pub fn request(self, port: Port, read: bool, address: u8) -> Self {
+        self.set_lines(true)
+            .write_bytes(&[request(port, read, address)])
+            .set_lines(false)
+            .turnaround()
+            .read_bits(3)
+    }
+
+    /// Read the data phase of a read request, then take SWDIO back from the target.
+    pub fn read_data(mut self) -> Self {
+        self.parent.commands.push(Command::ReadDataShiftBytes {
+            options: READ_OPTIONS,
+            length: 4,
+        });
+
+        self.read_bits(1).turnaround().set_lines(true)
+    }
+
+    /// Take SWDIO back from the target, then write the data phase of a write request.
+    pub fn write_data(self, value: u32) -> Self {
+        self.turnaround()
+            .set_lines(true)
+            .write_bytes(&value.to_le_bytes())
+            .write_bits(parity(value) as u8, 1)
+    }
+
+    /// Take SWDIO back from the target after a request it didn't accept.
+    pub fn abandon(self) -> Self {
+        self.turnaround().set_lines(true)
+    }
+
+    /// Commit this sequence to the parent Builder.
+    fn commit(self) -> Builder {
+        self.parent
+    }
+
+    builder_funcs!();
+}
+
+/// Error returned by the [`Swd`] driver.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// Running the commands failed.
+    Transport(transport::Error<E>),
+    /// The target kept answering WAIT.
+    Wait,
+    /// The target answered FAULT.
+    Fault,
+    /// The ACK was not a valid response, usually because nothing answered.
+    Protocol(u8),
+    /// The parity bit of read data was wrong.
+    Parity,
+}
+
+impl<E> From<transport::Error<E>> for Error<E> {
+    fn from(err: transport::Error<E>) -> Self {
+        Error::Transport(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Transport(err) => write!(f, "{}", err),
+            Error::Wait => write!(f, "target kept answering WAIT"),
+            Error::Fault => write!(f, "target answered FAULT"),
+            Error::Protocol(ack) => write!(f, "invalid ACK {:#05b}", ack),
+            Error::Parity => write!(f, "read data parity error"),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+/// Run SWD transactions over a [`Transport`].
+#[derive(Debug)]
+pub struct Swd<T> {
+    transport: T,
+    timeout: Duration,
+    overrun_detection: bool,
+}
+
+impl<T: Transport> Swd<T> {
+    /// Create a driver using the given transport, with the
+    /// [default timeout](transport::DEFAULT_TIMEOUT).
+    pub fn new(transport: T) -> Self {
+        Swd {
+            transport,
+            timeout: transport::DEFAULT_TIMEOUT,
+            overrun_detection: false,
+        }
+    }
+
+    /// Set how long to wait for the adapter to respond.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Swd { timeout, ..self }
+    }
+
+    /// Get the underlying transport.
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Get back the underlying transport.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Whether the driver expects the DP to have overrun detection enabled.
+    pub fn overrun_detection(&self) -> bool {
+        self.overrun_detection
+    }
+
+    /// Tell the driver whether `CTRL/STAT.ORUNDETECT` is set in the DP.
+    ///
+    /// With overrun detection enabled, every request has a data phase even if the target
+    /// answers WAIT or FAULT, which lets transactions be sent back to back without checking
+    /// each ACK first. A WAIT then sets the sticky overrun flag, which the driver clears
+    /// through ABORT before retrying.
+    pub fn set_overrun_detection(&mut self, enable: bool) {
+        self.overrun_detection = enable;
+    }
+
+    /// Run commands, flushing and returning their response.
+    pub fn execute(&mut self, builder: Builder) -> Result<Vec<u8>, Error<T::Error>> {
+        Ok(transport::flush(
+            &mut self.transport,
+            builder,
+            self.timeout,
+        )?)
+    }
+
+    /// Prepare the pins, then switch the target from JTAG to SWD.
+    ///
+    /// The DP then needs DPIDR to be read before it accepts other transactions.
+    pub fn setup(&mut self) -> Result<(), Error<T::Error>> {
+        self.execute(Builder::new().swd().setup().jtag_to_swd().then())?;
+        Ok(())
+    }
+
+    /// Send a line reset.
+    pub fn line_reset(&mut self) -> Result<(), Error<T::Error>> {
+        self.execute(Builder::new().swd().line_reset().then())?;
+        Ok(())
+    }
+
+    /// Send a request and wait for the target to accept it, retrying while it answers WAIT.
+    fn request(&mut self, port: Port, read: bool, address: u8) -> Result<(), Error<T::Error>> {
+        for _ in 0..WAIT_RETRIES {
+            let response =
+                self.execute(Builder::new().swd().request(port, read, address).then())?;
+            let ack = decode_ack(response[0]);
+            if ack != Ok(Ack::Ok) {
+                let builder = match (self.overrun_detection, read) {
+                    (false, _) => Builder::new().swd().abandon(),
+                    (true, true) => Builder::new().swd().read_data(),
+                    (true, false) => Builder::new().swd().write_data(0),
+                };
+                self.execute(builder.then())?;
+            }
+            if ack == Ok(Ack::Wait) && self.overrun_detection {
+                self.clear_overrun()?;
+            }
+
+            match ack {
+                Ok(Ack::Ok) => return Ok(()),
+                Ok(Ack::Wait) => continue,
+                Ok(Ack::Fault) => return Err(Error::Fault),
+                Err(bits) => return Err(Error::Protocol(bits)),
+            }
+        }
+
+        Err(Error::Wait)
+    }
+
+    /// Clear the sticky overrun flag by writing ORUNERRCLR to ABORT, which the DP always
+    /// accepts.
+    pub fn clear_overrun(&mut self) -> Result<(), Error<T::Error>> {
+        let builder = Builder::new()
+            .swd()
+            .request(Port::Debug, false, ABORT)
+            .write_data(ORUNERRCLR)
+            .idle(8);
+
+        match decode_ack(self.execute(builder.then())?[0]) {
+            Ok(Ack::Ok) => Ok(()),
+            Ok(Ack::Wait) => Err(Error::Wait),
+            Ok(Ack::Fault) => Err(Error::Fault),
+            Err(bits) => Err(Error::Protocol(bits)),
+        }
+    }
+
+    /// Read a register.
+    ///
+    /// * `address` - The register address, of which bits 2 and 3 are sent.
+    pub fn read(&mut self, port: Port, address: u8) -> Result<u32, Error<T::Error>> {
+        self.request(port, true, address)?;
+
+        let response = self.execute(Builder::new().swd().read_data().idle(8).then())?;
+        match decode_data(&response) {
+            (value, true) => Ok(value),
+            (_, false) => Err(Error::Parity),
+        }
+    }
+
+    /// Write a register.
+    ///
+    /// * `address` - The register address, of which bits 2 and 3 are sent.
+    pub fn write(&mut self, port: Port, address: u8, value: u32) -> Result<(), Error<T::Error>> {
+        self.request(port, false, address)?;
+
+        self.execute(Builder::new().swd().write_data(value).idle(8).then())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod swd_tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn request_parity() {
+        assert_eq!(request(Port::Access, true, 0xC), 0x9F);
+        assert_eq!(request(Port::Access, false, 0x4), 0x8B);
+    }
+
+    #[test]
+    fn read_checks_parity() {
+        // ACK OK in the top 3 bits, then 0x2BA01477, which has even parity.
+        let transport = MockTransport::with_responses(&[0x20, 0x77, 0x14, 0xA0, 0x2B, 0x00]);
+        let mut swd = Swd::new(transport);
+
+        assert_eq!(swd.read(Port::Debug, 0x0).unwrap(), 0x2BA0_1477);
+
+        let transport = MockTransport::with_responses(&[0x20, 0x77, 0x14, 0xA0, 0x2B, 0x80]);
+        let mut swd = Swd::new(transport);
+
+        match swd.read(Port::Debug, 0x0) {
+            Err(Error::Parity) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn write_retries_wait() {
+        let transport = MockTransport::with_responses(&[0x40, 0x40, 0x20]);
+        let mut swd = Swd::new(transport);
+
+        swd.write(Port::Debug, 0x8, 0x0000_00F0).unwrap();
+
+        let written = &swd.transport().written;
+        let requests = written.windows(2).filter(|w| *w == [0x00, 0xB1]);
+        assert_eq!(requests.count(), 3);
+        assert!(written.windows(4).any(|w| w == [0xF0, 0x00, 0x00, 0x00]));
+    }
+
+    #[test]
+    fn fault_is_reported() {
+        let transport = MockTransport::with_responses(&[0x80]);
+        let mut swd = Swd::new(transport);
+
+        match swd.read(Port::Access, 0xC) {
+            Err(Error::Fault) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
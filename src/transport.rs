@@ -0,0 +1,378 @@
+//! Running built commands on a device.
+//!
+//! This crate only builds MPSSE command streams. To run them, implement [`Transport`] for
+//! whichever USB library talks to the FTDI chip, then use [`execute`] (or one of the protocol
+//! drivers) to send commands and collect their responses.
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::builder::Builder;
+use crate::clock::ClockModel;
+use crate::command::{Command, CommandList, PinRange, PinValue};
+
+/// How long the protocol drivers wait for a response, unless given another timeout.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// GPIOL1 (ADBUS5), the pin the wait on I/O commands watch.
+const GPIOL1: u8 = 0x20;
+
+/// A connection to an FTDI chip in MPSSE mode.
+pub trait Transport {
+    /// Error raised by the underlying USB library.
+    type Error;
+
+    /// Write all of `data` to the MPSSE.
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Read up to `buffer.len()` bytes from the MPSSE.
+    ///
+    /// Returns the number of bytes read, which is `0` if nothing arrived within `timeout`.
+    fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> Result<usize, Self::Error>;
+
+    /// Abort whatever the MPSSE is running and throw away any buffered data, for example by
+    /// resetting the bit mode, purging the buffers and setting MPSSE mode again.
+    ///
+    /// The MPSSE needs setting up again afterwards. The default does nothing, which leaves an
+    /// MPSSE blocked on a Wait On I/O command stuck.
+    fn reset(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    type Error = T::Error;
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        (**self).write(data)
+    }
+
+    fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> Result<usize, Self::Error> {
+        (**self).read(buffer, timeout)
+    }
+
+    fn reset(&mut self) -> Result<(), Self::Error> {
+        (**self).reset()
+    }
+}
+
+/// Error returned when executing commands over a [`Transport`].
+///
+/// The protocol drivers wrap this with their own errors, or return it as it is when they have
+/// none.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The underlying transport failed.
+    Transport(E),
+    /// The device did not send the whole response in time.
+    Timeout { expected: usize, received: usize },
+    /// The device did not drive its ready line within the timeout, see [`wait_for_ready`].
+    NotReady,
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Transport(err) => write!(f, "transport error: {}", err),
+            Error::Timeout { expected, received } => write!(
+                f,
+                "timed out after receiving {} of {} bytes",
+                received, expected
+            ),
+            Error::NotReady => write!(f, "timed out waiting for the device to be ready"),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+/// Send a command list to the device, then wait for its whole response.
+///
+/// * `timeout` - How long to wait for each chunk of the response before giving up.
+///
+/// The MPSSE only flushes its response early when told to, so command lists that read data
+/// should usually end with a Send Immediate command.
+pub fn execute<T>(
+    transport: &mut T,
+    commands: CommandList,
+    timeout: Duration,
+) -> Result<Vec<u8>, Error<T::Error>>
+where
+    T: Transport + ?Sized,
+{
+    let expected = commands.expected_response_length();
+    let bytes: Vec<u8> = commands.into();
+
+    transport.write(&bytes).map_err(Error::Transport)?;
+
+    let mut response = vec![0; expected];
+    let mut received = 0;
+    while received < expected {
+        let count = transport
+            .read(&mut response[received..], timeout)
+            .map_err(Error::Transport)?;
+        if count == 0 {
+            return Err(Error::Timeout { expected, received });
+        }
+        received += count;
+    }
+
+    Ok(response)
+}
+
+/// Send the commands from `builder` followed by a Send Immediate, then wait for their whole
+/// response.
+///
+/// This is how the protocol drivers run each transaction.
+pub fn flush<T>(
+    transport: &mut T,
+    builder: Builder,
+    timeout: Duration,
+) -> Result<Vec<u8>, Error<T::Error>>
+where
+    T: Transport + ?Sized,
+{
+    let commands = builder.send_immediate().then().into_command_list();
+    execute(transport, commands, timeout)
+}
+
+/// How [`wait_for_ready`] waits for GPIOL1.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WaitMode {
+    /// Send one Wait On I/O command. If the pin never changes, the transport is reset to stop
+    /// the MPSSE waiting.
+    Block,
+    /// Repeatedly clock `bytes` bytes or until GPIOL1 changes, so the MPSSE is never blocked for
+    /// longer than `bytes * 8` clock cycles. `bytes` must not be 0.
+    ///
+    /// `clock` gives how long each run takes, so that a run is never cut short by `timeout`.
+    Clock { bytes: u16, clock: ClockModel },
+}
+
+/// Wait for a device to drive its ready line on GPIOL1 (ADBUS5) to `value`.
+///
+/// Returns [`Error::NotReady`] if the pin doesn't change within `timeout`, by which point the
+/// MPSSE is no longer waiting. In [`WaitMode::Block`] that needs [`Transport::reset`], after
+/// which the MPSSE must be set up again.
+///
+/// Panics if `mode` clocks for 0 bytes.
+pub fn wait_for_ready<T>(
+    transport: &mut T,
+    value: PinValue,
+    mode: WaitMode,
+    timeout: Duration,
+) -> Result<(), Error<T::Error>>
+where
+    T: Transport + ?Sized,
+{
+    if let WaitMode::Clock { bytes, .. } = mode {
+        assert!(bytes > 0, "cannot clock for 0 bytes");
+    }
+
+    // The pins are read back to show the wait has finished, and whether the pin changed or
+    // the clocking ran out.
+    let commands = || {
+        let wait = match mode {
+            WaitMode::Block => Command::WaitForIo { value },
+            WaitMode::Clock { bytes, .. } => Command::ClockBytesUntilIo {
+                value,
+                length: bytes,
+            },
+        };
+        CommandList(vec![
+            wait,
+            Command::ReadBits {
+                range: PinRange::Low,
+            },
+            Command::SendImmediate,
+        ])
+    };
+    let run_time = match mode {
+        WaitMode::Block => Duration::default(),
+        WaitMode::Clock { clock, .. } => commands().estimate(&clock).wire_time,
+    };
+    let expected = match value {
+        PinValue::High => GPIOL1,
+        PinValue::Low => 0,
+    };
+
+    let start = Instant::now();
+    loop {
+        let remaining = timeout.checked_sub(start.elapsed()).unwrap_or_default();
+
+        match execute(transport, commands(), remaining.max(run_time)) {
+            Ok(response) if response[0] & GPIOL1 == expected => return Ok(()),
+            Ok(_) if start.elapsed() < timeout => (),
+            Ok(_) => return Err(Error::NotReady),
+            Err(Error::Timeout { .. }) if mode == WaitMode::Block => {
+                transport.reset().map_err(Error::Transport)?;
+                return Err(Error::NotReady);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::convert::Infallible;
+
+    /// Transport that records everything written, and replays canned responses.
+    #[derive(Debug, Default)]
+    pub struct MockTransport {
+        pub written: Vec<u8>,
+        pub responses: VecDeque<u8>,
+        pub resets: usize,
+        /// The timeout given to each read.
+        pub timeouts: Vec<Duration>,
+    }
+
+    impl MockTransport {
+        pub fn with_responses(responses: &[u8]) -> Self {
+            MockTransport {
+                written: Vec::new(),
+                responses: responses.iter().copied().collect(),
+                resets: 0,
+                timeouts: Vec::new(),
+            }
+        }
+    }
+
+    impl Transport for MockTransport {
+        type Error = Infallible;
+
+        fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.written.extend_from_slice(data);
+            Ok(())
+        }
+
+        fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> Result<usize, Self::Error> {
+            self.timeouts.push(timeout);
+            let count = buffer.len().min(self.responses.len());
+            for (byte, response) in buffer.iter_mut().zip(self.responses.drain(..count)) {
+                *byte = response;
+            }
+            Ok(count)
+        }
+
+        fn reset(&mut self) -> Result<(), Self::Error> {
+            self.resets += 1;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod execute_tests {
+    use super::mock::MockTransport;
+    use super::*;
+    use crate::command::{Command, PinRange};
+
+    #[test]
+    fn reads_expected_response() {
+        let mut transport = MockTransport::with_responses(&[0xA5]);
+        let commands = CommandList(vec![
+            Command::ReadBits {
+                range: PinRange::Low,
+            },
+            Command::SendImmediate,
+        ]);
+
+        let response = execute(&mut transport, commands, Duration::from_millis(10)).unwrap();
+
+        assert_eq!(response, vec![0xA5]);
+        assert_eq!(transport.written, vec![0x81, 0x87]);
+    }
+
+    #[test]
+    fn short_response_times_out() {
+        let mut transport = MockTransport::default();
+        let commands = CommandList(vec![Command::ReadBits {
+            range: PinRange::High,
+        }]);
+
+        match execute(&mut transport, commands, Duration::from_millis(10)) {
+            Err(Error::Timeout {
+                expected: 1,
+                received: 0,
+            }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod wait_tests {
+    use super::mock::MockTransport;
+    use super::*;
+
+    #[test]
+    fn clock_mode_polls_until_ready() {
+        let mut transport = MockTransport::with_responses(&[0x00, 0x00, 0x20]);
+        let mode = WaitMode::Clock {
+            bytes: 0x100,
+            clock: ClockModel::default(),
+        };
+
+        wait_for_ready(&mut transport, PinValue::High, mode, Duration::from_secs(1)).unwrap();
+
+        assert_eq!(transport.written, [0x9C, 0xFF, 0x00, 0x81, 0x87].repeat(3));
+        assert_eq!(transport.resets, 0);
+    }
+
+    #[test]
+    fn clock_mode_waits_for_whole_runs() {
+        let mut transport = MockTransport::with_responses(&[0x00]);
+        let mode = WaitMode::Clock {
+            bytes: 0x100,
+            clock: ClockModel::default(),
+        };
+
+        match wait_for_ready(
+            &mut transport,
+            PinValue::High,
+            mode,
+            Duration::from_micros(1),
+        ) {
+            Err(Error::NotReady) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        // 2048 cycles at 6MHz, even though the overall timeout is shorter.
+        assert!(transport.timeouts[0] >= Duration::from_micros(341));
+        assert_eq!(transport.resets, 0);
+
+        // A missing response is the transport's timeout, and needs no reset.
+        let mut transport = MockTransport::default();
+        match wait_for_ready(&mut transport, PinValue::High, mode, Duration::from_secs(1)) {
+            Err(Error::Timeout { .. }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(transport.resets, 0);
+    }
+
+    #[test]
+    fn blocked_wait_is_reset() {
+        let mut transport = MockTransport::default();
+        let timeout = Duration::from_millis(10);
+
+        match wait_for_ready(&mut transport, PinValue::Low, WaitMode::Block, timeout) {
+            Err(Error::NotReady) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(transport.written, vec![0x89, 0x81, 0x87]);
+        assert_eq!(transport.resets, 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn clock_mode_rejects_zero_bytes() {
+        let mut transport = MockTransport::default();
+        let mode = WaitMode::Clock {
+            bytes: 0,
+            clock: ClockModel::default(),
+        };
+
+        let _ = wait_for_ready(&mut transport, PinValue::High, mode, Duration::from_secs(1));
+    }
+}
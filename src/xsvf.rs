@@ -0,0 +1,695 @@
+//! XSVF player.
+//!
+//! XSVF is Xilinx's compact binary encoding of [SVF](crate::svf), described in XAPP503. Files
+//! are [parsed](parse) into [`Instruction`]s, which [`play`] turns into commands on the
+//! [JTAG layer](crate::jtag). Scans that don't compare TDO are batched together; scans that do
+//! are run straight away, so failed comparisons can be retried as many times as `XREPEAT`
+//! allows.
+//!
+//! ```
+//! use mpsse::xsvf::{parse, Instruction};
+//!
+//! let instructions = parse(&[
+//!     0x07, 0x20, // XREPEAT 32
+//!     0x02, 0x06, 0x09, // XSIR 6 bits of 0x09
+//!     0x00, // XCOMPLETE
+//! ]).unwrap();
+//!
+//! assert_eq!(instructions[0], (0, Instruction::Repeat(32)));
+//! assert_eq!(instructions[2], (5, Instruction::Complete));
+//! ```
+use std::fmt;
+
+use crate::builder::Builder;
+use crate::jtag::{self, decode_scan, push_clocks, Jtag, TapState};
+use crate::svf::to_hex;
+use crate::transport::Transport;
+
+const XCOMPLETE: u8 = 0x00;
+const XTDOMASK: u8 = 0x01;
+const XSIR: u8 = 0x02;
+const XSDR: u8 = 0x03;
+const XRUNTEST: u8 = 0x04;
+const XREPEAT: u8 = 0x07;
+const XSDRSIZE: u8 = 0x08;
+const XSDRTDO: u8 = 0x09;
+const XSETSDRMASKS: u8 = 0x0A;
+const XSDRINC: u8 = 0x0B;
+const XSDRB: u8 = 0x0C;
+const XSDRC: u8 = 0x0D;
+const XSDRE: u8 = 0x0E;
+const XSDRTDOB: u8 = 0x0F;
+const XSDRTDOC: u8 = 0x10;
+const XSDRTDOE: u8 = 0x11;
+const XSTATE: u8 = 0x12;
+const XENDIR: u8 = 0x13;
+const XENDDR: u8 = 0x14;
+const XSIR2: u8 = 0x15;
+const XCOMMENT: u8 = 0x16;
+const XWAIT: u8 = 0x17;
+
+/// The largest `XSDRSIZE` accepted, so a corrupt file can't ask for gigabytes of scan data.
+pub const MAX_SDR_SIZE: u32 = 0x10_0000;
+
+/// Error found while parsing an XSVF file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The byte offset of the instruction's opcode.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "offset {:#x}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Error returned when playing an XSVF file.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The file could not be parsed.
+    Parse(ParseError),
+    /// The TDO bits captured by a scan did not match, even after retrying.
+    Mismatch {
+        /// The byte offset of the instruction's opcode.
+        offset: usize,
+        /// The instruction's name, e.g. `XSDRTDO`.
+        instruction: &'static str,
+        expected: String,
+        captured: String,
+        mask: String,
+    },
+    /// Running the commands failed.
+    Jtag(jtag::Error<E>),
+}
+
+impl<E> From<ParseError> for Error<E> {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(err)
+    }
+}
+
+impl<E> From<jtag::Error<E>> for Error<E> {
+    fn from(err: jtag::Error<E>) -> Self {
+        Error::Jtag(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(err) => write!(f, "{}", err),
+            Error::Mismatch {
+                offset,
+                instruction,
+                expected,
+                captured,
+                mask,
+            } => write!(
+                f,
+                "offset {:#x}: {} TDO mismatch, expected {} captured {} (mask {})",
+                offset, instruction, expected, captured, mask
+            ),
+            Error::Jtag(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+/// Which part of a data register scan split over several instructions (`XSDRB`, `XSDRC`,
+/// `XSDRE` and their `TDO` forms) an instruction shifts.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Segment {
+    /// Move to Shift-DR, shift, and stay there.
+    Begin,
+    /// Shift, and stay in Shift-DR.
+    Continue,
+    /// Shift, then move to the `XENDDR` state.
+    End,
+}
+
+/// A parsed XSVF instruction.
+///
+/// Bit vectors are stored first bit shifted first, so bit 0 is the lowest bit of the last byte
+/// in the file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Complete,
+    TdoMask(Vec<bool>),
+    /// `XSIR` or `XSIR2`.
+    Sir(Vec<bool>),
+    Sdr(Vec<bool>),
+    /// Microseconds to wait in the end state after each `XSIR` and `XSDR`.
+    RunTest(u32),
+    Repeat(u8),
+    SdrSize(u32),
+    SdrTdo {
+        tdi: Vec<bool>,
+        tdo: Vec<bool>,
+    },
+    SetSdrMasks {
+        address: Vec<bool>,
+        data: Vec<bool>,
+    },
+    SdrInc {
+        start: Vec<bool>,
+        data: Vec<Vec<bool>>,
+    },
+    /// `XSDRB`, `XSDRC`, `XSDRE`, or with `tdo`, `XSDRTDOB`, `XSDRTDOC` and `XSDRTDOE`.
+    SdrSegment {
+        segment: Segment,
+        tdi: Vec<bool>,
+        tdo: Option<Vec<bool>>,
+    },
+    State(TapState),
+    EndIr(TapState),
+    EndDr(TapState),
+    Comment(String),
+    Wait {
+        wait_state: TapState,
+        end_state: TapState,
+        microseconds: u32,
+    },
+}
+
+impl Instruction {
+    /// The instruction's name, as used by XAPP503.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::Complete => "XCOMPLETE",
+            Instruction::TdoMask(_) => "XTDOMASK",
+            Instruction::Sir(_) => "XSIR",
+            Instruction::Sdr(_) => "XSDR",
+            Instruction::RunTest(_) => "XRUNTEST",
+            Instruction::Repeat(_) => "XREPEAT",
+            Instruction::SdrSize(_) => "XSDRSIZE",
+            Instruction::SdrTdo { .. } => "XSDRTDO",
+            Instruction::SetSdrMasks { .. } => "XSETSDRMASKS",
+            Instruction::SdrInc { .. } => "XSDRINC",
+            Instruction::SdrSegment { segment, tdo, .. } => match (segment, tdo.is_some()) {
+                (Segment::Begin, false) => "XSDRB",
+                (Segment::Continue, false) => "XSDRC",
+                (Segment::End, false) => "XSDRE",
+                (Segment::Begin, true) => "XSDRTDOB",
+                (Segment::Continue, true) => "XSDRTDOC",
+                (Segment::End, true) => "XSDRTDOE",
+            },
+            Instruction::State(_) => "XSTATE",
+            Instruction::EndIr(_) => "XENDIR",
+            Instruction::EndDr(_) => "XENDDR",
+            Instruction::Comment(_) => "XCOMMENT",
+            Instruction::Wait { .. } => "XWAIT",
+        }
+    }
+}
+
+/// Reads the arguments of an instruction.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + length)
+            .ok_or_else(|| "unexpected end of file".to_string())?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Read a big-endian value `length` bits long, as bits least significant first.
+    fn bits(&mut self, length: usize) -> Result<Vec<bool>, String> {
+        let bytes = self.take(length.div_ceil(8))?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .flat_map(|byte| (0..8).map(move |i| byte >> i & 1 == 1))
+            .take(length)
+            .collect())
+    }
+
+    fn state(&mut self) -> Result<TapState, String> {
+        let code = self.u8()?;
+        // XSVF numbers the states in the same order as `TapState::ALL`.
+        TapState::ALL
+            .get(code as usize)
+            .copied()
+            .ok_or_else(|| format!("unknown state {:#04x}", code))
+    }
+
+    fn comment(&mut self) -> Result<String, String> {
+        let rest = &self.bytes[self.position..];
+        let length = rest
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or_else(|| "unterminated XCOMMENT".to_string())?;
+        self.position += length + 1;
+        Ok(String::from_utf8_lossy(&rest[..length]).into_owned())
+    }
+}
+
+/// Parse an XSVF file into instructions, each paired with the byte offset of its opcode.
+///
+/// Parsing stops after `XCOMPLETE`.
+pub fn parse(bytes: &[u8]) -> Result<Vec<(usize, Instruction)>, ParseError> {
+    let mut reader = Reader { bytes, position: 0 };
+    let mut instructions = Vec::new();
+    let mut sdr_size = 0;
+    let mut data_mask_length = 0;
+
+    while reader.position < bytes.len() {
+        let offset = reader.position;
+        let instruction = parse_instruction(&mut reader, &mut sdr_size, &mut data_mask_length)
+            .map_err(|message| ParseError { offset, message })?;
+
+        let complete = instruction == Instruction::Complete;
+        instructions.push((offset, instruction));
+        if complete {
+            return Ok(instructions);
+        }
+    }
+
+    Err(ParseError {
+        offset: bytes.len(),
+        message: "missing XCOMPLETE".to_string(),
+    })
+}
+
+fn parse_instruction(
+    reader: &mut Reader,
+    sdr_size: &mut usize,
+    data_mask_length: &mut usize,
+) -> Result<Instruction, String> {
+    let instruction = match reader.u8()? {
+        XCOMPLETE => Instruction::Complete,
+        XTDOMASK => Instruction::TdoMask(reader.bits(*sdr_size)?),
+        XSIR => {
+            let length = reader.u8()? as usize;
+            Instruction::Sir(reader.bits(length)?)
+        }
+        XSIR2 => {
+            let length = reader.u16()? as usize;
+            Instruction::Sir(reader.bits(length)?)
+        }
+        XSDR => Instruction::Sdr(reader.bits(*sdr_size)?),
+        XRUNTEST => Instruction::RunTest(reader.u32()?),
+        XREPEAT => Instruction::Repeat(reader.u8()?),
+        XSDRSIZE => {
+            let size = reader.u32()?;
+            if size > MAX_SDR_SIZE {
+                return Err(format!("XSDRSIZE {} is too large", size));
+            }
+            *sdr_size = size as usize;
+            Instruction::SdrSize(size)
+        }
+        XSDRTDO => Instruction::SdrTdo {
+            tdi: reader.bits(*sdr_size)?,
+            tdo: reader.bits(*sdr_size)?,
+        },
+        XSETSDRMASKS => {
+            let address = reader.bits(*sdr_size)?;
+            let data = reader.bits(*sdr_size)?;
+            *data_mask_length = data.iter().filter(|bit| **bit).count();
+            Instruction::SetSdrMasks { address, data }
+        }
+        XSDRINC => {
+            let start = reader.bits(*sdr_size)?;
+            let count = reader.u8()?;
+            let data = (0..count)
+                .map(|_| reader.bits(*data_mask_length))
+                .collect::<Result<_, _>>()?;
+            Instruction::SdrInc { start, data }
+        }
+        opcode @ (XSDRB | XSDRC | XSDRE) => Instruction::SdrSegment {
+            segment: segment(opcode, XSDRB),
+            tdi: reader.bits(*sdr_size)?,
+            tdo: None,
+        },
+        opcode @ (XSDRTDOB | XSDRTDOC | XSDRTDOE) => Instruction::SdrSegment {
+            segment: segment(opcode, XSDRTDOB),
+            tdi: reader.bits(*sdr_size)?,
+            tdo: Some(reader.bits(*sdr_size)?),
+        },
+        XSTATE => Instruction::State(reader.state()?),
+        XENDIR => match reader.u8()? {
+            0 => Instruction::EndIr(TapState::RunTestIdle),
+            1 => Instruction::EndIr(TapState::PauseIR),
+            state => return Err(format!("unknown XENDIR state {:#04x}", state)),
+        },
+        XENDDR => match reader.u8()? {
+            0 => Instruction::EndDr(TapState::RunTestIdle),
+            1 => Instruction::EndDr(TapState::PauseDR),
+            state => return Err(format!("unknown XENDDR state {:#04x}", state)),
+        },
+        XCOMMENT => Instruction::Comment(reader.comment()?),
+        XWAIT => Instruction::Wait {
+            wait_state: reader.state()?,
+            end_state: reader.state()?,
+            microseconds: reader.u32()?,
+        },
+        opcode => return Err(format!("unknown instruction {:#04x}", opcode)),
+    };
+
+    Ok(instruction)
+}
+
+/// Which segment the `XSDRB`-style `opcode` shifts, for the family starting at `begin`.
+fn segment(opcode: u8, begin: u8) -> Segment {
+    match opcode - begin {
+        0 => Segment::Begin,
+        1 => Segment::Continue,
+        _ => Segment::End,
+    }
+}
+
+/// Add one to the bits of `value` selected by `mask`, as if they were a number on their own.
+fn increment_masked(value: &mut [bool], mask: &[bool]) {
+    for (bit, _) in value.iter_mut().zip(mask).filter(|(_, mask)| **mask) {
+        *bit = !*bit;
+        if *bit {
+            return;
+        }
+    }
+}
+
+/// Replace the bits of `value` selected by `mask` with `data`, lowest first.
+fn insert_masked(value: &mut [bool], mask: &[bool], data: &[bool]) {
+    let selected = value.iter_mut().zip(mask).filter(|(_, mask)| **mask);
+    for ((bit, _), data) in selected.zip(data) {
+        *bit = *data;
+    }
+}
+
+/// The TDO comparison for a data register scan.
+struct Compare<'a> {
+    expected: &'a [bool],
+    mask: &'a [bool],
+}
+
+/// Runs instructions over a JTAG driver, keeping track of the state they leave behind.
+struct Interpreter {
+    frequency: f64,
+    tdo_mask: Vec<bool>,
+    tdo_expected: Vec<bool>,
+    address_mask: Vec<bool>,
+    data_mask: Vec<bool>,
+    run_test: u32,
+    repeat: u8,
+    end_ir: TapState,
+    end_dr: TapState,
+}
+
+impl Interpreter {
+    /// Add clocks for a wait of `microseconds` in the current state.
+    fn wait(&self, mut builder: Builder, microseconds: u32) -> Builder {
+        let cycles = (microseconds as f64 * self.frequency / 1e6).ceil() as usize;
+        push_clocks(&mut builder.commands, cycles);
+        builder
+    }
+
+    /// Run `instruction`, adding to `builder` and running it if the instruction has to check
+    /// what it captured.
+    fn run<T: Transport>(
+        &mut self,
+        jtag: &mut Jtag<T>,
+        builder: Builder,
+        offset: usize,
+        instruction: &Instruction,
+    ) -> Result<Builder, Error<T::Error>> {
+        let mut builder = builder;
+        let name = instruction.name();
+
+        match instruction {
+            Instruction::Complete | Instruction::Comment(_) | Instruction::SdrSize(_) => (),
+            Instruction::TdoMask(mask) => self.tdo_mask = mask.clone(),
+            Instruction::Sir(tdi) => {
+                builder = match tdi.is_empty() {
+                    true => builder.goto(self.end_ir).then(),
+                    false => builder.scan_ir(tdi).with_end_state(self.end_ir).then(),
+                };
+                builder = self.wait(builder, self.run_test);
+            }
+            Instruction::Sdr(tdi) => {
+                let (expected, mask) = (self.tdo_expected.clone(), self.tdo_mask.clone());
+                builder = self.shift_dr(jtag, builder, offset, name, tdi, &expected, &mask)?;
+            }
+            Instruction::RunTest(microseconds) => self.run_test = *microseconds,
+            Instruction::Repeat(repeat) => self.repeat = *repeat,
+            Instruction::SdrTdo { tdi, tdo } => {
+                self.tdo_expected = tdo.clone();
+                let mask = self.tdo_mask.clone();
+                builder = self.shift_dr(jtag, builder, offset, name, tdi, tdo, &mask)?;
+            }
+            Instruction::SetSdrMasks { address, data } => {
+                self.address_mask = address.clone();
+                self.data_mask = data.clone();
+            }
+            Instruction::SdrInc { start, data } => {
+                let (expected, mask) = (self.tdo_expected.clone(), self.tdo_mask.clone());
+                let mut tdi = start.clone();
+                builder = self.shift_dr(jtag, builder, offset, name, &tdi, &expected, &mask)?;
+
+                for data in data {
+                    increment_masked(&mut tdi, &self.address_mask);
+                    insert_masked(&mut tdi, &self.data_mask, data);
+                    builder = self.shift_dr(jtag, builder, offset, name, &tdi, &expected, &mask)?;
+                }
+            }
+            Instruction::SdrSegment { segment, tdi, tdo } => {
+                let end_state = match segment {
+                    Segment::Begin | Segment::Continue => TapState::ShiftDR,
+                    Segment::End => self.end_dr,
+                };
+                let compare = tdo.as_ref().map(|tdo| Compare {
+                    expected: tdo,
+                    mask: &self.tdo_mask,
+                });
+                builder = scan(jtag, builder, offset, name, tdi, compare, end_state)?;
+            }
+            Instruction::State(TapState::TestLogicReset) => builder = builder.reset_tap().then(),
+            Instruction::State(state) => builder = builder.goto(*state).then(),
+            Instruction::EndIr(state) => self.end_ir = *state,
+            Instruction::EndDr(state) => self.end_dr = *state,
+            Instruction::Wait {
+                wait_state,
+                end_state,
+                microseconds,
+            } => {
+                builder = builder.goto(*wait_state).then();
+                builder = self.wait(builder, *microseconds);
+                builder = builder.goto(*end_state).then();
+            }
+        }
+
+        Ok(builder)
+    }
+
+    /// Shift `tdi` through the data register, comparing what comes out against `expected`,
+    /// and retrying as many times as `XREPEAT` allows.
+    ///
+    /// Between retries the TAP goes through Pause-DR rather than Update-DR, so a failed value
+    /// never takes effect, and the `XRUNTEST` wait grows by a quarter each time.
+    #[allow(clippy::too_many_arguments)]
+    fn shift_dr<T: Transport>(
+        &self,
+        jtag: &mut Jtag<T>,
+        builder: Builder,
+        offset: usize,
+        name: &'static str,
+        tdi: &[bool],
+        expected: &[bool],
+        mask: &[bool],
+    ) -> Result<Builder, Error<T::Error>> {
+        if tdi.is_empty() || !mask.iter().any(|bit| *bit) {
+            let builder = scan(jtag, builder, offset, name, tdi, None, self.end_dr)?;
+            return Ok(self.wait(builder, self.run_test));
+        }
+
+        let mut builder = builder;
+        let mut run_test = self.run_test;
+        let mut attempt = 0;
+        loop {
+            let compare = Compare { expected, mask };
+            match scan(
+                jtag,
+                builder,
+                offset,
+                name,
+                tdi,
+                Some(compare),
+                TapState::Exit1DR,
+            ) {
+                Ok(next) => {
+                    let next = next.goto(self.end_dr).then();
+                    return Ok(self.wait(next, run_test));
+                }
+                Err(Error::Mismatch { .. }) if attempt < self.repeat => {
+                    builder = jtag
+                        .builder()
+                        .deselect_device()
+                        .goto(TapState::PauseDR)
+                        .then();
+                    run_test += run_test / 4;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Shift `tdi` through the data register and move to `end_state`. If there is something to
+/// compare, the commands are run straight away, and a fresh builder is returned.
+fn scan<T: Transport>(
+    jtag: &mut Jtag<T>,
+    builder: Builder,
+    offset: usize,
+    name: &'static str,
+    tdi: &[bool],
+    compare: Option<Compare>,
+    end_state: TapState,
+) -> Result<Builder, Error<T::Error>> {
+    if tdi.is_empty() {
+        return Ok(builder.goto(end_state).then());
+    }
+
+    let compare = match compare {
+        Some(compare) if compare.mask.iter().any(|bit| *bit) => compare,
+        _ => return Ok(builder.scan_dr(tdi, false).with_end_state(end_state).then()),
+    };
+
+    let start = builder.expected_response_length();
+    let builder = builder.scan_dr(tdi, true).with_end_state(end_state).then();
+    let response = jtag.execute(builder)?;
+    let captured = decode_scan(&response[start..], tdi.len());
+
+    let matches = captured
+        .iter()
+        .zip(compare.expected)
+        .zip(compare.mask)
+        .all(|((captured, expected), mask)| !mask || captured == expected);
+
+    match matches {
+        true => Ok(jtag.builder().deselect_device()),
+        false => Err(Error::Mismatch {
+            offset,
+            instruction: name,
+            expected: to_hex(compare.expected),
+            captured: to_hex(&captured),
+            mask: to_hex(compare.mask),
+        }),
+    }
+}
+
+/// Play an XSVF file over a JTAG driver, stopping at the first scan that doesn't match.
+///
+/// * `frequency` - The TCK frequency the adapter is running at, used to turn `XRUNTEST` and
+///   `XWAIT` times into clock cycles. Overestimating it makes waits longer, never shorter.
+///
+/// XSVF files describe the whole scan chain, so any device selected on the driver is ignored.
+pub fn play<T: Transport>(
+    jtag: &mut Jtag<T>,
+    xsvf: &[u8],
+    frequency: f64,
+) -> Result<(), Error<T::Error>> {
+    let instructions = parse(xsvf)?;
+    let mut interpreter = Interpreter {
+        frequency,
+        tdo_mask: Vec::new(),
+        tdo_expected: Vec::new(),
+        address_mask: Vec::new(),
+        data_mask: Vec::new(),
+        run_test: 0,
+        repeat: 0,
+        end_ir: TapState::RunTestIdle,
+        end_dr: TapState::RunTestIdle,
+    };
+
+    let mut builder = jtag.builder().deselect_device();
+    for (offset, instruction) in instructions.iter() {
+        builder = interpreter.run(jtag, builder, *offset, instruction)?;
+    }
+
+    jtag.execute(builder)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod xsvf_tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn values_are_big_endian() {
+        let instructions = parse(&[XSDRSIZE, 0, 0, 0, 12, XSDR, 0x0A, 0xBC, XCOMPLETE]).unwrap();
+
+        assert_eq!(
+            instructions[1],
+            (5, Instruction::Sdr(jtag::to_bits(0xABC, 12)))
+        );
+    }
+
+    #[test]
+    fn truncated_file() {
+        let error = parse(&[XREPEAT, 0x20, XSDRSIZE, 0, 0]).unwrap_err();
+
+        assert_eq!(error.offset, 2);
+    }
+
+    #[test]
+    fn masked_address_increment() {
+        let mut value = jtag::to_bits(0b0110_0111, 8);
+        let address = jtag::to_bits(0b0000_1111, 8);
+        let data = jtag::to_bits(0b1111_0000, 8);
+
+        increment_masked(&mut value, &address);
+        insert_masked(&mut value, &data, &jtag::to_bits(0b1001, 4));
+
+        assert_eq!(jtag::from_bits(&value), 0b1001_1000);
+    }
+
+    #[test]
+    fn retries_then_reports_instruction() {
+        // Every attempt at the 8 bit XSDRTDO captures zeros: 7 bits, then the TMS bit.
+        let transport = MockTransport::with_responses(&[0x00; 6]);
+        let mut jtag = Jtag::new(transport);
+
+        let result = play(
+            &mut jtag,
+            &[
+                XREPEAT, 2, XSDRSIZE, 0, 0, 0, 8, XTDOMASK, 0xFF, XSDRTDO, 0x00, 0x81, XCOMPLETE,
+            ],
+            1e6,
+        );
+
+        match result {
+            Err(Error::Mismatch {
+                offset: 9,
+                instruction: "XSDRTDO",
+                ..
+            }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(jtag.transport().responses.is_empty());
+    }
+}